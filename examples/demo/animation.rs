@@ -51,6 +51,19 @@ pub fn setup(
             ..Default::default()
         })
         .with(Parent(*scene_root))
+        // Tweens animate things without stepping them by hand, like this box fading back and forth
+        .with(
+            Tween::new(
+                Tweenable::Colors {
+                    from: Colors::bg(Color::White),
+                    to: Colors::bg(Color::DarkGrey),
+                },
+                std::time::Duration::from_millis(1500),
+                Ease::SineInOut,
+            )
+            .repeat(Repeat::Forever)
+            .yoyo(),
+        )
         .spawn(SpriteBundle {
             sprite: asset_server.get_handle("demo/bounce.txt"),
             stylemap: asset_server.get_handle("demo/bounce.stylemap"),
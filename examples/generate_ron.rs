@@ -22,6 +22,9 @@ fn main() {
         .map
         .push(vec![w, w, w, w, w, w, w, w, w, w, w, w, w, w, w, w, w, w]);
 
+    // Store each unique style once instead of once per cell. This makes big stylemaps much smaller
+    stylemap.compact();
+
     let file = std::fs::File::create("bounce.stylemap").unwrap();
     ron::ser::to_writer(&file, &stylemap).unwrap();
 }
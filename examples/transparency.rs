@@ -28,6 +28,12 @@ static SMALL_BOX: &str = r##"@@@@@
 @   @
 @ @ @
 @@@@@"##;
+// A ship whose corners are see-through, and whose other cells keep the background of whatever
+// they're over
+static SHIP: &str = " /^\\ \n<=o=>";
+static SHIP_MASK: &str = ".---.\n-----";
+// Spaces which darken whatever they're over, like a shadow
+static SHADOW: &str = "         \n         ";
 
 fn startup_system(
     commands: &mut Commands,
@@ -41,6 +47,12 @@ fn startup_system(
     // Create our resources
     let plain = stylemaps.add(StyleMap::default());
     let white_bg = stylemaps.add(StyleMap::with_bg(Color::White));
+    let ship_style =
+        stylemaps.add(StyleMap::with_fg(Color::Blue).with_mask(AlphaMask::parse(SHIP_MASK)));
+    let shadow_style = stylemaps.add(StyleMap::new(
+        Style::with_bg(Color::DarkGrey).with_blend(Blend::Multiply),
+        Vec::new(),
+    ));
 
     // Spawn two sprites into the world
     commands
@@ -64,5 +76,27 @@ fn startup_system(
             },
             stylemap: plain.clone(),
             visible: Visible::transparent(),
+        })
+        // Sits half over the box, so the cells over it are drawn on white and the rest aren't
+        .spawn(SpriteBundle {
+            sprite: sprites.add(Sprite::new(SHIP)),
+            position: Position {
+                x: window.x_center() as i32 - 6,
+                y: window.y_center() as i32 + 3,
+                z: 2,
+            },
+            stylemap: ship_style,
+            ..Default::default()
+        })
+        // Drawn over the bottom of the box and the floor beneath it, darkening both
+        .spawn(SpriteBundle {
+            sprite: sprites.add(Sprite::new(SHADOW)),
+            position: Position {
+                x: window.x_center() as i32 - 2,
+                y: window.y_center() as i32 + 3,
+                z: 1,
+            },
+            stylemap: shadow_style,
+            ..Default::default()
         });
 }
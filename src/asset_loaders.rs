@@ -1,9 +1,15 @@
+use anyhow::Context;
 use bevy::{
-    asset::{AssetLoader, LoadContext, LoadedAsset},
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    ecs::{FromResources, Resources},
     utils::BoxedFuture,
 };
 
-use crate::components::{Sprite, StyleMap};
+use crate::components::{self, Sprite, StyleMap};
+use crate::figlet::FigletFont;
+use crate::images::{self, ImageSettings, RgbaImage};
+use crate::tiled;
+use crate::tilemap::Tileset;
 
 #[derive(Default)]
 pub struct SpriteLoader;
@@ -37,7 +43,7 @@ impl AssetLoader for StyleMapLoader {
         load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
         Box::pin(async move {
-            let stylemap = ron::de::from_bytes::<StyleMap>(bytes)?;
+            let stylemap = components::from_ron::<StyleMap>(bytes)?;
             load_context.set_default_asset(LoadedAsset::new(stylemap));
             Ok(())
         })
@@ -47,3 +53,143 @@ impl AssetLoader for StyleMapLoader {
         &["stylemap"]
     }
 }
+
+/// Loads png and ppm images as a sprite drawn with half blocks. The stylemap which colors the sprite
+/// is a labeled asset, so "player.png" is the sprite and "player.png#stylemap" is its stylemap.
+/// Pngs are left to other loaders if `ImageSettings::png` is off.
+pub struct ImageLoader {
+    settings: ImageSettings,
+}
+
+impl FromResources for ImageLoader {
+    fn from_resources(resources: &Resources) -> Self {
+        let settings = resources
+            .get::<ImageSettings>()
+            .map(|settings| (*settings).clone())
+            .unwrap_or_default();
+        ImageLoader { settings }
+    }
+}
+
+impl AssetLoader for ImageLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let image = match load_context.path().extension().and_then(|ext| ext.to_str()) {
+                Some("ppm") => RgbaImage::from_ppm(bytes)?,
+                _ => RgbaImage::from_png(bytes)?,
+            };
+            let (sprite, stylemap) = images::image_to_sprite(&image, &self.settings);
+            load_context.set_default_asset(LoadedAsset::new(sprite));
+            load_context
+                .set_labeled_asset(images::IMAGE_STYLEMAP_LABEL, LoadedAsset::new(stylemap));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        if self.settings.png {
+            &["png", "ppm"]
+        } else {
+            &["ppm"]
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct FigletFontLoader;
+
+impl AssetLoader for FigletFontLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            // Plenty of fonts out there are latin-1, so don't insist on utf-8
+            let string = String::from_utf8_lossy(bytes);
+            let font = FigletFont::parse(&string)?;
+            load_context.set_default_asset(LoadedAsset::new(font));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["flf"]
+    }
+}
+
+#[derive(Default)]
+pub struct TilesetLoader;
+
+impl AssetLoader for TilesetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let tileset = components::from_ron::<Tileset>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(tileset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tileset"]
+    }
+}
+
+/// Loads maps made with Tiled, from .tmx files or from JSON maps saved as .json or .tmj. The tileset
+/// the map's tiles are drawn with is a labeled asset, so "level.tmx#tileset" is its tileset.
+///
+/// Tilesets kept in files of their own are read as part of the map rather than loaded as assets, so
+/// editing one doesn't reload the map. Save the map again, or touch it, to pick up the changes.
+#[derive(Default)]
+pub struct TiledMapLoader;
+
+impl AssetLoader for TiledMapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let mut parsed = match load_context.path().extension().and_then(|ext| ext.to_str()) {
+                Some("json") | Some("tmj") => tiled::parse_json(bytes)?,
+                _ => tiled::parse_tmx(bytes)?,
+            };
+
+            // Tilesets can be kept in files of their own, which are relative to the map
+            let directory = load_context
+                .path()
+                .parent()
+                .map(|directory| directory.to_path_buf())
+                .unwrap_or_default();
+            for tileset in parsed.tilesets.iter_mut() {
+                if let Some(source) = tileset.source.take() {
+                    let path = directory.join(&source);
+                    let bytes = load_context.read_asset_bytes(&path).await?;
+                    tileset.tiles = tiled::parse_tileset_file(&path, &bytes)
+                        .with_context(|| format!("couldn't read tileset {}", source))?;
+                }
+            }
+
+            let (mut map, tileset) = parsed.finish();
+            map.tileset = load_context.get_handle(AssetPath::new_ref(
+                load_context.path(),
+                Some(tiled::TILED_TILESET_LABEL),
+            ));
+            load_context.set_labeled_asset(tiled::TILED_TILESET_LABEL, LoadedAsset::new(tileset));
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmx", "json", "tmj"]
+    }
+}
@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+
+use crate::components::{Position, Sprite};
+use crate::sprite_transform::{self, TransformedSprite};
+use crate::viewport::Viewports;
+use crate::CrosstermWindow;
+
+/// The part of the world that can be seen. Entities are drawn moved up and left by the camera's
+/// position, except for ones marked as ScreenSpace, which stay where they are on the screen.
+///
+/// Moving the camera blanks what it shows and draws everything in it again, rather than moving every
+/// entity. The rest of the screen is left alone.
+///
+/// The Camera resource is the window's camera. When the window is split into Viewports, each of them
+/// has a camera of its own instead.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Camera {
+    /// The position in the world which is shown at the top left of the window. The camera can move
+    /// smoothly by fractions of a cell, but only ever shows whole cells.
+    pub x: f32,
+    pub y: f32,
+    /// An entity which the camera keeps in the middle of the window
+    pub follow: Option<Entity>,
+    /// How quickly the camera catches up with the entity it's following, as the fraction of the
+    /// distance left it covers per second. When it's None, the camera keeps the entity in the middle
+    /// at all times.
+    pub follow_speed: Option<f32>,
+    /// The part of the world the camera stays within. If the window is bigger than the bounds, the
+    /// camera stays at their top or left edge.
+    pub bounds: Option<CameraBounds>,
+}
+
+/// A rectangle of the world, which the camera doesn't show anything outside of
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CameraBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Camera {
+    pub fn new(x: f32, y: f32) -> Camera {
+        Camera {
+            x,
+            y,
+            ..Default::default()
+        }
+    }
+
+    pub fn following(entity: Entity) -> Camera {
+        Camera {
+            follow: Some(entity),
+            ..Default::default()
+        }
+    }
+
+    /// How far entities are moved on the screen by the camera
+    pub(crate) fn offset(&self) -> (i32, i32) {
+        (-self.x.round() as i32, -self.y.round() as i32)
+    }
+
+    /// Moves the camera back inside its bounds, given the size of what it's showing the world in
+    fn clamp(&self, x: f32, y: f32, (width, height): (u16, u16)) -> (f32, f32) {
+        let bounds = match &self.bounds {
+            Some(bounds) => bounds,
+            None => return (x, y),
+        };
+        let clamp_axis = |value: f32, start: i32, size: usize, window_size: u16| {
+            let end = start + size as i32 - window_size as i32;
+            value.min(end.max(start) as f32).max(start as f32)
+        };
+        (
+            clamp_axis(x, bounds.x, bounds.width, width),
+            clamp_axis(y, bounds.y, bounds.height, height),
+        )
+    }
+}
+
+/// Keeps an entity, and everything beneath it in the hierarchy, in the same place on the screen
+/// however the camera moves. It's for things like status bars and menus.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScreenSpace;
+
+/// Moves a camera towards the entity it's following, and keeps it in its bounds
+fn move_camera(
+    camera: &mut Camera,
+    (width, height): (u16, u16),
+    delta_seconds: f32,
+    sprites: &Assets<Sprite>,
+    targets: &Query<(
+        &Position,
+        Option<&Handle<Sprite>>,
+        Option<&TransformedSprite>,
+    )>,
+) {
+    let (mut x, mut y) = (camera.x, camera.y);
+
+    let target = camera.follow.and_then(|entity| targets.get(entity).ok());
+    if let Some((position, sprite, transformed)) = target {
+        // Put the middle of the entity in the middle of what the camera shows
+        let (sprite_width, sprite_height) = sprite
+            .and_then(|sprite| sprite_transform::drawn_sprite(sprites, sprite, transformed))
+            .map_or((0, 0), |sprite| (sprite.width(), sprite.height()));
+        let target_x = position.x as f32 + sprite_width as f32 / 2.0 - width as f32 / 2.0;
+        let target_y = position.y as f32 + sprite_height as f32 / 2.0 - height as f32 / 2.0;
+        match camera.follow_speed {
+            Some(speed) => {
+                let amount = 1.0 - (1.0 - speed.clamp(0.0, 1.0)).powf(delta_seconds);
+                x += (target_x - x) * amount;
+                y += (target_y - y) * amount;
+            }
+            None => {
+                x = target_x;
+                y = target_y;
+            }
+        }
+    }
+
+    let (x, y) = camera.clamp(x, y, (width, height));
+    if camera.x != x || camera.y != y {
+        camera.x = x;
+        camera.y = y;
+    }
+}
+
+/// Moves the window's camera and the cameras of every viewport
+pub(crate) fn update_camera(
+    time: Res<Time>,
+    window: Res<CrosstermWindow>,
+    sprites: Res<Assets<Sprite>>,
+    mut camera: ResMut<Camera>,
+    mut viewports: ResMut<Viewports>,
+    targets: Query<(
+        &Position,
+        Option<&Handle<Sprite>>,
+        Option<&TransformedSprite>,
+    )>,
+) {
+    let delta_seconds = time.delta_seconds();
+    let window_size = (window.width(), window.height());
+    move_camera(&mut camera, window_size, delta_seconds, &sprites, &targets);
+    for viewport in viewports.0.iter_mut() {
+        let size = (viewport.width, viewport.height);
+        move_camera(
+            &mut viewport.camera,
+            size,
+            delta_seconds,
+            &sprites,
+            &targets,
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use crossterm::event::{KeyCode, KeyModifiers, MouseEvent};
+
+use crate::camera::ScreenSpace;
+use crate::components::{DrawArea, Position, ScreenRect, Sprite};
+use crate::focus::{Focus, FocusedKeyEvent};
+use crate::layers::RenderLayers;
+use crate::sprite_transform::{self, TransformedSprite};
+use crate::viewport::ActiveViewports;
+
+/// Clips the entity's descendants to a rectangle starting at its position, so nothing beneath it in
+/// the hierarchy is drawn outside of the rectangle. The entity itself isn't clipped.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClipRect {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl ClipRect {
+    pub fn new(width: usize, height: usize) -> ClipRect {
+        ClipRect { width, height }
+    }
+}
+
+/// Clips the entity's descendants like a ClipRect, and scrolls them. The descendants are positioned
+/// as if nothing was scrolled, and are drawn moved up and left by the scroll.
+///
+/// While it's focused, the arrow keys, page up and down, home and end scroll it, and the mouse wheel
+/// scrolls it while the mouse is over it, or scrolls it sideways while shift is held. It can't be
+/// scrolled past the edges of its descendants' sprites. It uses the arrow keys, so it should be made
+/// focusable with `Focusable::with_arrow_keys`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScrollView {
+    pub width: usize,
+    pub height: usize,
+    pub scroll_x: usize,
+    pub scroll_y: usize,
+}
+
+impl ScrollView {
+    pub fn new(width: usize, height: usize) -> ScrollView {
+        ScrollView {
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    /// Scrolls by some rows and columns, without going past the edges of the content
+    fn scroll_by(&mut self, columns: i32, rows: i32, content: (usize, usize)) {
+        let max_x = content.0.saturating_sub(self.width) as i32;
+        let max_y = content.1.saturating_sub(self.height) as i32;
+        let scroll_x = (self.scroll_x as i32 + columns).min(max_x).max(0) as usize;
+        let scroll_y = (self.scroll_y as i32 + rows).min(max_y).max(0) as usize;
+        if self.scroll_x != scroll_x || self.scroll_y != scroll_y {
+            self.scroll_x = scroll_x;
+            self.scroll_y = scroll_y;
+        }
+    }
+}
+
+/// How far the sprites of an entity's descendants reach to the right of and below a position
+fn content_size(
+    entity: Entity,
+    origin: &Position,
+    sprites: &Assets<Sprite>,
+    children: &Query<&Children>,
+    contents: &Query<(&Position, &Handle<Sprite>, Option<&TransformedSprite>)>,
+) -> (usize, usize) {
+    let mut size = (0, 0);
+    let entity_children = match children.get(entity) {
+        Ok(entity_children) => entity_children,
+        Err(_) => return size,
+    };
+    for child in entity_children.iter() {
+        if let Ok((position, sprite, transformed)) = contents.get(*child) {
+            if let Some(sprite) = sprite_transform::drawn_sprite(sprites, sprite, transformed) {
+                let right = position.x - origin.x + sprite.width() as i32;
+                let bottom = position.y - origin.y + sprite.height() as i32;
+                size.0 = size.0.max(right.max(0) as usize);
+                size.1 = size.1.max(bottom.max(0) as usize);
+            }
+        }
+        let (width, height) = content_size(*child, origin, sprites, children, contents);
+        size = (size.0.max(width), size.1.max(height));
+    }
+    size
+}
+
+pub(crate) fn scroll_view_input(
+    mut key_reader: Local<EventReader<FocusedKeyEvent>>,
+    key_events: Res<Events<FocusedKeyEvent>>,
+    mut mouse_reader: Local<EventReader<MouseEvent>>,
+    mouse_events: Res<Events<MouseEvent>>,
+    focus: Res<Focus>,
+    viewports: Res<ActiveViewports>,
+    sprites: Res<Assets<Sprite>>,
+    children: Query<&Children>,
+    contents: Query<(&Position, &Handle<Sprite>, Option<&TransformedSprite>)>,
+    mut views: Query<(
+        Entity,
+        &mut ScrollView,
+        &Position,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
+) {
+    for event in key_reader.iter(&key_events) {
+        let (entity, mut view, position, ..) = match views.get_mut(event.entity) {
+            Ok(view) => view,
+            Err(_) => continue,
+        };
+        let content = content_size(entity, position, &sprites, &children, &contents);
+        let page = view.height.max(1) as i32;
+        match event.key.code {
+            KeyCode::Up => view.scroll_by(0, -1, content),
+            KeyCode::Down => view.scroll_by(0, 1, content),
+            KeyCode::Left => view.scroll_by(-1, 0, content),
+            KeyCode::Right => view.scroll_by(1, 0, content),
+            KeyCode::PageUp => view.scroll_by(0, -page, content),
+            KeyCode::PageDown => view.scroll_by(0, page, content),
+            KeyCode::Home => view.scroll_by(0, i32::MIN / 2, content),
+            KeyCode::End => view.scroll_by(0, i32::MAX / 2, content),
+            _ => {}
+        }
+    }
+
+    for event in mouse_reader.iter(&mouse_events) {
+        let (column, row, modifiers, amount) = match *event {
+            MouseEvent::ScrollDown(column, row, modifiers) => (column, row, modifiers, 1),
+            MouseEvent::ScrollUp(column, row, modifiers) => (column, row, modifiers, -1),
+            _ => continue,
+        };
+        for (entity, mut view, position, area, layers) in views.iter_mut() {
+            let size = (view.width, view.height);
+            let over = viewports.cell_at(position, size, area, layers, (column, row));
+            if over.is_none() || focus.is_blocked(entity) {
+                continue;
+            }
+            let content = content_size(entity, position, &sprites, &children, &contents);
+            if modifiers.contains(KeyModifiers::SHIFT) {
+                view.scroll_by(amount, 0, content);
+            } else {
+                view.scroll_by(0, amount, content);
+            }
+        }
+    }
+}
+
+/// Works out where an entity and its descendants are drawn and clipped, given the area its parent
+/// gives it
+fn draw_areas(
+    entity: Entity,
+    area: DrawArea,
+    nodes: &Query<(
+        Option<&Position>,
+        Option<&ClipRect>,
+        Option<&ScrollView>,
+        Option<&ScreenSpace>,
+        Option<&Children>,
+    )>,
+    areas: &mut HashMap<Entity, DrawArea>,
+) {
+    let (position, clip, scroll, screen_space, children) = match nodes.get(entity) {
+        Ok(node) => node,
+        Err(_) => return,
+    };
+    let area = own_area(area, screen_space);
+    areas.insert(entity, area);
+    let children = match children {
+        Some(children) => children,
+        None => return,
+    };
+
+    let child_area = child_area(area, position, clip, scroll);
+    for child in children.iter() {
+        draw_areas(*child, child_area, nodes, areas);
+    }
+}
+
+/// The area an entity is drawn in, given the area its parent gives it
+fn own_area(area: DrawArea, screen_space: Option<&ScreenSpace>) -> DrawArea {
+    let mut area = area;
+    if screen_space.is_some() {
+        area.screen_space = true;
+    }
+    area
+}
+
+/// The area an entity drawn in an area gives its children, clipped and scrolled by it
+fn child_area(
+    area: DrawArea,
+    position: Option<&Position>,
+    clip: Option<&ClipRect>,
+    scroll: Option<&ScrollView>,
+) -> DrawArea {
+    let mut child_area = area;
+    if let Some(position) = position {
+        let size = clip
+            .map(|clip| (clip.width, clip.height))
+            .or_else(|| scroll.map(|scroll| (scroll.width, scroll.height)));
+        if let Some((width, height)) = size {
+            let rect = ScreenRect {
+                x: position.x + area.offset_x,
+                y: position.y + area.offset_y,
+                width: width as i32,
+                height: height as i32,
+            };
+            child_area.clip = Some(match area.clip {
+                Some(clip) => clip.intersect(&rect),
+                None => rect,
+            });
+        }
+        if let Some(scroll) = scroll {
+            child_area.offset_x -= scroll.scroll_x as i32;
+            child_area.offset_y -= scroll.scroll_y as i32;
+        }
+    }
+    child_area
+}
+
+/// Works out where an entity is drawn and clipped, and whether it's in screen space, for entities
+/// whose ClipRect, ScrollView, ScreenSpace, parent or children changed, along with clip rects and
+/// scroll views that moved. Their descendants are worked out again with them.
+pub(crate) fn update_draw_areas(
+    commands: &mut Commands,
+    changed: Query<
+        Entity,
+        Or<(
+            Changed<ClipRect>,
+            Changed<ScrollView>,
+            Added<ScreenSpace>,
+            Changed<Parent>,
+            Changed<Children>,
+        )>,
+    >,
+    moved: Query<Entity, (Changed<Position>, Or<(With<ClipRect>, With<ScrollView>)>)>,
+    parents: Query<&Parent>,
+    nodes: Query<(
+        Option<&Position>,
+        Option<&ClipRect>,
+        Option<&ScrollView>,
+        Option<&ScreenSpace>,
+        Option<&Children>,
+    )>,
+    mut targets: Query<Option<&mut DrawArea>, With<Position>>,
+) {
+    let mut dirty: HashSet<Entity> = changed.iter().chain(moved.iter()).collect();
+    dirty.extend(changed.removed::<ClipRect>());
+    dirty.extend(changed.removed::<ScrollView>());
+    dirty.extend(changed.removed::<ScreenSpace>());
+    dirty.extend(changed.removed::<Parent>());
+    if dirty.is_empty() {
+        return;
+    }
+
+    let ancestors = |entity: Entity| {
+        let mut ancestors = Vec::new();
+        let mut current = entity;
+        while let Ok(parent) = parents.get(current) {
+            ancestors.push(parent.0);
+            current = parent.0;
+        }
+        ancestors
+    };
+
+    let mut areas = HashMap::default();
+    for entity in dirty.iter() {
+        // Entities beneath another changed entity are worked out along with it
+        let ancestors = ancestors(*entity);
+        if ancestors.iter().any(|ancestor| dirty.contains(ancestor)) {
+            continue;
+        }
+
+        // The area the entity's parent gives it, worked out down from the root of its hierarchy
+        let mut area = DrawArea::default();
+        for ancestor in ancestors.iter().rev() {
+            if let Ok((position, clip, scroll, screen_space, _)) = nodes.get(*ancestor) {
+                area = child_area(own_area(area, screen_space), position, clip, scroll);
+            }
+        }
+        draw_areas(*entity, area, &nodes, &mut areas);
+    }
+
+    // Entities are drawn at their position unless they're given somewhere else
+    for (entity, area) in areas {
+        match targets.get_mut(entity) {
+            Ok(Some(mut current)) if *current != area => *current = area,
+            Ok(None) if area != DrawArea::default() => {
+                commands.insert_one(entity, area);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn children_are_clipped_and_scrolled() {
+        let outer = DrawArea {
+            offset_x: 1,
+            clip: Some(ScreenRect {
+                x: 0,
+                y: 0,
+                width: 10,
+                height: 10,
+            }),
+            ..Default::default()
+        };
+        let view = ScrollView {
+            width: 20,
+            height: 5,
+            scroll_x: 2,
+            scroll_y: 3,
+        };
+        let area = child_area(outer, Some(&Position::new(4, 2, 0)), None, Some(&view));
+        assert_eq!(
+            area.clip,
+            Some(ScreenRect {
+                x: 5,
+                y: 2,
+                width: 5,
+                height: 5,
+            })
+        );
+        assert_eq!((area.offset_x, area.offset_y), (-1, -3));
+
+        // Without a position there's nothing to clip to
+        let clip = ClipRect::new(1, 1);
+        assert_eq!(child_area(outer, None, Some(&clip), None), outer);
+        assert!(own_area(outer, Some(&ScreenSpace)).screen_space);
+    }
+}
@@ -1,14 +1,17 @@
+use bevy::asset::HandleId;
 use bevy::prelude::*;
 use bevy::reflect::TypeUuid;
 use bevy::utils::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 use std::default::Default;
+use std::sync::atomic::{AtomicU64, Ordering};
 use unicode_segmentation::UnicodeSegmentation;
 
 pub use crossterm::style::Color;
 
+/// The parts of the screen each entity was drawn in, one for each viewport that showed it
 #[derive(Default)]
-pub(crate) struct PreviousEntityDetails(pub HashMap<Entity, (PreviousPosition, PreviousSize)>);
+pub(crate) struct PreviousEntityDetails(pub HashMap<Entity, Vec<ScreenRect>>);
 
 pub(crate) struct PreviousWindowColors(pub Colors);
 
@@ -23,13 +26,124 @@ pub(crate) struct EntitiesToRedraw {
     pub full_redraw: bool,
     pub to_clear: HashSet<Entity>,
     pub to_draw: Vec<EntityDepth>,
+    /// Parts of the screen to blank before anything's drawn, because tiles or sprite cells changed
+    /// there
+    pub to_blank: Vec<ScreenRect>,
+    /// The parts of the screen to draw tilemaps, and sprites that only had some of their cells
+    /// changed, in. Other entities are drawn whole, and in a full redraw everything is.
+    pub regions: HashMap<Entity, Vec<ScreenRect>>,
+    /// The tilemaps on the screen, which are found before everything else is worked out
+    pub tilemaps: Vec<TilemapDetails>,
+    /// Whether a tilemap was added, moved, hidden or removed, which redraws the whole screen
+    pub tilemaps_changed: bool,
+    /// The particle clouds on the screen, which are found before everything else is worked out
+    pub particles: Vec<ParticleDetails>,
+    /// Whether a particle cloud was moved or hidden, which redraws the whole screen
+    pub particles_changed: bool,
+    /// The revision of each sprite that was last drawn, so a sprite that had some of its cells
+    /// changed since then only has those drawn again
+    pub sprite_revisions: HashMap<HandleId, u64>,
 }
 
+/// What was last drawn in a cell of the screen. Cells that were blanked have no colors.
+#[derive(Clone, Default)]
+pub(crate) struct ScreenCell {
+    pub grapheme: String,
+    pub colors: Colors,
+}
+
+/// What each cell of the screen was last drawn with, so cells can be drawn over whatever's under
+/// them
+#[derive(Default)]
+pub(crate) struct ScreenCells {
+    width: usize,
+    height: usize,
+    cells: Vec<ScreenCell>,
+}
+
+impl ScreenCells {
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Forgets everything that was drawn, for when the whole screen is cleared
+    pub fn reset(&mut self, width: usize, height: usize) {
+        let blank = ScreenCell {
+            grapheme: " ".to_string(),
+            colors: Colors::default(),
+        };
+        self.width = width;
+        self.height = height;
+        self.cells = vec![blank; width * height];
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        Some(y as usize * self.width + x as usize)
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<&ScreenCell> {
+        self.index(x, y).map(|index| &self.cells[index])
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, grapheme: &str, colors: Colors) {
+        if let Some(index) = self.index(x, y) {
+            let cell = &mut self.cells[index];
+            // Reuses the string, since this happens for every cell that's drawn
+            cell.grapheme.clear();
+            cell.grapheme.push_str(grapheme);
+            cell.colors = colors;
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
 pub(crate) struct EntityDepth {
     pub entity: Entity,
+    /// Where the entity's layer comes in the drawing order
+    pub layer: usize,
     pub z: i32,
 }
 
+pub(crate) struct TilemapDetails {
+    pub depth: EntityDepth,
+    /// The parts of the screen the tilemap covers
+    pub rects: Vec<ScreenRect>,
+    /// The parts of the screen where its tiles changed
+    pub dirty: Vec<ScreenRect>,
+}
+
+/// Where a particle cloud's cells are on the screen. Like a tilemap's tiles, they're drawn on their
+/// own rather than as a whole sprite.
+pub(crate) struct ParticleDetails {
+    pub depth: EntityDepth,
+    /// The parts of the screen the particles are in
+    pub rects: Vec<ScreenRect>,
+    /// The parts of the screen particles left, which have to be blanked
+    pub vacated: Vec<ScreenRect>,
+    /// The parts of the screen that particles moved into or changed in, which are drawn over
+    pub covered: Vec<ScreenRect>,
+}
+
+/// A style drawn on top of every entity beneath a depth, like the dimmed backdrop behind a dialog.
+/// Blank parts of the screen are beneath everything, so they're drawn with it too.
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+pub(crate) struct StyleOverride {
+    /// The rank of the layer and the z that everything beneath gets the style
+    pub below: (usize, i32),
+    pub style: Option<Style>,
+}
+
+impl StyleOverride {
+    /// The style to draw on top of an entity on the layer with the given rank and at the given z, if
+    /// there is one
+    pub fn style_at(&self, layer: usize, z: i32) -> Option<Style> {
+        self.style.filter(|_| (layer, z) < self.below)
+    }
+}
+
 #[derive(Bundle, Default)]
 pub struct SpriteBundle {
     pub sprite: Handle<Sprite>,
@@ -38,9 +152,11 @@ pub struct SpriteBundle {
     pub visible: Visible,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Colors {
+    #[serde(with = "color_parser")]
     pub foreground: Option<Color>,
+    #[serde(with = "color_parser")]
     pub background: Option<Color>,
 }
 impl Default for Colors {
@@ -97,8 +213,10 @@ impl Colors {
     }
 }
 
-mod attribute_parser {
-    use serde::de::Visitor;
+// Attributes are written as a list of names, like ["Bold", "Underlined"]. The bitmask integer that
+// older stylemaps used is still accepted
+pub(crate) mod attribute_parser {
+    use serde::de::{SeqAccess, Visitor};
     use serde::{Deserializer, Serializer};
     pub fn serialize<S>(
         attrs: &crossterm::style::Attributes,
@@ -107,13 +225,16 @@ mod attribute_parser {
     where
         S: Serializer,
     {
-        let mut attr_bits = 0u32;
-        for attr in crossterm::style::Attribute::iterator() {
-            if attrs.has(attr) {
-                attr_bits |= attr.bytes();
-            }
-        }
-        serializer.serialize_u32(attr_bits)
+        let names = crossterm::style::Attribute::iterator()
+            .filter(|attr| attrs.has(*attr))
+            .map(|attr| format!("{:?}", attr));
+        serializer.collect_seq(names)
+    }
+
+    pub fn parse_name(name: &str) -> Option<crossterm::style::Attribute> {
+        let name = name.replace('_', "").to_lowercase();
+        crossterm::style::Attribute::iterator()
+            .find(|attr| format!("{:?}", attr).to_lowercase() == name)
     }
 
     struct AttrVisitor;
@@ -122,47 +243,396 @@ mod attribute_parser {
         type Value = crossterm::style::Attributes;
 
         fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("expecting an u32")
+            formatter.write_str("expecting a list of attribute names or an u32")
         }
 
-        fn visit_u32<E>(self, attr_bits: u32) -> Result<Self::Value, E>
+        fn visit_u64<E>(self, attr_bits: u64) -> Result<Self::Value, E>
         where
             E: serde::de::Error,
         {
+            if attr_bits > u32::MAX as u64 {
+                return Err(E::custom(format!("invalid attribute bits {}", attr_bits)));
+            }
+
             let mut attrs = crossterm::style::Attributes::default();
             for attr in crossterm::style::Attribute::iterator() {
-                if attr_bits & attr.bytes() != 0 {
+                if attr_bits as u32 & attr.bytes() != 0 {
                     attrs.set(attr);
                 }
             }
             Ok(attrs)
         }
+
+        fn visit_i64<E>(self, attr_bits: i64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            if attr_bits < 0 {
+                return Err(E::custom(format!("invalid attribute bits {}", attr_bits)));
+            }
+            self.visit_u64(attr_bits as u64)
+        }
+
+        fn visit_str<E>(self, name: &str) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            parse_name(name)
+                .map(|attr| attr.into())
+                .ok_or_else(|| E::custom(format!("unknown attribute {:?}", name)))
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut attrs = crossterm::style::Attributes::default();
+            while let Some(name) = seq.next_element::<String>()? {
+                let attr = parse_name(&name).ok_or_else(|| {
+                    serde::de::Error::custom(format!("unknown attribute {:?}", name))
+                })?;
+                attrs.set(attr);
+            }
+            Ok(attrs)
+        }
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<crossterm::style::Attributes, D::Error>
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_u32(AttrVisitor)
+        deserializer.deserialize_any(AttrVisitor)
     }
 }
 
+// Colors are written as a bare string, either a name like "dark_red" or a hex code like "#ff8800",
+// or as a bare number for an ANSI value. The `Some(DarkRed)` form that older stylemaps used is
+// still accepted
+pub(crate) mod color_parser {
+    use crossterm::style::Color;
+    use serde::de::Visitor;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    static NAMES: &[(&str, Color)] = &[
+        ("reset", Color::Reset),
+        ("black", Color::Black),
+        ("dark_grey", Color::DarkGrey),
+        ("red", Color::Red),
+        ("dark_red", Color::DarkRed),
+        ("green", Color::Green),
+        ("dark_green", Color::DarkGreen),
+        ("yellow", Color::Yellow),
+        ("dark_yellow", Color::DarkYellow),
+        ("blue", Color::Blue),
+        ("dark_blue", Color::DarkBlue),
+        ("magenta", Color::Magenta),
+        ("dark_magenta", Color::DarkMagenta),
+        ("cyan", Color::Cyan),
+        ("dark_cyan", Color::DarkCyan),
+        ("white", Color::White),
+        ("grey", Color::Grey),
+    ];
+
+    pub fn serialize<S>(color: &Option<Color>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match color {
+            None => serializer.serialize_none(),
+            Some(Color::Rgb { r, g, b }) => {
+                serializer.serialize_str(&format!("#{:02x}{:02x}{:02x}", r, g, b))
+            }
+            Some(Color::AnsiValue(value)) => serializer.serialize_u8(*value),
+            Some(color) => {
+                let (name, _) = NAMES
+                    .iter()
+                    .find(|(_, named)| named == color)
+                    .expect("every named color is in the table");
+                serializer.serialize_str(name)
+            }
+        }
+    }
+
+    /// Parses a color name or a "#rgb" or "#rrggbb" hex code
+    pub fn parse(value: &str) -> Option<Color> {
+        if let Some(hex) = value.strip_prefix('#') {
+            if !hex.is_ascii() {
+                return None;
+            }
+            let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+            return match hex.len() {
+                3 => Some(Color::Rgb {
+                    r: channel(&hex[0..1])? * 0x11,
+                    g: channel(&hex[1..2])? * 0x11,
+                    b: channel(&hex[2..3])? * 0x11,
+                }),
+                6 => Some(Color::Rgb {
+                    r: channel(&hex[0..2])?,
+                    g: channel(&hex[2..4])?,
+                    b: channel(&hex[4..6])?,
+                }),
+                _ => None,
+            };
+        }
+
+        // Be forgiving about how names are written, so "dark_red", "DarkRed" and "dark red" all work
+        let normalized: String = value
+            .chars()
+            .filter(|c| *c != '_' && *c != ' ')
+            .flat_map(char::to_lowercase)
+            .collect();
+        NAMES
+            .iter()
+            .find(|(name, _)| name.replace('_', "") == normalized)
+            .map(|(_, color)| *color)
+    }
+
+    struct ColorVisitor;
+
+    impl<'de> Visitor<'de> for ColorVisitor {
+        type Value = Option<Color>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("expecting a color name, a hex color, an ANSI value, or an option")
+        }
+
+        fn visit_none<E>(self) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_unit<E>(self) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(None)
+        }
+
+        // Stylemaps saved before colors had names wrote them as crossterm does, like `Some(White)`.
+        // RON drops the names of bare identifiers when it's asked for whatever comes next, so a
+        // color inside `Some(...)` can only be read one way, and it's read the way those were written.
+        fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Color::deserialize(deserializer).map(Some)
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            parse(value)
+                .map(Some)
+                .ok_or_else(|| E::custom(format!("unknown color {:?}", value)))
+        }
+
+        fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            if value > u8::MAX as u64 {
+                return Err(E::custom(format!("ANSI color {} is out of range", value)));
+            }
+            Ok(Some(Color::AnsiValue(value as u8)))
+        }
+
+        fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            if value < 0 {
+                return Err(E::custom(format!("ANSI color {} is out of range", value)));
+            }
+            self.visit_u64(value as u64)
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Color>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ColorVisitor)
+    }
+}
+
+/// Reads a RON asset which has colors in it, like a stylemap or tileset
+pub(crate) fn from_ron<T>(bytes: &[u8]) -> Result<T, anyhow::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    Ok(ron::de::from_bytes(bytes)?)
+}
+
+/// How a style's colors are mixed with the colors of whatever's drawn under the cell. Colors are
+/// mixed as RGB, so they need a truecolor terminal to look right, and colors that have no RGB value,
+/// like `Color::Reset`, are drawn as they are.
+///
+/// Spaces in blended cells show the grapheme underneath, so a sprite of spaces can shade or tint
+/// what's under it without hiding it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Blend {
+    /// The colors are drawn as they are
+    Replace,
+    /// The colors are drawn over the ones underneath with an opacity, where 255 is fully opaque,
+    /// for things like fog and translucent panels
+    Alpha(u8),
+    /// The colors are multiplied with the ones underneath, which darkens them, for things like
+    /// shadows
+    Multiply,
+    /// What's underneath is shaded in the colors, keeping how bright it is, like colored glass
+    Tint,
+}
+
+impl Default for Blend {
+    fn default() -> Self {
+        Blend::Replace
+    }
+}
+
+impl Blend {
+    pub fn is_replace(&self) -> bool {
+        *self == Blend::Replace
+    }
+
+    /// Mixes colors with the colors underneath them. Colors that aren't set are the colors
+    /// underneath.
+    pub fn apply(&self, colors: Colors, underneath: Colors) -> Colors {
+        Colors {
+            foreground: self.mix(colors.foreground, underneath.foreground),
+            background: self.mix(colors.background, underneath.background),
+        }
+    }
+
+    fn mix(&self, color: Option<Color>, underneath: Option<Color>) -> Option<Color> {
+        let color = match color {
+            Some(color) => color,
+            None => return underneath,
+        };
+        let (top, bottom) = match (to_rgb(color), underneath.and_then(to_rgb)) {
+            (Some(top), Some(bottom)) => (top, bottom),
+            _ => return Some(color),
+        };
+
+        let channels = |mix: &dyn Fn(u32, u32) -> u32| Color::Rgb {
+            r: mix(top.0 as u32, bottom.0 as u32) as u8,
+            g: mix(top.1 as u32, bottom.1 as u32) as u8,
+            b: mix(top.2 as u32, bottom.2 as u32) as u8,
+        };
+        Some(match *self {
+            Blend::Replace => color,
+            Blend::Alpha(alpha) => {
+                let alpha = alpha as u32;
+                channels(&|top, bottom| (top * alpha + bottom * (255 - alpha)) / 255)
+            }
+            Blend::Multiply => channels(&|top, bottom| top * bottom / 255),
+            Blend::Tint => {
+                let luma =
+                    (bottom.0 as u32 * 299 + bottom.1 as u32 * 587 + bottom.2 as u32 * 114) / 1000;
+                channels(&|top, _| top * luma / 255)
+            }
+        })
+    }
+}
+
+/// The RGB value of a color, using the usual xterm values for the named and ANSI colors. Colors that
+/// depend on the terminal, like `Color::Reset`, don't have one.
+pub fn to_rgb(color: Color) -> Option<(u8, u8, u8)> {
+    const ANSI: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (128, 0, 0),
+        (0, 128, 0),
+        (128, 128, 0),
+        (0, 0, 128),
+        (128, 0, 128),
+        (0, 128, 128),
+        (192, 192, 192),
+        (128, 128, 128),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (0, 0, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+    let ansi = match color {
+        Color::Reset => return None,
+        Color::Rgb { r, g, b } => return Some((r, g, b)),
+        Color::AnsiValue(value) => value,
+        Color::Black => 0,
+        Color::DarkRed => 1,
+        Color::DarkGreen => 2,
+        Color::DarkYellow => 3,
+        Color::DarkBlue => 4,
+        Color::DarkMagenta => 5,
+        Color::DarkCyan => 6,
+        Color::Grey => 7,
+        Color::DarkGrey => 8,
+        Color::Red => 9,
+        Color::Green => 10,
+        Color::Yellow => 11,
+        Color::Blue => 12,
+        Color::Magenta => 13,
+        Color::Cyan => 14,
+        Color::White => 15,
+    };
+    Some(match ansi {
+        0..=15 => ANSI[ansi as usize],
+        16..=231 => {
+            let cube = ansi - 16;
+            (
+                CUBE[(cube / 36) as usize],
+                CUBE[(cube / 6 % 6) as usize],
+                CUBE[(cube % 6) as usize],
+            )
+        }
+        _ => {
+            let grey = 8 + (ansi - 232) * 10;
+            (grey, grey, grey)
+        }
+    })
+}
+
+/// Fields may be added to styles, so outside this crate they're made with the constructors and the
+/// `with_` methods rather than struct literals
 #[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct Style {
     #[serde(with = "attribute_parser")]
     pub attributes: crossterm::style::Attributes,
     pub colors: Colors,
+    #[serde(default, skip_serializing_if = "Blend::is_replace")]
+    pub blend: Blend,
+}
+
+// Attributes can't be hashed, so styles are hashed by their colors and blend alone. Styles that are
+// equal still hash the same.
+impl std::hash::Hash for Style {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.colors.hash(state);
+        self.blend.hash(state);
+    }
 }
 
 impl Style {
     pub fn new(colors: Colors, attributes: crossterm::style::Attributes) -> Style {
-        Style { colors, attributes }
+        Style {
+            colors,
+            attributes,
+            blend: Blend::Replace,
+        }
     }
 
     pub fn with_attrib(attribute: crossterm::style::Attribute) -> Style {
         Style {
             colors: Colors::default(),
             attributes: attribute.into(),
+            blend: Blend::Replace,
         }
     }
 
@@ -170,6 +640,7 @@ impl Style {
         Style {
             colors: Colors::default(),
             attributes,
+            blend: Blend::Replace,
         }
     }
 
@@ -177,6 +648,7 @@ impl Style {
         Style {
             colors: Colors::fg(foreground),
             attributes: crossterm::style::Attribute::Reset.into(),
+            blend: Blend::Replace,
         }
     }
 
@@ -184,6 +656,7 @@ impl Style {
         Style {
             colors: Colors::bg(background),
             attributes: crossterm::style::Attribute::Reset.into(),
+            blend: Blend::Replace,
         }
     }
 
@@ -191,8 +664,15 @@ impl Style {
         Style {
             colors,
             attributes: crossterm::style::Attribute::Reset.into(),
+            blend: Blend::Replace,
         }
     }
+
+    /// Mixes the style's colors with whatever's drawn under it
+    pub fn with_blend(mut self, blend: Blend) -> Style {
+        self.blend = blend;
+        self
+    }
 }
 
 impl Default for Style {
@@ -200,20 +680,405 @@ impl Default for Style {
         Style {
             attributes: crossterm::style::Attribute::Reset.into(),
             colors: Colors::default(),
+            blend: Blend::Replace,
+        }
+    }
+}
+
+/// Index value which marks a cell in a `StyleIndices` grid as having no style of its own
+pub const NO_STYLE_U8: u8 = u8::MAX;
+/// Index value which marks a cell in a wide or run length encoded `StyleIndices` grid as having no
+/// style of its own
+pub const NO_STYLE_U16: u16 = u16::MAX;
+
+/// The indices into a palette of styles, stored in one of a few encodings depending on how many
+/// unique styles there are and how repetitive the rows are
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StyleIndices {
+    /// One byte per cell, so the palette can have at most 255 styles, indexed from 0 to 254.
+    /// `NO_STYLE_U8` marks cells without a style.
+    Narrow(Vec<Vec<u8>>),
+    /// Two bytes per cell, so the palette can have at most 65535 styles, indexed from 0 to 65534.
+    /// `NO_STYLE_U16` marks cells without a style.
+    Wide(Vec<Vec<u16>>),
+    /// Each row is a list of (run length, palette index) pairs
+    RunLength(Vec<Vec<(u16, u16)>>),
+}
+
+impl StyleIndices {
+    fn index_at(&self, x: usize, y: usize) -> Option<usize> {
+        match self {
+            StyleIndices::Narrow(rows) => rows
+                .get(y)
+                .and_then(|row| row.get(x))
+                .filter(|idx| **idx != NO_STYLE_U8)
+                .map(|idx| *idx as usize),
+            StyleIndices::Wide(rows) => rows
+                .get(y)
+                .and_then(|row| row.get(x))
+                .filter(|idx| **idx != NO_STYLE_U16)
+                .map(|idx| *idx as usize),
+            StyleIndices::RunLength(rows) => {
+                let mut start = 0;
+                for (len, idx) in rows.get(y)? {
+                    start += *len as usize;
+                    if x < start {
+                        return if *idx == NO_STYLE_U16 {
+                            None
+                        } else {
+                            Some(*idx as usize)
+                        };
+                    }
+                }
+                None
+            }
         }
     }
+
+    fn row_len(&self, y: usize) -> usize {
+        match self {
+            StyleIndices::Narrow(rows) => rows.get(y).map_or(0, |row| row.len()),
+            StyleIndices::Wide(rows) => rows.get(y).map_or(0, |row| row.len()),
+            StyleIndices::RunLength(rows) => rows
+                .get(y)
+                .map_or(0, |row| row.iter().map(|(len, _)| *len as usize).sum()),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            StyleIndices::Narrow(rows) => rows.len(),
+            StyleIndices::Wide(rows) => rows.len(),
+            StyleIndices::RunLength(rows) => rows.len(),
+        }
+    }
+}
+
+/// The per-grapheme styles of a `StyleMap`, stored as indices into a palette so each unique style is
+/// only stored once. This is much smaller than a style for every cell for large sprites.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StylePalette {
+    pub styles: Vec<Style>,
+    pub indices: StyleIndices,
+}
+
+impl StylePalette {
+    pub fn style_at(&self, x: usize, y: usize) -> Option<&Style> {
+        self.indices
+            .index_at(x, y)
+            .and_then(|idx| self.styles.get(idx))
+    }
+
+    /// The number of rows in the palette's grid
+    pub fn height(&self) -> usize {
+        self.indices.height()
+    }
+
+    /// The number of cells in row y
+    pub fn row_len(&self, y: usize) -> usize {
+        self.indices.row_len(y)
+    }
+
+    /// Builds the smallest palette encoding of rows of cells, where None is a cell without a style
+    /// of its own. Returns None if there are too many unique styles to be indexed.
+    pub fn from_cells<I, R>(cells: I) -> Option<StylePalette>
+    where
+        I: IntoIterator<Item = R>,
+        R: IntoIterator<Item = Option<Style>>,
+    {
+        let mut styles: Vec<Style> = Vec::new();
+        let mut known: HashMap<Style, u16> = HashMap::default();
+        let mut rows: Vec<Vec<u16>> = Vec::new();
+        let mut cells_count = 0;
+        let mut runs = 0;
+
+        for cell_row in cells {
+            let mut row = Vec::new();
+            for cell in cell_row {
+                let idx = match cell {
+                    Some(style) => match known.get(&style) {
+                        Some(idx) => *idx,
+                        None => {
+                            if styles.len() >= NO_STYLE_U16 as usize {
+                                return None;
+                            }
+                            let idx = styles.len() as u16;
+                            styles.push(style);
+                            known.insert(style, idx);
+                            idx
+                        }
+                    },
+                    None => NO_STYLE_U16,
+                };
+                if row.last() != Some(&idx) {
+                    runs += 1;
+                }
+                row.push(idx);
+            }
+            cells_count += row.len();
+            rows.push(row);
+        }
+
+        let narrow = styles.len() <= NO_STYLE_U8 as usize;
+        let indexed_size = if narrow { cells_count } else { cells_count * 2 };
+        let indices = if runs * 4 < indexed_size {
+            StyleIndices::RunLength(rows.iter().map(|row| encode_runs(row)).collect())
+        } else if narrow {
+            StyleIndices::Narrow(
+                rows.iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|idx| {
+                                if *idx == NO_STYLE_U16 {
+                                    NO_STYLE_U8
+                                } else {
+                                    *idx as u8
+                                }
+                            })
+                            .collect()
+                    })
+                    .collect(),
+            )
+        } else {
+            StyleIndices::Wide(rows)
+        };
+
+        Some(StylePalette { styles, indices })
+    }
+}
+
+fn encode_runs(row: &[u16]) -> Vec<(u16, u16)> {
+    let mut runs: Vec<(u16, u16)> = Vec::new();
+    for idx in row {
+        match runs.last_mut() {
+            Some((len, last)) if last == idx && *len < u16::MAX => *len += 1,
+            _ => runs.push((1, *idx)),
+        }
+    }
+    runs
+}
+
+/// Per-grapheme styles in either of the forms a `StyleMap` can hold them, for code that generates
+/// them. Give it to a stylemap with `StyleMap::with_grid`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum StyleGrid {
+    Full(Vec<Vec<Style>>),
+    Palette(StylePalette),
+}
+
+impl Default for StyleGrid {
+    fn default() -> Self {
+        StyleGrid::Full(Vec::new())
+    }
+}
+
+impl From<Vec<Vec<Style>>> for StyleGrid {
+    fn from(map: Vec<Vec<Style>>) -> Self {
+        StyleGrid::Full(map)
+    }
+}
+
+impl StyleGrid {
+    /// Builds the smallest palette encoding of rows of cells, where None is a cell without a style
+    /// of its own. Returns None if there are too many unique styles to be indexed.
+    pub fn from_cells<I, R>(cells: I) -> Option<StyleGrid>
+    where
+        I: IntoIterator<Item = R>,
+        R: IntoIterator<Item = Option<Style>>,
+    {
+        StylePalette::from_cells(cells).map(StyleGrid::Palette)
+    }
+
+    /// Builds a palette like `from_cells`, or falls back to a style for every cell if there are too
+    /// many unique styles, like in a truecolor photo. Cells without a style of their own get the
+    /// `unstyled` style there, which is usually the stylemap's style.
+    pub fn from_cells_or_full<I, R>(cells: I, unstyled: Style) -> StyleGrid
+    where
+        I: IntoIterator<Item = R>,
+        R: IntoIterator<Item = Option<Style>>,
+    {
+        let cells: Vec<Vec<Option<Style>>> = cells
+            .into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect();
+        StyleGrid::from_cells(cells.iter().map(|row| row.iter().copied())).unwrap_or_else(|| {
+            StyleGrid::Full(
+                cells
+                    .iter()
+                    .map(|row| row.iter().map(|cell| cell.unwrap_or(unstyled)).collect())
+                    .collect(),
+            )
+        })
+    }
+}
+
+/// How a cell of a sprite covers whatever's under it
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Alpha {
+    /// Follows the entity's `Visible`, so the cell only shows what's under it if it's a space with
+    /// no style of its own in a transparent sprite
+    Auto,
+    /// Always drawn, even if it's a space with no style of its own
+    Opaque,
+    /// Never drawn, so whatever's under it shows through
+    Transparent,
+    /// The grapheme is drawn in its own foreground color, over the background color of whatever's
+    /// under it
+    TransparentBackground,
+}
+
+impl Default for Alpha {
+    fn default() -> Self {
+        Alpha::Auto
+    }
+}
+
+/// The characters `AlphaMask::parse` reads, and the mask is saved with. Any other character is
+/// `Alpha::Auto`.
+pub const ALPHA_MASK_KEY: [(char, Alpha); 3] = [
+    ('#', Alpha::Opaque),
+    ('.', Alpha::Transparent),
+    ('-', Alpha::TransparentBackground),
+];
+
+/// Which cells of a sprite cover what's under them, laid out the same way as the sprite's
+/// graphemes. Cells outside the mask are `Alpha::Auto`.
+///
+/// Masks can be written as text the same shape as the sprite, where each character stands for a
+/// cell, and that's how they're saved in .stylemap files.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<String>", into = "Vec<String>")]
+pub struct AlphaMask(pub Vec<Vec<Alpha>>);
+
+impl AlphaMask {
+    pub fn new(rows: Vec<Vec<Alpha>>) -> AlphaMask {
+        AlphaMask(rows)
+    }
+
+    /// Reads a mask written with the characters in `ALPHA_MASK_KEY`, like `"..##..\n-####-"`
+    pub fn parse(text: &str) -> AlphaMask {
+        AlphaMask::from_key(text, &ALPHA_MASK_KEY)
+    }
+
+    /// Reads a mask written with characters of your own choosing. Characters that aren't in the key
+    /// are `Alpha::Auto`.
+    pub fn from_key(text: &str, key: &[(char, Alpha)]) -> AlphaMask {
+        AlphaMask(
+            text.lines()
+                .map(|line| {
+                    line.chars()
+                        .map(|c| {
+                            key.iter()
+                                .find(|(key_char, _)| *key_char == c)
+                                .map_or(Alpha::Auto, |(_, alpha)| *alpha)
+                        })
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    pub fn alpha_at(&self, x: usize, y: usize) -> Alpha {
+        self.0
+            .get(y)
+            .and_then(|row| row.get(x))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0
+            .iter()
+            .all(|row| row.iter().all(|alpha| *alpha == Alpha::Auto))
+    }
+}
+
+impl From<Vec<String>> for AlphaMask {
+    fn from(rows: Vec<String>) -> Self {
+        AlphaMask::parse(&rows.join("\n"))
+    }
+}
+
+impl From<AlphaMask> for Vec<String> {
+    fn from(mask: AlphaMask) -> Self {
+        mask.0
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|alpha| {
+                        ALPHA_MASK_KEY
+                            .iter()
+                            .find(|(_, key_alpha)| key_alpha == alpha)
+                            .map_or(' ', |(c, _)| *c)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
 }
 
-#[derive(Default, Serialize, Deserialize, PartialEq, Eq, TypeUuid)]
+/// Fields may be added to stylemaps, so outside this crate they're made with the constructors and
+/// the `with_` methods rather than struct literals
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq, Eq, TypeUuid)]
+#[non_exhaustive]
 #[uuid = "a5418d12-e050-498a-a31e-37fd0b6c078d"]
 pub struct StyleMap {
     pub style: Style,
+    #[serde(default)]
     pub map: Vec<Vec<Style>>,
+    /// The per-grapheme styles stored as a palette, which is used instead of `map` when it's set.
+    /// `compact` moves `map` into it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub palette: Option<StylePalette>,
+    /// Which cells cover what's under them. Sprites without a mask follow their `Visible`.
+    #[serde(default, skip_serializing_if = "AlphaMask::is_empty")]
+    pub mask: AlphaMask,
 }
 
 impl StyleMap {
     pub fn new(style: Style, map: Vec<Vec<Style>>) -> StyleMap {
-        StyleMap { style, map }
+        StyleMap {
+            style,
+            map,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_palette(style: Style, palette: StylePalette) -> StyleMap {
+        StyleMap {
+            style,
+            palette: Some(palette),
+            ..Default::default()
+        }
+    }
+
+    /// Makes a stylemap from per-grapheme styles in either form
+    pub fn with_grid(style: Style, grid: StyleGrid) -> StyleMap {
+        match grid {
+            StyleGrid::Full(map) => StyleMap::new(style, map),
+            StyleGrid::Palette(palette) => StyleMap::with_palette(style, palette),
+        }
+    }
+
+    /// Moves the map into the smallest palette encoding. If there are too many unique styles to
+    /// index, the map is left as it is.
+    pub fn compact(&mut self) {
+        if self.palette.is_some() {
+            return;
+        }
+        let map = &self.map;
+        let cells = map.iter().map(|row| row.iter().copied().map(Some));
+        if let Some(palette) = StylePalette::from_cells(cells) {
+            self.palette = Some(palette);
+            self.map = Vec::new();
+        }
+    }
+
+    /// The number of rows with per-grapheme styles
+    pub fn height(&self) -> usize {
+        self.palette
+            .as_ref()
+            .map_or(self.map.len(), StylePalette::height)
     }
 
     pub fn with_attrib(attribute: crossterm::style::Attribute) -> StyleMap {
@@ -251,9 +1116,32 @@ impl StyleMap {
         }
     }
 
+    /// Gives the map a mask, to pick which cells cover what's under them
+    pub fn with_mask(mut self, mask: AlphaMask) -> StyleMap {
+        self.mask = mask;
+        self
+    }
+
+    pub fn alpha_at(&self, x: usize, y: usize) -> Alpha {
+        self.mask.alpha_at(x, y)
+    }
+
+    /// Whether any of the map's styles are mixed with what's under them
+    pub(crate) fn is_blended(&self) -> bool {
+        let blended = |style: &Style| !style.blend.is_replace();
+        blended(&self.style)
+            || match &self.palette {
+                Some(palette) => palette.styles.iter().any(blended),
+                None => self.map.iter().flatten().any(blended),
+            }
+    }
+
     /// If there is a style available in the map, this fetches it. Otherwise, this returns None
     pub fn style_at(&self, x: usize, y: usize) -> Option<&Style> {
-        self.map.get(y).and_then(|vec| vec.get(x))
+        match &self.palette {
+            Some(palette) => palette.style_at(x, y),
+            None => self.map.get(y).and_then(|vec| vec.get(x)),
+        }
     }
 
     /// If there is a style for the grapheme at position x,y in the map, this fetches it. Otherwise
@@ -291,6 +1179,8 @@ impl Visible {
         }
     }
 
+    /// Lets spaces with no style of their own show what's under them. A stylemap's `AlphaMask` can
+    /// pick which cells are see-through instead.
     pub fn transparent() -> Visible {
         Visible {
             is_visible: true,
@@ -299,7 +1189,18 @@ impl Visible {
     }
 }
 
-#[derive(Default, Eq, PartialEq, Debug, TypeUuid)]
+// How many edits a sprite remembers the cells of before the oldest are merged together
+const SPRITE_EDITS: usize = 16;
+
+// Revisions are counted across every sprite, so a sprite that's put in place of another behind the
+// same handle is never taken for one that's already been drawn
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, TypeUuid)]
 #[uuid = "f04f5352-e656-4a90-95a5-2269c02d0091"]
 pub struct Sprite {
     // The whole sprites's data
@@ -309,6 +1210,47 @@ pub struct Sprite {
     // must be updated in tandem
     graphemes: Vec<Vec<(usize, usize)>>,
     max_width: usize,
+    // Changes with every change made to the sprite. The renderer keeps the revision it last drew,
+    // and redraws the cells changed since then.
+    revision: u64,
+    // The revision the sprite was last replaced wholesale at
+    replaced: u64,
+    // The cells each edit since then changed, and the revision it made
+    edits: Vec<(u64, ScreenRect)>,
+}
+
+// Sprites are the same if they look the same, however they were edited to get there
+impl PartialEq for Sprite {
+    fn eq(&self, other: &Sprite) -> bool {
+        self.data == other.data
+    }
+}
+
+impl Eq for Sprite {}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        let revision = next_revision();
+        Sprite {
+            data: String::new(),
+            graphemes: Vec::new(),
+            max_width: 0,
+            revision,
+            replaced: revision,
+            edits: Vec::new(),
+        }
+    }
+}
+
+// The length of the newline at the start of some text, if there is one
+fn newline_len(text: &str) -> usize {
+    if text.starts_with("\r\n") {
+        2
+    } else if text.starts_with('\n') || text.starts_with('\r') {
+        1
+    } else {
+        0
+    }
 }
 
 impl Sprite {
@@ -373,6 +1315,347 @@ impl Sprite {
         self.data = value.to_string();
         self.graphemes.clear();
         Sprite::convert_to_sprite(self);
+        self.revision = next_revision();
+        self.replaced = self.revision;
+        self.edits.clear();
+    }
+
+    /// Sets the grapheme in a cell. The line is padded with spaces, and lines are added to the
+    /// sprite, to reach the cell if they have to be. Only the first grapheme of the string is used,
+    /// and a newline is set as a space.
+    pub fn set_cell(&mut self, x: usize, y: usize, grapheme: &str) {
+        let old_size = (self.width(), self.height());
+        let dirty = self.replace_cells(x, y, &[grapheme]);
+        if dirty.width > 0 {
+            self.mark_dirty(dirty, old_size);
+        }
+    }
+
+    /// Sets every cell in a rectangle to the same grapheme
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, grapheme: &str) {
+        let old_size = (self.width(), self.height());
+        let cells = vec![grapheme; width];
+        let mut dirty = ScreenRect::default();
+        for row in y..y + height {
+            dirty = dirty.union(&self.replace_cells(x, row, &cells));
+        }
+        if dirty.width > 0 {
+            self.mark_dirty(dirty, old_size);
+        }
+    }
+
+    /// Copies another sprite's graphemes onto this one, with its top left corner at a cell. The
+    /// spaces that pad out the other sprite's shorter lines aren't copied.
+    pub fn blit(&mut self, other: &Sprite, x: usize, y: usize) {
+        let old_size = (self.width(), self.height());
+        let mut dirty = ScreenRect::default();
+        for (row, line) in other.graphemes().iter().enumerate() {
+            let cells: Vec<&str> = line
+                .iter()
+                .map(|grapheme| other.grapheme(grapheme))
+                .collect();
+            dirty = dirty.union(&self.replace_cells(x, y + row, &cells));
+        }
+        if dirty.width > 0 {
+            self.mark_dirty(dirty, old_size);
+        }
+    }
+
+    /// Sets the graphemes in a run of cells along a line, starting from a cell, and returns the
+    /// cells that changed, which is empty if none did. The line is padded and lines are added like
+    /// set_cell does. The changed part of the line is built and spliced into the data in one go,
+    /// rather than a cell at a time.
+    fn replace_cells(&mut self, x: usize, y: usize, cells: &[&str]) -> ScreenRect {
+        if cells.is_empty() {
+            return ScreenRect::default();
+        }
+        let cells: Vec<&str> = cells
+            .iter()
+            .map(|grapheme| match grapheme.graphemes(true).next() {
+                None | Some("\r") | Some("\n") | Some("\r\n") => " ",
+                Some(grapheme) => grapheme,
+            })
+            .collect();
+        while self.graphemes.len() <= y {
+            self.push_line("");
+        }
+
+        // Only the cells from the first to the last one that changed are replaced. Cells past the
+        // end of the line always change, and so does any padding before them.
+        let len = self.graphemes[y].len();
+        let changed = |column: usize| {
+            column >= len || {
+                let (start, end) = self.graphemes[y][column];
+                self.data[start..end] != *cells[column - x]
+            }
+        };
+        let mut changed_columns = (x..x + cells.len()).filter(|column| changed(*column));
+        let first = match changed_columns.next() {
+            Some(first) => first,
+            None => return ScreenRect::default(),
+        };
+        let last = changed_columns.next_back().unwrap_or(first);
+        let first = first.min(len);
+        let end = last + 1;
+
+        let at = match self.graphemes[y].get(first) {
+            Some(grapheme) => grapheme.0,
+            None => self.line_end(y),
+        };
+        let until = if last < len {
+            self.graphemes[y][last].1
+        } else {
+            self.line_end(y)
+        };
+        let mut text = String::new();
+        let mut line = Vec::with_capacity(end - first);
+        for column in first..end {
+            let grapheme = if column < x { " " } else { cells[column - x] };
+            line.push((at + text.len(), at + text.len() + grapheme.len()));
+            text.push_str(grapheme);
+        }
+
+        self.splice(at..until, &text, (end, y));
+        self.graphemes[y].splice(first..end.min(len), line);
+        self.max_width = self.max_width.max(self.graphemes[y].len());
+        ScreenRect {
+            x: first as i32,
+            y: y as i32,
+            width: (end - first) as i32,
+            height: 1,
+        }
+    }
+
+    /// Inserts a line of text above a line, moving that line and the ones below it down. Empty
+    /// lines are added if the line is past the bottom of the sprite. Anything after a newline in the
+    /// text is left out.
+    pub fn insert_line(&mut self, y: usize, text: &str) {
+        let text = text.lines().next().unwrap_or_default();
+        let old_size = (self.width(), self.height());
+        if y >= self.height() {
+            while self.graphemes.len() < y {
+                self.push_line("");
+            }
+            self.push_line(text);
+        } else {
+            let at = self.line_start(y);
+            self.splice(at..at, &format!("{}\n", text), (0, y));
+            let line = Sprite::line_graphemes(text, at);
+            self.max_width = self.max_width.max(line.len());
+            self.graphemes.insert(y, line);
+        }
+
+        // Everything below the new line moves down
+        let y = y.min(old_size.1);
+        self.mark_dirty(
+            ScreenRect {
+                x: 0,
+                y: y as i32,
+                width: self.width() as i32,
+                height: (self.height() - y) as i32,
+            },
+            old_size,
+        );
+    }
+
+    /// Removes a line, moving the lines below it up
+    pub fn delete_line(&mut self, y: usize) {
+        if y >= self.height() {
+            return;
+        }
+        let old_size = (self.width(), self.height());
+        let start = self.line_start(y);
+        let end = self.line_end(y);
+        let end = end + newline_len(&self.data[end..]);
+        self.splice(start..end, "", (0, y + 1));
+        self.graphemes.remove(y);
+        self.max_width = self.graphemes.iter().map(Vec::len).max().unwrap_or(0);
+
+        // Everything below the line moves up, and the bottom line is left empty
+        self.mark_dirty(
+            ScreenRect {
+                x: 0,
+                y: y as i32,
+                width: old_size.0 as i32,
+                height: (old_size.1 - y) as i32,
+            },
+            old_size,
+        );
+    }
+
+    pub(crate) fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The cells that changed since a revision of the sprite, or None if it's been replaced
+    /// wholesale since then, or the revision is another sprite's, and all of it has to be drawn
+    pub(crate) fn dirty_since(&self, revision: u64) -> Option<ScreenRect> {
+        if revision < self.replaced || revision > self.revision {
+            return None;
+        }
+        Some(
+            self.edits
+                .iter()
+                .filter(|(edit, _)| *edit > revision)
+                .fold(ScreenRect::default(), |dirty, (_, cells)| {
+                    dirty.union(cells)
+                }),
+        )
+    }
+
+    fn mark_dirty(&mut self, mut cells: ScreenRect, (old_width, old_height): (usize, usize)) {
+        // Lines are padded out to the widest one, so when that changes the padding of every line
+        // does too
+        if self.width() != old_width {
+            let narrowest = self.width().min(old_width);
+            cells = cells.union(&ScreenRect {
+                x: narrowest as i32,
+                y: 0,
+                width: (self.width().max(old_width) - narrowest) as i32,
+                height: self.height().max(old_height) as i32,
+            });
+        }
+        self.revision = next_revision();
+        self.edits.push((self.revision, cells));
+        // The oldest edits are merged into the next one, which only makes a renderer that's that
+        // far behind draw more than it has to
+        if self.edits.len() > SPRITE_EDITS {
+            let (_, oldest) = self.edits.remove(0);
+            self.edits[0].1 = self.edits[0].1.union(&oldest);
+        }
+    }
+
+    /// Replaces some of the data, and moves the graphemes after it along to match. The graphemes
+    /// from a cell onwards are moved.
+    fn splice(&mut self, range: std::ops::Range<usize>, text: &str, (x, y): (usize, usize)) {
+        let delta = text.len() as isize - range.len() as isize;
+        self.data.replace_range(range, text);
+        if delta == 0 {
+            return;
+        }
+        for (line_num, line) in self.graphemes.iter_mut().enumerate().skip(y) {
+            let from = if line_num == y { x.min(line.len()) } else { 0 };
+            for grapheme in line[from..].iter_mut() {
+                grapheme.0 = (grapheme.0 as isize + delta) as usize;
+                grapheme.1 = (grapheme.1 as isize + delta) as usize;
+            }
+        }
+    }
+
+    // The graphemes of a line of text that's at some index in the data
+    fn line_graphemes(text: &str, at: usize) -> Vec<(usize, usize)> {
+        UnicodeSegmentation::grapheme_indices(text, true)
+            .map(|(start, grapheme)| (at + start, at + start + grapheme.len()))
+            .collect()
+    }
+
+    /// Where a line starts in the data
+    fn line_start(&self, y: usize) -> usize {
+        // Start from the end of the closest line above with anything in it, and step over the
+        // newlines after it
+        let above = self.graphemes[..y]
+            .iter()
+            .rposition(|line| !line.is_empty());
+        let (mut start, newlines) = match above {
+            Some(line) => (self.graphemes[line].last().unwrap().1, y - line),
+            None => (0, y),
+        };
+        for _ in 0..newlines {
+            start += newline_len(&self.data[start..]);
+        }
+        start
+    }
+
+    /// Where a line's graphemes end in the data, which is where its newline is if it has one
+    fn line_end(&self, y: usize) -> usize {
+        match self.graphemes[y].last() {
+            Some(grapheme) => grapheme.1,
+            None => self.line_start(y),
+        }
+    }
+
+    /// Adds a line to the bottom of the sprite
+    fn push_line(&mut self, text: &str) {
+        // Every line other than the last has a newline after it, so make sure the last line does
+        // before another goes after it
+        if let Some(last) = self.graphemes.len().checked_sub(1) {
+            let end = self.line_end(last);
+            if newline_len(&self.data[end..]) == 0 {
+                self.data.push('\n');
+            }
+        }
+        let line = Sprite::line_graphemes(text, self.data.len());
+        self.data.push_str(text);
+        self.data.push('\n');
+        self.max_width = self.max_width.max(line.len());
+        self.graphemes.push(line);
+    }
+}
+
+/// Replaces the sprite behind an entity's handle, or adds a new sprite if the handle doesn't point
+/// at one yet. This is how components which generate their entity's sprite hand it to the renderer.
+/// Nothing is redrawn if the sprite is already the same.
+pub(crate) fn set_sprite<T: std::string::ToString>(
+    sprites: &mut Assets<Sprite>,
+    handle: &mut Mut<Handle<Sprite>>,
+    value: T,
+) {
+    let value = value.to_string();
+    if sprites
+        .get(&**handle)
+        .map_or(false, |sprite| sprite.data() == value)
+    {
+        return;
+    }
+
+    if let Some(sprite) = sprites.get_mut(&**handle) {
+        sprite.update(value);
+    } else {
+        **handle = sprites.add(Sprite::new(value));
+    }
+}
+
+/// Replaces the stylemap behind an entity's handle, or adds a new stylemap if the handle doesn't
+/// point at one yet
+pub(crate) fn set_stylemap(
+    stylemaps: &mut Assets<StyleMap>,
+    handle: &mut Mut<Handle<StyleMap>>,
+    value: StyleMap,
+) {
+    if stylemaps
+        .get(&**handle)
+        .map_or(false, |stylemap| *stylemap == value)
+    {
+        return;
+    }
+
+    if let Some(stylemap) = stylemaps.get_mut(&**handle) {
+        *stylemap = value;
+    } else {
+        **handle = stylemaps.add(value);
+    }
+}
+
+/// Gives an entity a sprite of its own when a component which generates its sprite is added to it.
+/// Entities can be spawned sharing a sprite handle, and they'd write over each other's sprite every
+/// frame otherwise, so this runs before the component's sprite is generated.
+pub(crate) fn own_sprite<T: bevy::ecs::Component>(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut entities: Query<&mut Handle<Sprite>, Added<T>>,
+) {
+    for mut sprite in entities.iter_mut() {
+        *sprite = sprites.add(Sprite::new(""));
+    }
+}
+
+/// Gives an entity a stylemap of its own when a component which generates its stylemap is added to
+/// it, the same way `own_sprite` does for sprites
+pub(crate) fn own_stylemap<T: bevy::ecs::Component>(
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut entities: Query<&mut Handle<StyleMap>, Added<T>>,
+) {
+    for mut stylemap in entities.iter_mut() {
+        *stylemap = stylemaps.add(StyleMap::default());
     }
 }
 
@@ -411,17 +1694,100 @@ impl Position {
     }
 }
 
-#[derive(Default, Eq, PartialEq, Debug)]
-pub(crate) struct PreviousPosition {
+/// A rectangle of the screen
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ScreenRect {
     pub x: i32,
     pub y: i32,
-    pub z: i32,
+    pub width: i32,
+    pub height: i32,
 }
 
-#[derive(Default, Eq, PartialEq, Debug)]
-pub(crate) struct PreviousSize {
-    pub width: u16,
-    pub height: u16,
+impl ScreenRect {
+    pub fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.right() && y < self.bottom()
+    }
+
+    /// The smallest rectangle which covers both rectangles
+    pub fn union(&self, other: &ScreenRect) -> ScreenRect {
+        if self.width == 0 || self.height == 0 {
+            return *other;
+        }
+        if other.width == 0 || other.height == 0 {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        ScreenRect {
+            x,
+            y,
+            width: self.right().max(other.right()) - x,
+            height: self.bottom().max(other.bottom()) - y,
+        }
+    }
+
+    /// The part of the screen covered by both rectangles. It's empty if they don't overlap
+    pub fn intersect(&self, other: &ScreenRect) -> ScreenRect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        ScreenRect {
+            x,
+            y,
+            width: (self.right().min(other.right()) - x).max(0),
+            height: (self.bottom().min(other.bottom()) - y).max(0),
+        }
+    }
+}
+
+/// Where an entity is drawn, when it's somewhere other than its position, or it's clipped by
+/// something other than the window. Entities without one are drawn at their position, moved by the
+/// camera. The offset and clip don't include the camera's offset.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct DrawArea {
+    /// Added to the entity's position to get where it's drawn on the screen
+    pub offset_x: i32,
+    pub offset_y: i32,
+    /// The part of the screen the entity can be drawn in
+    pub clip: Option<ScreenRect>,
+    /// Whether the entity stays in the same place on the screen when the camera moves
+    pub screen_space: bool,
+}
+
+impl DrawArea {
+    /// Moves the area by the camera's offset, unless it's in screen space
+    pub fn with_camera(mut self, (camera_x, camera_y): (i32, i32)) -> DrawArea {
+        if !self.screen_space {
+            self.offset_x += camera_x;
+            self.offset_y += camera_y;
+            if let Some(clip) = self.clip.as_mut() {
+                clip.x += camera_x;
+                clip.y += camera_y;
+            }
+        }
+        self
+    }
+
+    /// The part of the screen covered by a sprite of the given size, after clipping
+    pub fn sprite_rect(&self, position: &Position, width: usize, height: usize) -> ScreenRect {
+        let rect = ScreenRect {
+            x: position.x + self.offset_x,
+            y: position.y + self.offset_y,
+            width: width as i32,
+            height: height as i32,
+        };
+        match &self.clip {
+            Some(clip) => rect.intersect(clip),
+            None => rect,
+        }
+    }
 }
 
 #[derive(Default, Eq, PartialEq, Debug)]
@@ -430,3 +1796,457 @@ pub(crate) struct GlobalPosition {
     pub y: i32,
     pub z: i32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(value: u8) -> Style {
+        Style::with_fg(Color::AnsiValue(value))
+    }
+
+    fn stylemap_from_ron(ron: &str) -> StyleMap {
+        from_ron(ron.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn palette_keeps_every_cell() {
+        let cells = vec![
+            vec![Some(grey(1)), None, Some(grey(2))],
+            vec![Some(grey(2)), Some(grey(2))],
+        ];
+        let palette = StylePalette::from_cells(cells.clone()).unwrap();
+        assert_eq!(palette.styles.len(), 2);
+        assert_eq!(palette.height(), 2);
+        assert_eq!(palette.row_len(1), 2);
+        for (y, row) in cells.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                assert!(palette.style_at(x, y) == cell.as_ref());
+            }
+        }
+        assert!(palette.style_at(3, 0).is_none());
+    }
+
+    #[test]
+    fn palette_picks_an_encoding() {
+        let runs = StylePalette::from_cells(vec![vec![Some(grey(1)); 40]]).unwrap();
+        assert!(matches!(runs.indices, StyleIndices::RunLength(_)));
+
+        let narrow = StylePalette::from_cells(vec![(0..=254).map(|v| Some(grey(v)))]).unwrap();
+        assert_eq!(narrow.styles.len(), 255);
+        assert!(matches!(narrow.indices, StyleIndices::Narrow(_)));
+        assert!(narrow.style_at(254, 0) == Some(&grey(254)));
+
+        let styles = (0..=255).map(|v| Some(grey(v)));
+        let wide = StylePalette::from_cells(vec![styles]).unwrap();
+        assert!(matches!(wide.indices, StyleIndices::Wide(_)));
+        assert!(wide.style_at(255, 0) == Some(&grey(255)));
+    }
+
+    #[test]
+    fn palette_runs_out_of_indices() {
+        let styles = (0..=u16::MAX as u32).map(|v| {
+            let [_, r, g, b] = v.to_be_bytes();
+            Some(Style::with_fg(Color::Rgb { r, g, b }))
+        });
+        assert!(StylePalette::from_cells(vec![styles]).is_none());
+    }
+
+    #[test]
+    fn compact_keeps_styles() {
+        let map = vec![vec![grey(1), grey(2), grey(1)], vec![grey(3)]];
+        let mut stylemap = StyleMap::new(Style::default(), map.clone());
+        stylemap.compact();
+        assert!(stylemap.palette.is_some());
+        assert!(stylemap.map.is_empty());
+        assert_eq!(stylemap.height(), 2);
+        for (y, row) in map.iter().enumerate() {
+            for (x, style) in row.iter().enumerate() {
+                assert!(stylemap.style_for(x, y) == *style);
+            }
+        }
+    }
+
+    #[test]
+    fn stylemaps_round_trip() {
+        let mut stylemap = StyleMap::new(grey(7), vec![vec![grey(1), grey(2)]]);
+        let full = ron::ser::to_string(&stylemap).unwrap();
+        assert!(stylemap_from_ron(&full) == stylemap);
+
+        stylemap.compact();
+        let compact = ron::ser::to_string(&stylemap).unwrap();
+        assert!(compact.contains("palette"));
+        assert!(stylemap_from_ron(&compact) == stylemap);
+    }
+
+    fn colors_from_ron(ron: &str) -> Colors {
+        from_ron(ron.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn colors_are_read_however_they_are_written() {
+        let rgb = Some(Color::Rgb {
+            r: 0xff,
+            g: 0x88,
+            b: 0x00,
+        });
+        let written = [
+            ("\"#ff8800\"", rgb),
+            (
+                "\"#f80\"",
+                Some(Color::Rgb {
+                    r: 0xff,
+                    g: 0x88,
+                    b: 0,
+                }),
+            ),
+            ("\"dark_red\"", Some(Color::DarkRed)),
+            ("\"DarkRed\"", Some(Color::DarkRed)),
+            ("\"dark red\"", Some(Color::DarkRed)),
+            ("Some(DarkRed)", Some(Color::DarkRed)),
+            ("Some(Rgb(r: 255, g: 136, b: 0))", rgb),
+            ("Some(AnsiValue(160))", Some(Color::AnsiValue(160))),
+            ("160", Some(Color::AnsiValue(160))),
+            ("None", None),
+        ];
+        for (color, expected) in written.iter() {
+            let ron = format!("(foreground: {}, background: None)", color);
+            assert_eq!(colors_from_ron(&ron).foreground, *expected, "{}", color);
+        }
+    }
+
+    #[test]
+    fn bad_colors_are_rejected() {
+        for color in [
+            "\"#ff88\"",
+            "\"#gg0000\"",
+            "\"purple\"",
+            "256",
+            "Some(Purple)",
+        ]
+        .iter()
+        {
+            let ron = format!("(foreground: {}, background: None)", color);
+            assert!(from_ron::<Colors>(ron.as_bytes()).is_err(), "{}", color);
+        }
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Labelled {
+        label: Option<String>,
+        count: Option<u32>,
+        colors: Colors,
+    }
+
+    #[test]
+    fn other_options_are_left_alone() {
+        let ron = "(label: Some(\"Some(1)\"), count: Some(3), colors: (foreground: Some(White), \
+                   background: \"#000\"))";
+        let labelled: Labelled = from_ron(ron.as_bytes()).unwrap();
+        assert_eq!(
+            labelled,
+            Labelled {
+                label: Some("Some(1)".to_string()),
+                count: Some(3),
+                colors: Colors::new(Color::White, Color::Rgb { r: 0, g: 0, b: 0 }),
+            }
+        );
+    }
+
+    #[test]
+    fn colors_round_trip() {
+        let colors = [
+            Colors::new(Color::DarkRed, Color::Rgb { r: 1, g: 2, b: 3 }),
+            Colors::new(Color::AnsiValue(42), Color::Reset),
+            Colors::default(),
+        ];
+        for colors in colors.iter() {
+            let ron = ron::ser::to_string(colors).unwrap();
+            assert_eq!(colors_from_ron(&ron), *colors, "{}", ron);
+        }
+    }
+
+    #[test]
+    fn attributes_round_trip() {
+        let mut attributes = crossterm::style::Attributes::from(crossterm::style::Attribute::Bold);
+        attributes.set(crossterm::style::Attribute::Underlined);
+        let style = Style::with_attribs(attributes);
+        let ron = ron::ser::to_string(&style).unwrap();
+        assert!(ron.contains("\"Bold\""));
+        let read: Style = ron::de::from_str(&ron).unwrap();
+        assert!(read == style);
+    }
+
+    #[test]
+    fn style_overrides_go_by_layer_then_z() {
+        let dim = Style::with_attrib(crossterm::style::Attribute::Dim);
+        let style_override = StyleOverride {
+            below: (4, 10),
+            style: Some(dim),
+        };
+        assert!(style_override.style_at(4, 9).is_some());
+        assert!(style_override.style_at(3, i32::MAX).is_some());
+        assert!(style_override.style_at(4, 10).is_none());
+        assert!(style_override.style_at(5, i32::MIN).is_none());
+        assert!(StyleOverride::default().style_at(0, -1).is_none());
+    }
+
+    #[test]
+    fn old_stylemaps_still_load() {
+        let stylemap = stylemap_from_ron(
+            r#"(
+                style: (attributes: [], colors: (foreground: None, background: None)),
+                map: [[(attributes: ["Bold"], colors: (foreground: Some(Red), background: None))]],
+            )"#,
+        );
+        assert!(stylemap.palette.is_none());
+        assert_eq!(stylemap.map.len(), 1);
+        let style = stylemap.style_for(0, 0);
+        assert!(style.attributes.has(crossterm::style::Attribute::Bold));
+        assert_eq!(style.colors.foreground, Some(Color::Red));
+    }
+
+    fn cell(sprite: &Sprite, x: usize, y: usize) -> &str {
+        sprite.grapheme(&sprite.graphemes()[y][x])
+    }
+
+    // The graphemes of an edited sprite should be the same as a sprite made from its data
+    fn assert_in_step(sprite: &Sprite) {
+        let fresh = Sprite::new(sprite.data());
+        assert_eq!(sprite.graphemes(), fresh.graphemes(), "{:?}", sprite.data());
+        assert_eq!(sprite.width(), fresh.width(), "{:?}", sprite.data());
+    }
+
+    #[test]
+    fn edits_keep_graphemes_in_step_with_the_data() {
+        let mut sprite = Sprite::new("h\u{e9}llo\n\u{65e5}\u{672c}\n\u{1f642}x");
+
+        sprite.set_cell(1, 0, "\u{20ac}");
+        assert_in_step(&sprite);
+        assert_eq!(cell(&sprite, 1, 0), "\u{20ac}");
+
+        sprite.insert_line(1, "e\u{301}\u{303}ab");
+        assert_in_step(&sprite);
+        assert_eq!(sprite.height(), 4);
+        assert_eq!(cell(&sprite, 0, 1), "e\u{301}\u{303}");
+        assert_eq!(cell(&sprite, 1, 2), "\u{672c}");
+
+        sprite.delete_line(0);
+        assert_in_step(&sprite);
+        assert_eq!(cell(&sprite, 1, 0), "a");
+        assert_eq!(cell(&sprite, 0, 2), "\u{1f642}");
+
+        sprite.insert_line(5, "z");
+        assert_in_step(&sprite);
+        assert_eq!(sprite.height(), 6);
+        assert!(sprite.graphemes()[4].is_empty());
+
+        sprite.blit(&Sprite::new("\u{65e5}x\n\u{1f642}"), 1, 1);
+        assert_in_step(&sprite);
+        assert_eq!(cell(&sprite, 1, 1), "\u{65e5}");
+        assert_eq!(cell(&sprite, 2, 1), "x");
+        assert_eq!(cell(&sprite, 1, 2), "\u{1f642}");
+        assert_eq!(cell(&sprite, 0, 2), "\u{1f642}");
+
+        sprite.set_cell(6, 4, "\u{8a9e}");
+        assert_in_step(&sprite);
+        assert_eq!(sprite.width(), 7);
+        assert_eq!(cell(&sprite, 5, 4), " ");
+
+        sprite.delete_line(5);
+        sprite.delete_line(0);
+        assert_in_step(&sprite);
+        assert_eq!(sprite.height(), 4);
+    }
+
+    #[test]
+    fn only_cells_changed_since_a_revision_are_dirty() {
+        let rect = |x, y, width, height| ScreenRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        let mut sprite = Sprite::new("abc\ndef");
+        let drawn = sprite.revision();
+        assert_eq!(sprite.dirty_since(drawn), Some(ScreenRect::default()));
+
+        sprite.set_cell(1, 1, "x");
+        assert_eq!(sprite.dirty_since(drawn), Some(rect(1, 1, 1, 1)));
+        let redrawn = sprite.revision();
+        sprite.set_cell(0, 0, "y");
+        sprite.set_cell(0, 0, "y");
+        assert_eq!(sprite.dirty_since(redrawn), Some(rect(0, 0, 1, 1)));
+        assert_eq!(sprite.dirty_since(drawn), Some(rect(0, 0, 2, 2)));
+
+        // Old edits are merged rather than lost
+        for i in 0..SPRITE_EDITS * 2 {
+            sprite.set_cell(2 + i % 2, 0, &i.to_string());
+        }
+        assert_eq!(sprite.dirty_since(drawn), Some(rect(0, 0, 4, 2)));
+        assert_eq!(sprite.dirty_since(redrawn), Some(rect(0, 0, 4, 2)));
+
+        // Another sprite put behind the same handle is drawn whole
+        let other = Sprite::new("abc\ndef");
+        assert_eq!(other.dirty_since(sprite.revision()), None);
+        assert_eq!(other.dirty_since(drawn), None);
+
+        sprite.update("new");
+        assert_eq!(sprite.dirty_since(redrawn), None);
+        assert_eq!(
+            sprite.dirty_since(sprite.revision()),
+            Some(ScreenRect::default())
+        );
+    }
+
+    #[test]
+    fn rectangles_are_changed_in_one_edit() {
+        let rect = |x, y, width, height| ScreenRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        let mut sprite = Sprite::new("abcd\nefgh\nij");
+        let drawn = sprite.revision();
+        sprite.fill_rect(1, 0, 2, 3, "\u{e9}");
+        assert_in_step(&sprite);
+        assert_eq!(
+            sprite.data(),
+            "a\u{e9}\u{e9}d\ne\u{e9}\u{e9}h\ni\u{e9}\u{e9}"
+        );
+        assert_eq!(sprite.edits.len(), 1);
+        assert_eq!(sprite.dirty_since(drawn), Some(rect(1, 0, 2, 3)));
+
+        // Filling cells with what's already in them changes nothing
+        let filled = sprite.revision();
+        sprite.fill_rect(1, 0, 2, 3, "\u{e9}");
+        assert_eq!(sprite.revision(), filled);
+
+        // Only the cells from the first to the last that changed in each line are dirty
+        sprite.fill_rect(0, 0, 4, 2, "\u{e9}");
+        assert_in_step(&sprite);
+        assert_eq!(sprite.dirty_since(filled), Some(rect(0, 0, 4, 2)));
+
+        // Lines are padded out to reach the blitted cells
+        let blitted = sprite.revision();
+        sprite.blit(&Sprite::new("\u{65e5}\n\n\u{1f642}x"), 4, 2);
+        assert_in_step(&sprite);
+        assert_eq!(sprite.height(), 5);
+        assert_eq!(cell(&sprite, 3, 2), " ");
+        assert_eq!(cell(&sprite, 5, 4), "x");
+        assert_eq!(sprite.edits.len(), 3);
+        assert_eq!(sprite.dirty_since(blitted), Some(rect(0, 0, 6, 5)));
+    }
+
+    #[test]
+    fn alpha_masks_are_read_from_text() {
+        let mask = AlphaMask::parse("#.-x\n\n .");
+        assert_eq!(
+            mask.0,
+            vec![
+                vec![
+                    Alpha::Opaque,
+                    Alpha::Transparent,
+                    Alpha::TransparentBackground,
+                    Alpha::Auto
+                ],
+                vec![],
+                vec![Alpha::Auto, Alpha::Transparent],
+            ]
+        );
+        assert_eq!(mask.alpha_at(1, 2), Alpha::Transparent);
+        // Cells past the end of a row or the mask are left to the sprite
+        assert_eq!(mask.alpha_at(0, 1), Alpha::Auto);
+        assert_eq!(mask.alpha_at(9, 0), Alpha::Auto);
+        assert_eq!(mask.alpha_at(0, 9), Alpha::Auto);
+        assert!(!mask.is_empty());
+        assert!(AlphaMask::parse("x \n?").is_empty());
+
+        let key = [('o', Alpha::Opaque), ('#', Alpha::Transparent)];
+        assert_eq!(
+            AlphaMask::from_key("o#.", &key).0,
+            vec![vec![Alpha::Opaque, Alpha::Transparent, Alpha::Auto]]
+        );
+    }
+
+    #[test]
+    fn alpha_masks_round_trip() {
+        let stylemap = stylemap_from_ron(
+            r##"(
+                style: (attributes: [], colors: (foreground: None, background: None)),
+                mask: ["#.-", "x#"],
+            )"##,
+        );
+        assert_eq!(stylemap.alpha_at(2, 0), Alpha::TransparentBackground);
+        assert_eq!(stylemap.alpha_at(1, 1), Alpha::Opaque);
+
+        // Characters that aren't in the key are written as spaces
+        let rows: Vec<String> = stylemap.mask.clone().into();
+        assert_eq!(rows, vec!["#.-".to_string(), " #".to_string()]);
+        let text = ron::ser::to_string(&stylemap).unwrap();
+        assert!(stylemap_from_ron(&text) == stylemap);
+
+        // Stylemaps without a mask don't write one
+        let text = ron::ser::to_string(&StyleMap::default()).unwrap();
+        assert!(!text.contains("mask"));
+    }
+
+    #[test]
+    fn colors_have_their_xterm_values() {
+        assert_eq!(to_rgb(Color::Rgb { r: 1, g: 2, b: 3 }), Some((1, 2, 3)));
+        assert_eq!(to_rgb(Color::DarkRed), Some((128, 0, 0)));
+        assert_eq!(to_rgb(Color::White), Some((255, 255, 255)));
+        assert_eq!(to_rgb(Color::AnsiValue(9)), to_rgb(Color::Red));
+        // The 6x6x6 cube, then the greys
+        assert_eq!(to_rgb(Color::AnsiValue(16)), Some((0, 0, 0)));
+        assert_eq!(
+            to_rgb(Color::AnsiValue(16 + 36 + 18 + 5)),
+            Some((95, 175, 255))
+        );
+        assert_eq!(to_rgb(Color::AnsiValue(231)), Some((255, 255, 255)));
+        assert_eq!(to_rgb(Color::AnsiValue(232)), Some((8, 8, 8)));
+        assert_eq!(to_rgb(Color::AnsiValue(255)), Some((238, 238, 238)));
+        assert_eq!(to_rgb(Color::Reset), None);
+    }
+
+    #[test]
+    fn blends_mix_with_the_color_underneath() {
+        let rgb = |r, g, b| Some(Color::Rgb { r, g, b });
+        let top = rgb(200, 100, 0);
+        let bottom = rgb(100, 200, 50);
+
+        assert_eq!(Blend::Replace.mix(top, bottom), top);
+        assert_eq!(Blend::Alpha(255).mix(top, bottom), top);
+        assert_eq!(Blend::Alpha(0).mix(top, bottom), bottom);
+        assert_eq!(Blend::Alpha(128).mix(top, bottom), rgb(150, 149, 24));
+        assert_eq!(Blend::Multiply.mix(top, bottom), rgb(78, 78, 0));
+        assert_eq!(Blend::Multiply.mix(Some(Color::White), bottom), bottom);
+        // Tinting keeps how bright the color underneath is
+        assert_eq!(Blend::Tint.mix(top, rgb(255, 255, 255)), top);
+        assert_eq!(Blend::Tint.mix(top, rgb(0, 0, 0)), rgb(0, 0, 0));
+
+        // Unset colors are whatever's underneath, and colors without an RGB value aren't mixed
+        assert_eq!(Blend::Multiply.mix(None, bottom), bottom);
+        assert_eq!(Blend::Alpha(128).mix(top, None), top);
+        assert_eq!(
+            Blend::Alpha(128).mix(Some(Color::Reset), bottom),
+            Some(Color::Reset)
+        );
+        assert_eq!(Blend::Alpha(128).mix(top, Some(Color::Reset)), top);
+
+        let mixed = Blend::Multiply.apply(
+            Colors::fg(Color::Grey),
+            Colors::new(Color::White, Color::Blue),
+        );
+        assert_eq!(
+            mixed.foreground,
+            Some(Color::Rgb {
+                r: 192,
+                g: 192,
+                b: 192
+            })
+        );
+        assert_eq!(mixed.background, Some(Color::Blue));
+    }
+}
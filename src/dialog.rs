@@ -0,0 +1,373 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy::window::WindowResized;
+use crossterm::event::{KeyCode, MouseButton, MouseEvent};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::camera::ScreenSpace;
+use crate::components::{
+    self, DrawArea, Position, Sprite, Style, StyleGrid, StyleMap, StyleOverride,
+};
+use crate::focus::{Focus, Focusable, FocusedKeyEvent, Modal};
+use crate::layers::{Layers, RenderLayers};
+use crate::panel::{Border, Panel};
+use crate::text::{Text, TextAlign};
+use crate::viewport::ActiveViewports;
+use crate::CrosstermWindow;
+
+/// The depth the first dialog is drawn at. Each dialog opened while others are still open is drawn
+/// one higher, over the ones opened before it. Nothing else should be drawn this high, so dialogs are
+/// always on top.
+pub const DIALOG_Z: i32 = 1 << 30;
+
+/// A modal dialog which asks the user to pick one of some choices. It's drawn as a bordered box in
+/// the middle of the window, on top of everything else, and takes all keyboard and mouse input until
+/// it's closed.
+///
+/// The left and right arrow keys and tab move between the choices, enter or clicking on a choice
+/// picks it, typing the first letter of a choice picks it, and escape closes the dialog without
+/// picking anything. Either way a DialogChoice event is sent and the dialog's entity is despawned.
+///
+/// Add it to an entity with a SpriteBundle, and the dialog positions itself and generates its own
+/// sprite and stylemap.
+#[derive(Clone, PartialEq)]
+pub struct Dialog {
+    pub title: Option<String>,
+    pub message: String,
+    pub choices: Vec<String>,
+    /// The index of the choice that enter picks
+    pub selected: usize,
+    pub border: Border,
+    pub style: Style,
+    pub selected_style: Style,
+    /// A style drawn on top of everything beneath the dialog while it's open, like
+    /// `Style::with_attrib(Attribute::Dim)` to dim it. When it's None, what's beneath is left alone.
+    pub backdrop: Option<Style>,
+    // Where the choices were drawn, as the row and the columns of each choice, relative to the dialog
+    choice_row: usize,
+    choice_columns: Vec<(usize, usize)>,
+    // The depth it's drawn at, which is higher for dialogs opened later
+    z: i32,
+}
+
+impl Dialog {
+    pub fn new<T: std::string::ToString>(message: T, choices: Vec<String>) -> Dialog {
+        Dialog {
+            title: None,
+            message: message.to_string(),
+            choices,
+            selected: 0,
+            border: Border::default(),
+            style: Style::default(),
+            selected_style: Style::with_attrib(crossterm::style::Attribute::Reverse),
+            backdrop: Some(Style::with_attrib(crossterm::style::Attribute::Dim)),
+            choice_row: 0,
+            choice_columns: Vec::new(),
+            z: DIALOG_Z,
+        }
+    }
+
+    /// A dialog asking a yes or no question. Yes is choice 0, and no is choice 1.
+    pub fn confirm<T: std::string::ToString>(message: T) -> Dialog {
+        Dialog::new(message, vec!["Yes".to_string(), "No".to_string()])
+    }
+
+    pub fn with_title<T: std::string::ToString>(mut self, title: T) -> Dialog {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    /// Generates the dialog's sprite and stylemap, and works out where its choices are
+    fn generate(
+        &self,
+        sprites: &Assets<Sprite>,
+        window: &CrosstermWindow,
+    ) -> (String, StyleMap, usize, Vec<(usize, usize)>) {
+        // Leave room for the border and a space of padding on either side
+        let max_inner_width = (window.width() as usize * 3 / 4).saturating_sub(4).max(1);
+        let message = Text {
+            max_width: Some(max_inner_width),
+            max_height: Some((window.height() as usize).saturating_sub(6).max(1)),
+            ..Text::new(&self.message)
+        }
+        .layout(&Position::default(), window);
+        let lines: Vec<&str> = message.lines().collect();
+
+        let labels: Vec<String> = self
+            .choices
+            .iter()
+            .map(|choice| format!("[ {} ]", choice))
+            .collect();
+        let choices_width = labels.iter().map(|label| label.width() + 2).sum::<usize>();
+        let title_width = self.title.as_ref().map_or(0, |title| title.width() + 2);
+        let inner_width = lines
+            .iter()
+            .map(|line| line.width())
+            .chain(vec![choices_width.saturating_sub(2), title_width])
+            .max()
+            .unwrap_or(0)
+            .min(max_inner_width);
+
+        let width = inner_width + 4;
+        let height = lines.len() + if labels.is_empty() { 4 } else { 5 };
+        let panel = Panel {
+            title: self.title.clone(),
+            title_align: TextAlign::Center,
+            ..Panel::with_border(width, height, self.border.clone())
+        };
+        let (frame, _) = panel.generate(sprites, &HashMap::default());
+        let frame: Vec<Vec<&str>> = frame
+            .lines()
+            .map(|row| row.graphemes(true).collect())
+            .collect();
+
+        // Fills a row of the frame with some content, centered between the borders
+        let centered = |row: usize, content: &str| {
+            let space = inner_width.saturating_sub(content.width());
+            format!(
+                "{} {}{}{} {}",
+                frame[row][0],
+                " ".repeat(space / 2),
+                content,
+                " ".repeat(space - space / 2),
+                frame[row][width - 1]
+            )
+        };
+
+        let mut rows = vec![frame[0].concat(), centered(1, "")];
+        for (i, line) in lines.iter().enumerate() {
+            rows.push(centered(i + 2, line));
+        }
+        rows.push(centered(rows.len(), ""));
+        let mut cells = vec![vec![]; rows.len()];
+        let choice_row = rows.len();
+        let mut choice_columns = Vec::new();
+        if !labels.is_empty() {
+            let choices = labels.join("  ");
+            rows.push(centered(choice_row, &choices));
+
+            // Style the selected choice, and remember the columns of all of them
+            let start = 2 + inner_width.saturating_sub(choices.width()) / 2;
+            let mut column = start;
+            let mut choice_cells = vec![None; start];
+            for (i, label) in labels.iter().enumerate() {
+                if i > 0 {
+                    choice_cells.extend(vec![None; 2]);
+                    column += 2;
+                }
+                let style = if i == self.selected {
+                    Some(self.selected_style)
+                } else {
+                    None
+                };
+                choice_cells.extend(vec![style; label.graphemes(true).count()]);
+                choice_columns.push((column, column + label.width()));
+                column += label.width();
+            }
+            cells.push(choice_cells);
+        }
+        rows.push(frame[height - 1].concat());
+
+        let stylemap =
+            StyleMap::with_grid(self.style, StyleGrid::from_cells_or_full(cells, self.style));
+        (rows.join("\n"), stylemap, choice_row, choice_columns)
+    }
+}
+
+/// Sent when a dialog is closed. The choice is the index of the choice that was picked, or None if
+/// the dialog was closed with escape.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DialogChoice {
+    pub entity: Entity,
+    pub choice: Option<usize>,
+}
+
+pub(crate) fn dialog_input(
+    commands: &mut Commands,
+    mut key_reader: Local<EventReader<FocusedKeyEvent>>,
+    key_events: Res<Events<FocusedKeyEvent>>,
+    mut mouse_reader: Local<EventReader<MouseEvent>>,
+    mouse_events: Res<Events<MouseEvent>>,
+    focus: Res<Focus>,
+    viewports: Res<ActiveViewports>,
+    sprites: Res<Assets<Sprite>>,
+    mut choice_events: ResMut<Events<DialogChoice>>,
+    mut dialogs: Query<(
+        Entity,
+        &mut Dialog,
+        &Position,
+        &Handle<Sprite>,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
+) {
+    // Dialogs are closed once everything has been read, so they're only closed once
+    let mut closed: Vec<(Entity, Option<usize>)> = Vec::new();
+
+    for event in key_reader.iter(&key_events) {
+        let (entity, mut dialog, ..) = match dialogs.get_mut(event.entity) {
+            Ok(dialog) => dialog,
+            Err(_) => continue,
+        };
+        let count = dialog.choices.len();
+        match event.key.code {
+            KeyCode::Esc => closed.push((entity, None)),
+            KeyCode::Enter | KeyCode::Char(' ') if count == 0 => closed.push((entity, None)),
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                closed.push((entity, Some(dialog.selected.min(count - 1))))
+            }
+            KeyCode::Left | KeyCode::Up | KeyCode::BackTab if count > 0 => {
+                dialog.selected = (dialog.selected + count - 1) % count
+            }
+            KeyCode::Right | KeyCode::Down | KeyCode::Tab if count > 0 => {
+                dialog.selected = (dialog.selected + 1) % count
+            }
+            KeyCode::Char(c) => {
+                let c = c.to_lowercase().next();
+                let picked = dialog
+                    .choices
+                    .iter()
+                    .position(|choice| choice.to_lowercase().chars().next() == c);
+                if let Some(index) = picked {
+                    closed.push((entity, Some(index)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for event in mouse_reader.iter(&mouse_events) {
+        if let MouseEvent::Down(MouseButton::Left, column, row, _) = *event {
+            for (entity, dialog, position, sprite, area, layers) in dialogs.iter_mut() {
+                // Only the topmost dialog can be clicked, like it's the only one that gets keys
+                if focus.is_blocked(entity) {
+                    continue;
+                }
+                let size = match sprites.get(sprite) {
+                    Some(sprite) => (sprite.width(), sprite.height()),
+                    None => continue,
+                };
+                let (column, row) =
+                    match viewports.cell_at(position, size, area, layers, (column, row)) {
+                        Some(cell) => cell,
+                        None => continue,
+                    };
+                if row != dialog.choice_row {
+                    continue;
+                }
+                let clicked = dialog
+                    .choice_columns
+                    .iter()
+                    .position(|(start, end)| column >= *start && column < *end);
+                if let Some(index) = clicked {
+                    closed.push((entity, Some(index)));
+                }
+            }
+        }
+    }
+
+    for (i, (entity, choice)) in closed.iter().enumerate() {
+        if closed[..i].iter().any(|(other, _)| other == entity) {
+            continue;
+        }
+        choice_events.send(DialogChoice {
+            entity: *entity,
+            choice: *choice,
+        });
+        commands.despawn(*entity);
+    }
+}
+
+pub(crate) fn update_dialogs(
+    commands: &mut Commands,
+    mut opened: Local<i32>,
+    mut resize_reader: Local<EventReader<WindowResized>>,
+    resize_events: Res<Events<WindowResized>>,
+    window: Res<CrosstermWindow>,
+    layers: Res<Layers>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut style_override: ResMut<StyleOverride>,
+    added: Query<Entity, Added<Dialog>>,
+    changed: Query<Entity, Changed<Dialog>>,
+    mut dialogs: Query<(
+        Entity,
+        &mut Dialog,
+        &mut Position,
+        &mut Handle<Sprite>,
+        &mut Handle<StyleMap>,
+        Option<&RenderLayers>,
+    )>,
+) {
+    // Dialogs take focus, and keep it until they're closed. They stay in the middle of the window
+    // wherever the camera is, on top of every other layer. Each one is drawn over the dialogs that
+    // were already open, and counting starts again once they've all been closed
+    let already_open = dialogs
+        .iter_mut()
+        .any(|(entity, ..)| added.get(entity).is_err());
+    if !already_open {
+        *opened = 0;
+    }
+    for entity in added.iter() {
+        commands.insert_one(entity, Focusable::with_arrow_keys(0));
+        commands.insert_one(entity, Modal);
+        commands.insert_one(entity, ScreenSpace);
+        commands.insert_one(entity, RenderLayers::overlay());
+        if let Ok((_, mut dialog, ..)) = dialogs.get_mut(entity) {
+            dialog.z = DIALOG_Z.saturating_add(*opened);
+            *opened += 1;
+        }
+    }
+
+    let resized = resize_reader.iter(&resize_events).next().is_some();
+    // The backdrop of the topmost dialog which has one, and the depth it's beneath
+    let mut backdrop: Option<(Style, (usize, i32))> = None;
+    for (entity, mut dialog, mut position, mut sprite, mut stylemap, render_layers) in
+        dialogs.iter_mut()
+    {
+        // The dialog's layers are only inserted once commands run
+        let rank = layers.rank(Some(render_layers.unwrap_or(&RenderLayers::overlay())));
+        if let Some(style) = dialog.backdrop {
+            if backdrop.map_or(true, |(_, below)| below < (rank, dialog.z)) {
+                backdrop = Some((style, (rank, dialog.z)));
+            }
+        }
+        if !resized && changed.get(entity).is_err() {
+            continue;
+        }
+
+        let (generated_sprite, generated_stylemap, choice_row, choice_columns) =
+            dialog.generate(&sprites, &window);
+        let width = generated_sprite
+            .lines()
+            .map(|row| row.width())
+            .max()
+            .unwrap_or(0) as i32;
+        let height = generated_sprite.lines().count() as i32;
+        let (x, y) = (
+            (window.width() as i32 - width) / 2,
+            (window.height() as i32 - height) / 2,
+        );
+        if position.x != x || position.y != y || position.z != dialog.z {
+            *position = Position::new(x, y, dialog.z);
+        }
+        if dialog.choice_row != choice_row || dialog.choice_columns != choice_columns {
+            dialog.choice_row = choice_row;
+            dialog.choice_columns = choice_columns;
+        }
+        components::set_sprite(&mut sprites, &mut sprite, generated_sprite);
+        components::set_stylemap(&mut stylemaps, &mut stylemap, generated_stylemap);
+    }
+
+    let style_below = match backdrop {
+        Some((style, below)) => StyleOverride {
+            below,
+            style: Some(style),
+        },
+        None => StyleOverride::default(),
+    };
+    if *style_override != style_below {
+        *style_override = style_below;
+    }
+}
@@ -0,0 +1,469 @@
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::components::{self, Sprite};
+
+// Layout bits from the FIGlet font header
+const SMUSH_EQUAL: u32 = 1;
+const SMUSH_LOWLINE: u32 = 2;
+const SMUSH_HIERARCHY: u32 = 4;
+const SMUSH_PAIR: u32 = 8;
+const SMUSH_BIGX: u32 = 16;
+const SMUSH_HARDBLANK: u32 = 32;
+const SMUSH_RULES: u32 = 63;
+const KERNING: u32 = 64;
+const SMUSHING: u32 = 128;
+
+// The characters every font has to define, in the order they appear in the file
+const REQUIRED_CHARS: &[u32] = &[196, 214, 220, 228, 246, 252, 223];
+
+/// How the characters of a FIGlet font are fitted together
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FigletLayout {
+    /// Use whatever layout the font asks for
+    FontDefault,
+    /// Every character keeps its full width
+    FullWidth,
+    /// Characters are moved together until they touch
+    Kerning,
+    /// Characters are moved together until they overlap by a column, which is merged using the
+    /// font's smushing rules. Fonts without rules use universal smushing.
+    Smushing,
+}
+
+impl Default for FigletLayout {
+    fn default() -> Self {
+        FigletLayout::FontDefault
+    }
+}
+
+/// A FIGlet font, loaded from a .flf file
+#[derive(Debug, TypeUuid)]
+#[uuid = "02a9bf78-92d7-4fa3-bcbd-505d6d4931b5"]
+pub struct FigletFont {
+    hardblank: char,
+    height: usize,
+    layout: u32,
+    characters: HashMap<char, Vec<Vec<char>>>,
+}
+
+impl FigletFont {
+    pub fn parse(source: &str) -> Result<FigletFont, anyhow::Error> {
+        let mut lines = source.lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("font is empty"))?;
+        if !header.starts_with("flf2a") {
+            anyhow::bail!("not a FIGlet font");
+        }
+        let hardblank = header
+            .chars()
+            .nth(5)
+            .ok_or_else(|| anyhow::anyhow!("font header has no hardblank"))?;
+
+        let params = header
+            .split_whitespace()
+            .skip(1)
+            .map(|param| param.parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()?;
+        if params.len() < 5 {
+            anyhow::bail!("font header is missing parameters");
+        }
+        let height = params[0].max(1) as usize;
+        let old_layout = params[3];
+        let comment_lines = params[4].max(0) as usize;
+        let layout = match params.get(6) {
+            Some(full_layout) => *full_layout as u32,
+            None if old_layout < 0 => 0,
+            None if old_layout == 0 => KERNING,
+            None => (old_layout as u32 & SMUSH_RULES) | SMUSHING,
+        };
+
+        let mut lines = lines.skip(comment_lines);
+        let mut characters = HashMap::default();
+        for code in (32..127).chain(REQUIRED_CHARS.iter().copied()) {
+            match read_glyph(&mut lines, height) {
+                Some(glyph) => {
+                    if let Some(c) = std::char::from_u32(code) {
+                        characters.insert(c, glyph);
+                    }
+                }
+                // Some older fonts stop after the ASCII characters
+                None => break,
+            }
+        }
+
+        // Anything else is preceded by a line starting with its character code
+        while let Some(tag) = lines.next() {
+            let code = match tag.split_whitespace().next() {
+                Some(code) => code,
+                None => continue,
+            };
+            let code =
+                if let Some(hex) = code.strip_prefix("0x").or_else(|| code.strip_prefix("0X")) {
+                    i64::from_str_radix(hex, 16)
+                } else if code.len() > 1 && code.starts_with('0') {
+                    i64::from_str_radix(&code[1..], 8)
+                } else {
+                    code.parse()
+                }?;
+
+            let glyph = match read_glyph(&mut lines, height) {
+                Some(glyph) => glyph,
+                None => break,
+            };
+            // Negative codes are for translation tables, which are not supported
+            if let Some(c) = std::char::from_u32(code as u32).filter(|_| code >= 0) {
+                characters.insert(c, glyph);
+            }
+        }
+
+        Ok(FigletFont {
+            hardblank,
+            height,
+            layout,
+            characters,
+        })
+    }
+
+    /// How many rows tall each character is
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Renders text with this font. Every line of the text becomes a block of rows in the result.
+    pub fn render(&self, text: &str, layout: FigletLayout) -> String {
+        let mode = match layout {
+            FigletLayout::FontDefault => self.layout,
+            FigletLayout::FullWidth => 0,
+            FigletLayout::Kerning => KERNING,
+            FigletLayout::Smushing => SMUSHING | (self.layout & SMUSH_RULES),
+        };
+
+        let mut result = String::new();
+        for line in text.lines() {
+            for row in self.render_line(line, mode) {
+                result.extend(
+                    row.into_iter()
+                        .map(|c| if c == self.hardblank { ' ' } else { c }),
+                );
+                result.push('\n');
+            }
+        }
+        result
+    }
+
+    fn render_line(&self, line: &str, mode: u32) -> Vec<Vec<char>> {
+        let mut output = vec![Vec::new(); self.height];
+        let mut previous_width = 0;
+
+        for c in line.chars() {
+            // Fonts can define character 0 as the glyph for anything missing
+            let glyph = match self
+                .characters
+                .get(&c)
+                .or_else(|| self.characters.get(&'\0'))
+            {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            let width = glyph.first().map_or(0, Vec::len);
+            let amount = self.overlap(&output, glyph, mode, previous_width, width);
+
+            for (row, glyph_row) in output.iter_mut().zip(glyph.iter()) {
+                let len = row.len();
+                for (k, right) in glyph_row.iter().take(amount).enumerate() {
+                    if len + k < amount {
+                        continue;
+                    }
+                    let idx = len + k - amount;
+                    row[idx] = self
+                        .smush(row[idx], *right, mode, previous_width, width)
+                        .unwrap_or(*right);
+                }
+                row.extend(glyph_row.iter().skip(amount));
+            }
+            previous_width = width;
+        }
+
+        output
+    }
+
+    /// Figures out how many columns the next glyph can overlap what has been rendered so far
+    fn overlap(
+        &self,
+        output: &[Vec<char>],
+        glyph: &[Vec<char>],
+        mode: u32,
+        previous_width: usize,
+        width: usize,
+    ) -> usize {
+        if mode & (SMUSHING | KERNING) == 0 {
+            return 0;
+        }
+
+        let mut max_overlap = width;
+        for (row, glyph_row) in output.iter().zip(glyph.iter()) {
+            let glyph_bound = glyph_row
+                .iter()
+                .position(|c| *c != ' ')
+                .unwrap_or(glyph_row.len());
+            let amount = match row.iter().rposition(|c| *c != ' ') {
+                Some(row_bound) => {
+                    let mut amount = glyph_bound + row.len() - 1 - row_bound;
+                    if let Some(right) = glyph_row.get(glyph_bound) {
+                        if self
+                            .smush(row[row_bound], *right, mode, previous_width, width)
+                            .is_some()
+                        {
+                            amount += 1;
+                        }
+                    }
+                    amount
+                }
+                None => glyph_bound + row.len(),
+            };
+            max_overlap = std::cmp::min(max_overlap, amount);
+        }
+        max_overlap
+    }
+
+    /// Merges two overlapping characters into one, if the layout allows it
+    fn smush(
+        &self,
+        left: char,
+        right: char,
+        mode: u32,
+        previous_width: usize,
+        width: usize,
+    ) -> Option<char> {
+        if left == ' ' {
+            return Some(right);
+        }
+        if right == ' ' {
+            return Some(left);
+        }
+        if previous_width < 2 || width < 2 || mode & SMUSHING == 0 {
+            return None;
+        }
+
+        let hardblank = self.hardblank;
+        if mode & SMUSH_RULES == 0 {
+            // Universal smushing, the right character wins unless it's a hardblank
+            return Some(if right == hardblank { left } else { right });
+        }
+
+        if mode & SMUSH_HARDBLANK != 0 && left == hardblank && right == hardblank {
+            return Some(left);
+        }
+        if left == hardblank || right == hardblank {
+            return None;
+        }
+        if mode & SMUSH_EQUAL != 0 && left == right {
+            return Some(left);
+        }
+        if mode & SMUSH_LOWLINE != 0 {
+            if left == '_' && "|/\\[]{}()<>".contains(right) {
+                return Some(right);
+            }
+            if right == '_' && "|/\\[]{}()<>".contains(left) {
+                return Some(left);
+            }
+        }
+        if mode & SMUSH_HIERARCHY != 0 {
+            let classes = ["|", "/\\", "[]", "{}", "()", "<>"];
+            let class_of = |c: char| classes.iter().position(|class| class.contains(c));
+            if let (Some(left_class), Some(right_class)) = (class_of(left), class_of(right)) {
+                if left_class < right_class {
+                    return Some(right);
+                }
+                if right_class < left_class {
+                    return Some(left);
+                }
+            }
+        }
+        if mode & SMUSH_PAIR != 0 {
+            match (left, right) {
+                ('[', ']') | (']', '[') | ('{', '}') | ('}', '{') | ('(', ')') | (')', '(') => {
+                    return Some('|')
+                }
+                _ => {}
+            }
+        }
+        if mode & SMUSH_BIGX != 0 {
+            match (left, right) {
+                ('/', '\\') => return Some('|'),
+                ('\\', '/') => return Some('Y'),
+                ('>', '<') => return Some('X'),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+fn read_glyph<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+    height: usize,
+) -> Option<Vec<Vec<char>>> {
+    let mut rows = Vec::with_capacity(height);
+    for _ in 0..height {
+        let mut row: Vec<char> = lines.next()?.trim_end_matches('\r').chars().collect();
+        // Each row ends with one or more end marks, which are whatever its last character is
+        if let Some(end_mark) = row.last().copied() {
+            while row.last() == Some(&end_mark) {
+                row.pop();
+            }
+        }
+        rows.push(row);
+    }
+
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    for row in rows.iter_mut() {
+        row.resize(width, ' ');
+    }
+    Some(rows)
+}
+
+/// Text drawn with a FIGlet font. The entity's sprite is regenerated whenever the text changes.
+/// Each entity gets a sprite of its own when the component is added, so the sprite handle it's
+/// spawned with is never written to.
+#[derive(Clone, Debug, Default)]
+pub struct BigText {
+    pub text: String,
+    pub font: Handle<FigletFont>,
+    pub layout: FigletLayout,
+}
+
+impl BigText {
+    pub fn new<T: std::string::ToString>(text: T, font: Handle<FigletFont>) -> BigText {
+        BigText {
+            text: text.to_string(),
+            font,
+            layout: FigletLayout::default(),
+        }
+    }
+}
+
+pub(crate) fn update_big_text(
+    mut font_event_reader: Local<EventReader<AssetEvent<FigletFont>>>,
+    font_events: Res<Events<AssetEvent<FigletFont>>>,
+    fonts: Res<Assets<FigletFont>>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut big_texts: QuerySet<(
+        Query<(&BigText, &mut Handle<Sprite>), Changed<BigText>>,
+        Query<(&BigText, &mut Handle<Sprite>)>,
+    )>,
+) {
+    for (big_text, mut sprite) in big_texts.q0_mut().iter_mut() {
+        if let Some(font) = fonts.get(&big_text.font) {
+            components::set_sprite(
+                &mut sprites,
+                &mut sprite,
+                font.render(&big_text.text, big_text.layout),
+            );
+        }
+    }
+
+    // Fonts load asynchronously, so the text has to be rendered again once its font shows up
+    let mut changed_fonts = HashSet::default();
+    for event in font_event_reader.iter(&font_events) {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                changed_fonts.insert(handle.clone());
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+    if changed_fonts.is_empty() {
+        return;
+    }
+
+    for (big_text, mut sprite) in big_texts.q1_mut().iter_mut() {
+        if !changed_fonts.contains(&big_text.font) {
+            continue;
+        }
+        if let Some(font) = fonts.get(&big_text.font) {
+            components::set_sprite(
+                &mut sprites,
+                &mut sprite,
+                font.render(&big_text.text, big_text.layout),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A font where every character is drawn as itself on a diagonal, two columns wide
+    fn font(layout: &str) -> FigletFont {
+        let mut source = format!("flf2a$ 2 2 8 {}\nA comment line\n", layout);
+        for code in (32..127).chain(REQUIRED_CHARS.iter().copied()) {
+            let c = std::char::from_u32(code).unwrap();
+            source.push_str(&format!("{} @\n {}@@\n", c, c));
+        }
+        source.push_str("0x263A SMILE\n:)@\n()@@\n-2 ignored\n..@\n..@@\n");
+        FigletFont::parse(&source).unwrap()
+    }
+
+    #[test]
+    fn reads_fonts() {
+        let font = font("-1 1");
+        assert_eq!(font.height(), 2);
+        assert_eq!(font.characters[&'A'], vec![vec!['A', ' '], vec![' ', 'A']]);
+        assert_eq!(
+            font.characters[&'\u{263A}'],
+            vec![vec![':', ')'], vec!['(', ')']]
+        );
+        // Glyphs with negative codes are skipped
+        assert_eq!(font.characters[&'.'], vec![vec!['.', ' '], vec![' ', '.']]);
+    }
+
+    #[test]
+    fn bad_fonts_are_rejected() {
+        assert!(FigletFont::parse("").is_err());
+        assert!(FigletFont::parse("tlf2a$ 2 2 8 -1 0").is_err());
+        assert!(FigletFont::parse("flf2a$ 2 2 8").is_err());
+        assert!(FigletFont::parse("flf2a$ 2 two 8 -1 0").is_err());
+    }
+
+    #[test]
+    fn characters_are_fitted_by_the_layout() {
+        let full_width = font("-1 1");
+        assert_eq!(
+            full_width.render("AB", FigletLayout::FontDefault),
+            "A B \n A B\n"
+        );
+
+        let kerned = font("0 1");
+        assert_eq!(kerned.render("AB", FigletLayout::FontDefault), "AB \n AB\n");
+        assert_eq!(kerned.render("AB", FigletLayout::FullWidth), "A B \n A B\n");
+
+        // Universal smushing lets the right character win
+        let smushed = font("0 1 0 128");
+        assert_eq!(smushed.render("AB", FigletLayout::FontDefault), "B \n B\n");
+        assert_eq!(
+            smushed.render("AB\nC", FigletLayout::Kerning),
+            "AB \n AB\nC \n C\n"
+        );
+    }
+
+    #[test]
+    fn smushing_follows_the_rules() {
+        let font = font(&format!("0 1 0 {}", SMUSHING | SMUSH_RULES));
+        let smush = |left, right| font.smush(left, right, SMUSHING | SMUSH_RULES, 2, 2);
+        assert_eq!(smush('|', '|'), Some('|'));
+        assert_eq!(smush('_', '/'), Some('/'));
+        assert_eq!(smush('|', '/'), Some('/'));
+        assert_eq!(smush('[', ']'), Some('|'));
+        assert_eq!(smush('/', '\\'), Some('|'));
+        assert_eq!(smush('\\', '/'), Some('Y'));
+        assert_eq!(smush('>', '<'), Some('X'));
+        assert_eq!(smush('$', '$'), Some('$'));
+        assert_eq!(smush('a', 'b'), None);
+        assert_eq!(font.smush('|', '|', SMUSHING | SMUSH_RULES, 1, 2), None);
+    }
+}
@@ -0,0 +1,285 @@
+use bevy::prelude::*;
+use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent};
+
+use crate::components::{DrawArea, Position, Sprite};
+use crate::layers::{Layers, RenderLayers};
+use crate::sprite_transform::{self, TransformedSprite};
+use crate::viewport::ActiveViewports;
+
+/// Lets an entity take keyboard focus. Tab and shift-tab move focus through focusable entities in
+/// order, the arrow keys move it to the nearest entity in that direction, and clicking on an
+/// entity's sprite focuses it.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Focusable {
+    /// Where the entity comes in the tab order. Entities with the same order are tabbed through from
+    /// top to bottom, then left to right.
+    pub order: i32,
+    /// Whether the entity uses the arrow keys itself, like lists and text inputs do. If it does, the
+    /// arrow keys don't move focus away from it.
+    pub uses_arrow_keys: bool,
+}
+
+impl Focusable {
+    pub fn new(order: i32) -> Focusable {
+        Focusable {
+            order,
+            uses_arrow_keys: false,
+        }
+    }
+
+    pub fn with_arrow_keys(order: i32) -> Focusable {
+        Focusable {
+            order,
+            uses_arrow_keys: true,
+        }
+    }
+}
+
+/// Marks the entity which has keyboard focus. It's added and removed as the Focus resource changes,
+/// and shouldn't be added by hand.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Focused;
+
+/// Makes a focusable entity take all input while it exists, like a dialog. It keeps focus until
+/// it's removed, and focus then goes back to where it was. If there's more than one, the one with the
+/// highest z has focus.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Modal;
+
+/// Which entity has keyboard focus. Set `entity` to move focus somewhere else.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Focus {
+    pub entity: Option<Entity>,
+    // The entity which was focused the last time the Focused marker and events were updated
+    synced: Option<Entity>,
+    modal: Option<Entity>,
+    // What was focused before a modal entity took focus
+    before_modal: Option<Entity>,
+}
+
+impl Focus {
+    /// The modal entity which is taking all input, if there is one
+    pub fn modal(&self) -> Option<Entity> {
+        self.modal
+    }
+
+    /// Whether input to an entity is blocked, because a modal entity is taking it all
+    pub fn is_blocked(&self, entity: Entity) -> bool {
+        self.modal.map_or(false, |modal| modal != entity)
+    }
+}
+
+/// Sent when an entity gains focus
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FocusGained {
+    pub entity: Entity,
+}
+
+/// Sent when an entity loses focus
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FocusLost {
+    pub entity: Entity,
+}
+
+/// A key that was pressed while an entity was focused. Every key press is still sent as a KeyEvent,
+/// but keys used to move focus aren't sent to the focused entity.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FocusedKeyEvent {
+    pub entity: Entity,
+    pub key: KeyEvent,
+}
+
+/// Finds the nearest focusable entity in the direction of an arrow key. Distances across the
+/// direction count double, so entities which are in line are preferred.
+fn nearest_in_direction(
+    from: (i32, i32),
+    direction: KeyCode,
+    candidates: &[(Entity, i32, Option<(i32, i32)>)],
+) -> Option<Entity> {
+    candidates
+        .iter()
+        .filter_map(|(entity, _, position)| {
+            let (x, y) = (*position)?;
+            let (dx, dy) = (x - from.0, y - from.1);
+            let (along, across) = match direction {
+                KeyCode::Up => (-dy, dx),
+                KeyCode::Down => (dy, dx),
+                KeyCode::Left => (-dx, dy),
+                KeyCode::Right => (dx, dy),
+                _ => return None,
+            };
+            if along > 0 {
+                Some((along + 2 * across.abs(), *entity))
+            } else {
+                None
+            }
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, entity)| entity)
+}
+
+/// Moves focus in response to keys and clicks, and sends the rest of the keys to the focused entity
+pub(crate) fn focus_input(
+    mut key_reader: Local<EventReader<KeyEvent>>,
+    key_events: Res<Events<KeyEvent>>,
+    mut mouse_reader: Local<EventReader<MouseEvent>>,
+    mouse_events: Res<Events<MouseEvent>>,
+    sprites: Res<Assets<Sprite>>,
+    viewports: Res<ActiveViewports>,
+    layers: Res<Layers>,
+    mut focus: ResMut<Focus>,
+    mut focused_key_events: ResMut<Events<FocusedKeyEvent>>,
+    modals: Query<(Entity, &Position, Option<&RenderLayers>), (With<Modal>, With<Focusable>)>,
+    focusables: Query<(
+        Entity,
+        &Focusable,
+        Option<&Position>,
+        Option<&Handle<Sprite>>,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+        Option<&TransformedSprite>,
+    )>,
+) {
+    // The topmost modal entity takes focus, and every key goes to it
+    let modal = modals
+        .iter()
+        .max_by_key(|(_, position, render_layers)| (layers.rank(*render_layers), position.z))
+        .map(|(entity, ..)| entity);
+    if modal != focus.modal {
+        if focus.modal.is_none() {
+            focus.before_modal = focus.entity;
+        }
+        if modal.is_none() {
+            focus.entity = focus.before_modal.take();
+        }
+        focus.modal = modal;
+    }
+    if let Some(modal) = modal {
+        if focus.entity != Some(modal) {
+            focus.entity = Some(modal);
+        }
+        for key in key_reader.iter(&key_events) {
+            focused_key_events.send(FocusedKeyEvent {
+                entity: modal,
+                key: *key,
+            });
+        }
+        // Clicks can't move focus away from it either
+        for _ in mouse_reader.iter(&mouse_events) {}
+        return;
+    }
+
+    // Entities in tab order
+    let mut order: Vec<(Entity, i32, Option<(i32, i32)>)> = focusables
+        .iter()
+        .map(|(entity, focusable, position, ..)| {
+            let position = position.map(|position| (position.x, position.y));
+            (entity, focusable.order, position)
+        })
+        .collect();
+    order.sort_by_key(|(entity, order, position)| {
+        let (x, y) = position.unwrap_or((0, 0));
+        (*order, y, x, *entity)
+    });
+
+    for key in key_reader.iter(&key_events) {
+        let current = focus
+            .entity
+            .and_then(|focused| order.iter().position(|(entity, ..)| *entity == focused));
+        let next = match key.code {
+            _ if order.is_empty() => None,
+            KeyCode::Tab => Some(match current {
+                Some(index) => order[(index + 1) % order.len()].0,
+                None => order[0].0,
+            }),
+            KeyCode::BackTab => Some(match current {
+                Some(index) => order[(index + order.len() - 1) % order.len()].0,
+                None => order[order.len() - 1].0,
+            }),
+            KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right => match current {
+                Some(index) => {
+                    let (entity, _, position) = &order[index];
+                    let uses_arrow_keys = focusables
+                        .get(*entity)
+                        .map_or(false, |(_, focusable, ..)| focusable.uses_arrow_keys);
+                    match position {
+                        Some(position) if !uses_arrow_keys => {
+                            nearest_in_direction(*position, key.code, &order)
+                        }
+                        _ => None,
+                    }
+                }
+                None => Some(order[0].0),
+            },
+            _ => None,
+        };
+
+        match (next, focus.entity) {
+            (Some(next), _) => focus.entity = Some(next),
+            (None, Some(entity)) if key.code != KeyCode::Tab && key.code != KeyCode::BackTab => {
+                focused_key_events.send(FocusedKeyEvent { entity, key: *key })
+            }
+            _ => {}
+        }
+    }
+
+    // Clicking focuses the topmost focusable entity under the mouse
+    for event in mouse_reader.iter(&mouse_events) {
+        if let MouseEvent::Down(MouseButton::Left, column, row, _) = *event {
+            let clicked = focusables
+                .iter()
+                .filter_map(
+                    |(entity, _, position, sprite, area, render_layers, transformed)| {
+                        let position = position?;
+                        let sprite =
+                            sprite_transform::drawn_sprite(&sprites, sprite?, transformed)?;
+                        let size = (sprite.width(), sprite.height());
+                        viewports.cell_at(position, size, area, render_layers, (column, row))?;
+                        Some(((layers.rank(render_layers), position.z), entity))
+                    },
+                )
+                .max_by_key(|(depth, _)| *depth);
+            if let Some((_, entity)) = clicked {
+                focus.entity = Some(entity);
+            }
+        }
+    }
+}
+
+/// Moves the Focused marker to the focused entity, and sends focus events when it changes
+pub(crate) fn update_focus(
+    commands: &mut Commands,
+    mut focus: ResMut<Focus>,
+    mut gained_events: ResMut<Events<FocusGained>>,
+    mut lost_events: ResMut<Events<FocusLost>>,
+    focusables: Query<Entity, With<Focusable>>,
+    focused: Query<Entity, With<Focused>>,
+) {
+    // Focus is lost when the entity is despawned or stops being focusable
+    if let Some(entity) = focus.entity {
+        if focusables.get(entity).is_err() {
+            focus.entity = None;
+        }
+    }
+
+    if focus.synced != focus.entity {
+        if let Some(entity) = focus.synced {
+            lost_events.send(FocusLost { entity });
+        }
+        if let Some(entity) = focus.entity {
+            gained_events.send(FocusGained { entity });
+        }
+        focus.synced = focus.entity;
+    }
+
+    for entity in focused.iter() {
+        if Some(entity) != focus.entity {
+            commands.remove_one::<Focused>(entity);
+        }
+    }
+    if let Some(entity) = focus.entity {
+        if focused.get(entity).is_err() {
+            commands.insert_one(entity, Focused);
+        }
+    }
+}
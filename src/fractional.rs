@@ -0,0 +1,116 @@
+use bevy::prelude::*;
+
+use crate::components::Position;
+use crate::sprite_transform::SpriteTransform;
+
+/// Where an entity is to a fraction of a cell, for things that move slowly or need smooth speeds.
+/// It's rounded to the entity's `Position`, which only changes when the cell does, so moving within a
+/// cell doesn't redraw anything. Move the entity with this rather than its `Position`, which is
+/// written over; z is still set on the `Position`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FractionalPosition {
+    pub x: f32,
+    pub y: f32,
+    /// Draws how far through a cell the entity is across, to the nearest eighth, with eighth blocks.
+    /// The sprite is shifted with a SpriteTransform, so this only looks right for sprites made of
+    /// full blocks, like bars. Every eighth of a cell the entity moves makes a new shifted copy of
+    /// its sprite and redraws the entity, so it's best kept to small sprites.
+    pub eighths: bool,
+}
+
+impl FractionalPosition {
+    pub fn new(x: f32, y: f32) -> FractionalPosition {
+        FractionalPosition {
+            x,
+            y,
+            eighths: false,
+        }
+    }
+
+    pub fn with_eighths(mut self) -> FractionalPosition {
+        self.eighths = true;
+        self
+    }
+
+    /// The cell the entity's in, and how many eighths of a cell it's moved right from there
+    pub fn cell(&self) -> (i32, i32, u8) {
+        let y = self.y.round() as i32;
+        if self.eighths {
+            // The fraction is drawn, so the cell is the one the left edge is in
+            let eighths = (self.x * 8.0).round() as i32;
+            (eighths.div_euclid(8), y, eighths.rem_euclid(8) as u8)
+        } else {
+            (self.x.round() as i32, y, 0)
+        }
+    }
+}
+
+pub(crate) fn update_fractional_positions(
+    commands: &mut Commands,
+    mut positions: Query<
+        (
+            Entity,
+            &FractionalPosition,
+            &mut Position,
+            Option<&mut SpriteTransform>,
+        ),
+        Changed<FractionalPosition>,
+    >,
+) {
+    for (entity, fractional, mut position, transform) in positions.iter_mut() {
+        let (x, y, eighths) = fractional.cell();
+        // Only the whole cell goes into the position, so moving within it doesn't redraw
+        if position.x != x || position.y != y {
+            position.x = x;
+            position.y = y;
+        }
+
+        match transform {
+            Some(mut transform) if transform.eighths != eighths => transform.eighths = eighths,
+            None if eighths != 0 => {
+                commands.insert_one(
+                    entity,
+                    SpriteTransform {
+                        eighths,
+                        ..Default::default()
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_round_to_the_nearest_cell() {
+        let cell = |x, y| FractionalPosition::new(x, y).cell();
+        assert_eq!(cell(2.4, 1.6), (2, 2, 0));
+        assert_eq!(cell(2.5, -1.5), (3, -2, 0));
+        assert_eq!(cell(-0.4, 0.0), (0, 0, 0));
+        assert_eq!(cell(-0.6, 0.0), (-1, 0, 0));
+        assert_eq!(cell(-2.5, 0.0), (-3, 0, 0));
+    }
+
+    #[test]
+    fn eighths_are_counted_right_from_the_cell_the_left_edge_is_in() {
+        let cell = |x, y| FractionalPosition::new(x, y).with_eighths().cell();
+        assert_eq!(cell(1.0, 0.6), (1, 1, 0));
+        assert_eq!(cell(1.03125, 0.0), (1, 0, 0));
+        assert_eq!(cell(1.0625, 0.0), (1, 0, 1));
+        assert_eq!(cell(1.5, 0.0), (1, 0, 4));
+        // Rounding up to the next whole cell moves into it rather than showing eight eighths
+        assert_eq!(cell(1.9375, 0.0), (2, 0, 0));
+
+        // Left of zero the cell rounds down too, so the eighths still count right from it
+        assert_eq!(cell(-0.03125, 0.0), (0, 0, 0));
+        assert_eq!(cell(-0.0625, 0.0), (-1, 0, 7));
+        assert_eq!(cell(-0.125, 0.0), (-1, 0, 7));
+        assert_eq!(cell(-0.75, 0.0), (-1, 0, 2));
+        assert_eq!(cell(-1.0, 0.0), (-1, 0, 0));
+        assert_eq!(cell(-1.25, 0.0), (-2, 0, 6));
+    }
+}
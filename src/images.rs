@@ -0,0 +1,488 @@
+use crate::components::{Color, Colors, Sprite, Style, StyleGrid, StyleMap};
+
+/// The label of the stylemap the image loader creates alongside each sprite. Load it with a path
+/// like "player.png#stylemap"
+pub const IMAGE_STYLEMAP_LABEL: &str = "stylemap";
+
+/// Which colors converted images are allowed to use
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ImageColors {
+    /// 24-bit color, for terminals which support it
+    TrueColor,
+    /// The 6x6x6 color cube and grayscale ramp of the 256 color ANSI palette
+    Ansi256,
+}
+
+/// Controls how images are converted into sprites. Insert this as a resource before adding the
+/// `CrosstermPlugin` to change how the image asset loader behaves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageSettings {
+    /// How much to scale the image by before converting it. Each terminal cell covers one pixel
+    /// horizontally and two vertically.
+    pub scale: f32,
+    pub colors: ImageColors,
+    /// Spread the error of each pixel's color to its neighbours when reducing to fewer colors
+    pub dither: bool,
+    /// Pixels with an alpha below this become transparent
+    pub alpha_threshold: u8,
+    /// Whether the image loader loads pngs as well as ppms. Turn this off if bevy's own image loader
+    /// is loading pngs as textures, since only one loader can have an extension.
+    pub png: bool,
+}
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        ImageSettings {
+            scale: 1.0,
+            colors: ImageColors::TrueColor,
+            dither: false,
+            alpha_threshold: 128,
+            png: true,
+        }
+    }
+}
+
+/// A decoded image, stored as rows of RGBA pixels
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RgbaImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl RgbaImage {
+    pub fn new(width: usize, height: usize, pixels: Vec<[u8; 4]>) -> RgbaImage {
+        assert_eq!(
+            width * height,
+            pixels.len(),
+            "image size doesn't match the number of pixels"
+        );
+        RgbaImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn from_png(bytes: &[u8]) -> Result<RgbaImage, anyhow::Error> {
+        let mut decoder = png::Decoder::new(bytes);
+        // Always get 8 bits per channel, and turn palettes and tRNS chunks into plain colors and alpha
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buffer = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buffer)?;
+
+        let width = info.width as usize;
+        let height = info.height as usize;
+        let mut pixels = Vec::with_capacity(width * height);
+        for line in buffer.chunks(info.line_size).take(height) {
+            for x in 0..width {
+                let pixel = match info.color_type {
+                    png::ColorType::Grayscale => [line[x], line[x], line[x], 255],
+                    png::ColorType::GrayscaleAlpha => {
+                        let p = &line[x * 2..x * 2 + 2];
+                        [p[0], p[0], p[0], p[1]]
+                    }
+                    png::ColorType::RGB => {
+                        let p = &line[x * 3..x * 3 + 3];
+                        [p[0], p[1], p[2], 255]
+                    }
+                    png::ColorType::RGBA => {
+                        let p = &line[x * 4..x * 4 + 4];
+                        [p[0], p[1], p[2], p[3]]
+                    }
+                    png::ColorType::Indexed => {
+                        anyhow::bail!("indexed png was not expanded")
+                    }
+                };
+                pixels.push(pixel);
+            }
+        }
+
+        Ok(RgbaImage::new(width, height, pixels))
+    }
+
+    /// Reads a binary (P6) or plain text (P3) portable pixmap
+    pub fn from_ppm(bytes: &[u8]) -> Result<RgbaImage, anyhow::Error> {
+        let binary = match bytes.get(0..2) {
+            Some(b"P6") => true,
+            Some(b"P3") => false,
+            _ => anyhow::bail!("not a P3 or P6 ppm"),
+        };
+        let mut reader = PpmReader { bytes, pos: 2 };
+
+        let width = reader.number()?;
+        let height = reader.number()?;
+        let max_value = reader.number()?;
+        if max_value == 0 || max_value > u16::MAX as usize {
+            anyhow::bail!("invalid ppm max value {}", max_value);
+        }
+        let channel = |value: usize| (value.min(max_value) * 255 / max_value) as u8;
+
+        let mut pixels = Vec::with_capacity(width * height);
+        if binary {
+            // Exactly one whitespace byte separates the header from the pixel data
+            let data = bytes
+                .get(reader.pos + 1..)
+                .ok_or_else(|| anyhow::anyhow!("ppm has no pixel data"))?;
+            let sample_size = if max_value < 256 { 1 } else { 2 };
+            let needed = width * height * 3 * sample_size;
+            if data.len() < needed {
+                anyhow::bail!("ppm pixel data is truncated");
+            }
+            for rgb in data[..needed].chunks(3 * sample_size) {
+                let sample = |i: usize| {
+                    if sample_size == 1 {
+                        rgb[i] as usize
+                    } else {
+                        (rgb[i * 2] as usize) << 8 | rgb[i * 2 + 1] as usize
+                    }
+                };
+                pixels.push([
+                    channel(sample(0)),
+                    channel(sample(1)),
+                    channel(sample(2)),
+                    255,
+                ]);
+            }
+        } else {
+            for _ in 0..width * height {
+                let r = reader.number()?;
+                let g = reader.number()?;
+                let b = reader.number()?;
+                pixels.push([channel(r), channel(g), channel(b), 255]);
+            }
+        }
+
+        Ok(RgbaImage::new(width, height, pixels))
+    }
+
+    /// Resizes the image using nearest neighbour sampling, which keeps pixel art crisp
+    pub fn scaled(&self, scale: f32) -> RgbaImage {
+        let width = (self.width as f32 * scale).round().max(0.0) as usize;
+        let height = (self.height as f32 * scale).round().max(0.0) as usize;
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let src_y = std::cmp::min(self.height - 1, (y as f32 / scale) as usize);
+            for x in 0..width {
+                let src_x = std::cmp::min(self.width - 1, (x as f32 / scale) as usize);
+                pixels.push(self.pixel(src_x, src_y));
+            }
+        }
+        RgbaImage::new(width, height, pixels)
+    }
+}
+
+// Splits the header and plain text pixels of a ppm into whitespace separated numbers
+struct PpmReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PpmReader<'a> {
+    fn number(&mut self) -> Result<usize, anyhow::Error> {
+        loop {
+            match self.bytes.get(self.pos) {
+                Some(b'#') => {
+                    while self.pos < self.bytes.len() && self.bytes[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                Some(byte) if byte.is_ascii_whitespace() => self.pos += 1,
+                Some(_) => break,
+                None => anyhow::bail!("unexpected end of ppm"),
+            }
+        }
+        let start = self.pos;
+        while self.pos < self.bytes.len() && !self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        let token = std::str::from_utf8(&self.bytes[start..self.pos])?;
+        Ok(token.parse()?)
+    }
+}
+
+// The levels of each channel in the 6x6x6 color cube of the 256 color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Finds the closest color in the 256 color palette, ignoring the first 16 since terminals are free
+/// to pick those however they like
+fn nearest_ansi(rgb: [u8; 3]) -> (u8, [u8; 3]) {
+    let distance = |a: [u8; 3], b: [u8; 3]| -> i32 {
+        (0..3).map(|i| (a[i] as i32 - b[i] as i32).pow(2)).sum()
+    };
+
+    let nearest_level = |value: u8| -> usize {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|i| (CUBE_LEVELS[*i] as i32 - value as i32).abs())
+            .unwrap()
+    };
+    let (r, g, b) = (
+        nearest_level(rgb[0]),
+        nearest_level(rgb[1]),
+        nearest_level(rgb[2]),
+    );
+    let cube = [CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]];
+    let cube_index = 16 + 36 * r + 6 * g + b;
+
+    let average = (rgb[0] as i32 + rgb[1] as i32 + rgb[2] as i32) / 3;
+    let gray_step = ((average - 8).max(0) / 10).min(23);
+    let gray_level = (8 + 10 * gray_step) as u8;
+    let gray = [gray_level, gray_level, gray_level];
+    let gray_index = 232 + gray_step as usize;
+
+    if distance(rgb, gray) < distance(rgb, cube) {
+        (gray_index as u8, gray)
+    } else {
+        (cube_index as u8, cube)
+    }
+}
+
+/// Picks a terminal color for every pixel, or None for transparent ones
+fn quantize(image: &RgbaImage, settings: &ImageSettings) -> Vec<Option<Color>> {
+    let opaque = |pixel: [u8; 4]| pixel[3] >= settings.alpha_threshold;
+
+    match settings.colors {
+        ImageColors::TrueColor => image
+            .pixels
+            .iter()
+            .map(|p| {
+                if opaque(*p) {
+                    Some(Color::Rgb {
+                        r: p[0],
+                        g: p[1],
+                        b: p[2],
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect(),
+        ImageColors::Ansi256 => {
+            // Floyd-Steinberg error diffusion, only spreading error between opaque pixels
+            let mut error = vec![[0.0f32; 3]; image.pixels.len()];
+            let mut colors = Vec::with_capacity(image.pixels.len());
+            for y in 0..image.height {
+                for x in 0..image.width {
+                    let idx = y * image.width + x;
+                    let pixel = image.pixels[idx];
+                    if !opaque(pixel) {
+                        colors.push(None);
+                        continue;
+                    }
+
+                    let mut wanted = [0u8; 3];
+                    for ((wanted, value), error) in
+                        wanted.iter_mut().zip(pixel.iter()).zip(error[idx].iter())
+                    {
+                        *wanted = (*value as f32 + error).round().clamp(0.0, 255.0) as u8;
+                    }
+                    let (ansi, actual) = nearest_ansi(wanted);
+                    colors.push(Some(Color::AnsiValue(ansi)));
+
+                    if !settings.dither {
+                        continue;
+                    }
+                    let mut spread = |dx: i32, dy: i32, weight: f32| {
+                        let nx = x as i32 + dx;
+                        let ny = y as i32 + dy;
+                        if nx < 0 || nx >= image.width as i32 || ny >= image.height as i32 {
+                            return;
+                        }
+                        let nidx = ny as usize * image.width + nx as usize;
+                        if !opaque(image.pixels[nidx]) {
+                            return;
+                        }
+                        for ((error, wanted), actual) in
+                            error[nidx].iter_mut().zip(wanted.iter()).zip(actual.iter())
+                        {
+                            *error += (*wanted as f32 - *actual as f32) * weight / 16.0;
+                        }
+                    };
+                    spread(1, 0, 7.0);
+                    spread(-1, 1, 3.0);
+                    spread(0, 1, 5.0);
+                    spread(1, 1, 1.0);
+                }
+            }
+            colors
+        }
+    }
+}
+
+/// Converts an image to a sprite and the stylemap which colors it. Each cell of the sprite shows
+/// two vertically stacked pixels, by drawing an upper half block with the top pixel as its
+/// foreground and the bottom pixel as its background.
+///
+/// Cells where both pixels are transparent are unstyled spaces, so they show whatever is underneath
+/// when the entity is `Visible::transparent()`.
+pub fn image_to_sprite(image: &RgbaImage, settings: &ImageSettings) -> (Sprite, StyleMap) {
+    let image = if (settings.scale - 1.0).abs() > f32::EPSILON {
+        image.scaled(settings.scale)
+    } else {
+        image.clone()
+    };
+    let colors = quantize(&image, settings);
+
+    let mut data = String::new();
+    let mut cells = Vec::new();
+    for cell_y in 0..(image.height + 1) / 2 {
+        let mut row = Vec::with_capacity(image.width);
+        for x in 0..image.width {
+            let top = colors[cell_y * 2 * image.width + x];
+            let bottom = if cell_y * 2 + 1 < image.height {
+                colors[(cell_y * 2 + 1) * image.width + x]
+            } else {
+                None
+            };
+
+            let (grapheme, style) = match (top, bottom) {
+                (None, None) => (' ', None),
+                (Some(top), Some(bottom)) if top == bottom => (' ', Some(Style::with_bg(bottom))),
+                (Some(top), bottom) => (
+                    '▀',
+                    Some(Style::with_colors(Colors {
+                        foreground: Some(top),
+                        background: bottom,
+                    })),
+                ),
+                (None, Some(bottom)) => ('▄', Some(Style::with_fg(bottom))),
+            };
+            data.push(grapheme);
+            row.push(style);
+        }
+        cells.push(row);
+        data.push('\n');
+    }
+
+    let stylemap = StyleMap::with_grid(
+        Style::default(),
+        StyleGrid::from_cells_or_full(cells, Style::default()),
+    );
+    (Sprite::new(data), stylemap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(r: u8, g: u8, b: u8) -> Option<Color> {
+        Some(Color::Rgb { r, g, b })
+    }
+
+    #[test]
+    fn reads_plain_ppms() {
+        let image = RgbaImage::from_ppm(b"P3\n# a comment\n2 1\n15\n15 0 0  0 0 15\n").unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(image.pixel(1, 0), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn reads_binary_ppms() {
+        let mut bytes = b"P6 1 2 255\n".to_vec();
+        bytes.extend_from_slice(&[10, 20, 30, 40, 50, 60]);
+        let image = RgbaImage::from_ppm(&bytes).unwrap();
+        assert_eq!(image.pixel(0, 0), [10, 20, 30, 255]);
+        assert_eq!(image.pixel(0, 1), [40, 50, 60, 255]);
+
+        let mut wide = b"P6 1 1 65535\n".to_vec();
+        wide.extend_from_slice(&[0xff, 0xff, 0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            RgbaImage::from_ppm(&wide).unwrap().pixel(0, 0),
+            [255, 127, 0, 255]
+        );
+    }
+
+    #[test]
+    fn bad_ppms_are_rejected() {
+        assert!(RgbaImage::from_ppm(b"P5 1 1 255\n\0").is_err());
+        assert!(RgbaImage::from_ppm(b"P6 2 2 255\n\0\0\0").is_err());
+        assert!(RgbaImage::from_ppm(b"P3 1 1 0\n0 0 0").is_err());
+        assert!(RgbaImage::from_ppm(b"P3 1 1 255\n0 0").is_err());
+    }
+
+    #[test]
+    fn reads_pngs() {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+            encoder.set_color(png::ColorType::GrayscaleAlpha);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[100, 255, 200, 0]).unwrap();
+        }
+        let image = RgbaImage::from_png(&bytes).unwrap();
+        assert_eq!(image.pixel(0, 0), [100, 100, 100, 255]);
+        assert_eq!(image.pixel(1, 0), [200, 200, 200, 0]);
+        assert!(RgbaImage::from_png(b"not a png").is_err());
+    }
+
+    #[test]
+    fn pixels_become_half_blocks() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let clear = [0, 0, 0, 0];
+        let image = RgbaImage::new(4, 2, vec![red, red, clear, clear, blue, red, blue, clear]);
+        let (sprite, stylemap) = image_to_sprite(&image, &ImageSettings::default());
+        assert_eq!(sprite.data(), "▀ ▄ \n");
+
+        let colors = |x| stylemap.style_at(x, 0).map(|style| style.colors);
+        assert_eq!(
+            colors(0),
+            Some(Colors {
+                foreground: rgb(255, 0, 0),
+                background: rgb(0, 0, 255),
+            })
+        );
+        assert_eq!(
+            colors(1),
+            Some(Colors::bg(Color::Rgb { r: 255, g: 0, b: 0 }))
+        );
+        assert_eq!(
+            colors(2),
+            Some(Colors::fg(Color::Rgb { r: 0, g: 0, b: 255 }))
+        );
+        assert_eq!(colors(3), None);
+    }
+
+    #[test]
+    fn photos_keep_their_colors() {
+        // Every cell has its own colors, more than a palette can index
+        let pixels = (0..256 * 512)
+            .map(|i| {
+                [
+                    (i % 256) as u8,
+                    (i / 256 % 256) as u8,
+                    (i / 256 / 256) as u8,
+                    255,
+                ]
+            })
+            .collect();
+        let image = RgbaImage::new(256, 512, pixels);
+        let (_, stylemap) = image_to_sprite(&image, &ImageSettings::default());
+        assert!(stylemap.palette.is_none());
+        let colors = stylemap.style_for(255, 255).colors;
+        assert_eq!(colors.foreground, rgb(255, 254, 1));
+        assert_eq!(colors.background, rgb(255, 255, 1));
+    }
+
+    #[test]
+    fn ansi_colors_are_nearest() {
+        assert_eq!(nearest_ansi([255, 0, 0]), (196, [255, 0, 0]));
+        assert_eq!(nearest_ansi([0, 0, 0]), (16, [0, 0, 0]));
+        assert_eq!(nearest_ansi([128, 128, 128]), (244, [128, 128, 128]));
+    }
+}
@@ -0,0 +1,181 @@
+/// The layer entities are on when they don't have RenderLayers
+pub const WORLD_LAYER: u8 = 0;
+/// For things drawn behind the world, like skies and backdrops
+pub const BACKGROUND_LAYER: u8 = 1;
+/// For things drawn over the world, like particles and weather
+pub const EFFECTS_LAYER: u8 = 2;
+/// For menus, status bars, and widgets
+pub const UI_LAYER: u8 = 3;
+/// For things drawn over everything else, like dialogs
+pub const OVERLAY_LAYER: u8 = 4;
+
+/// Which render layers an entity is on, as a mask with a bit for each of 32 layers. Viewports only
+/// show entities on at least one of the layers in their own mask, and the Layers resource decides
+/// which layers are drawn and in what order. Entities without RenderLayers are on the world layer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RenderLayers(pub u32);
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        RenderLayers::layer(WORLD_LAYER)
+    }
+}
+
+impl RenderLayers {
+    /// The number of layers there are
+    pub const COUNT: u8 = 32;
+
+    /// Just one layer. Layers past the last one wrap around.
+    pub fn layer(layer: u8) -> RenderLayers {
+        RenderLayers(1 << (layer % RenderLayers::COUNT))
+    }
+
+    pub fn background() -> RenderLayers {
+        RenderLayers::layer(BACKGROUND_LAYER)
+    }
+
+    pub fn world() -> RenderLayers {
+        RenderLayers::layer(WORLD_LAYER)
+    }
+
+    pub fn effects() -> RenderLayers {
+        RenderLayers::layer(EFFECTS_LAYER)
+    }
+
+    pub fn ui() -> RenderLayers {
+        RenderLayers::layer(UI_LAYER)
+    }
+
+    pub fn overlay() -> RenderLayers {
+        RenderLayers::layer(OVERLAY_LAYER)
+    }
+
+    /// Every layer
+    pub fn all() -> RenderLayers {
+        RenderLayers(u32::MAX)
+    }
+
+    /// No layers at all, so nothing shows it
+    pub fn none() -> RenderLayers {
+        RenderLayers(0)
+    }
+
+    pub fn with(self, layer: u8) -> RenderLayers {
+        RenderLayers(self.0 | RenderLayers::layer(layer).0)
+    }
+
+    pub fn without(self, layer: u8) -> RenderLayers {
+        RenderLayers(self.0 & !RenderLayers::layer(layer).0)
+    }
+
+    pub fn contains(&self, layer: u8) -> bool {
+        self.intersects(&RenderLayers::layer(layer))
+    }
+
+    /// Whether there's a layer in both masks
+    pub fn intersects(&self, other: &RenderLayers) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+/// Which layers are drawn, and the order they're drawn in. Hiding a layer or changing the order
+/// redraws the whole screen.
+///
+/// Layers are drawn from the bottom of the order up, and entities on the same layer are drawn in
+/// order of their z. An entity on more than one layer is drawn with the highest of them, and is
+/// shown while any of them are visible.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Layers {
+    /// The layers that are drawn
+    pub visible: RenderLayers,
+    /// The layers in the order they're drawn, from the bottom up. Layers that aren't in the order
+    /// are drawn beneath all the ones that are.
+    pub order: Vec<u8>,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Layers {
+            visible: RenderLayers::all(),
+            order: vec![
+                BACKGROUND_LAYER,
+                WORLD_LAYER,
+                EFFECTS_LAYER,
+                UI_LAYER,
+                OVERLAY_LAYER,
+            ],
+        }
+    }
+}
+
+impl Layers {
+    pub fn show(&mut self, layer: u8) {
+        self.visible = self.visible.with(layer);
+    }
+
+    pub fn hide(&mut self, layer: u8) {
+        self.visible = self.visible.without(layer);
+    }
+
+    pub fn toggle(&mut self, layer: u8) {
+        if self.is_visible(layer) {
+            self.hide(layer);
+        } else {
+            self.show(layer);
+        }
+    }
+
+    pub fn is_visible(&self, layer: u8) -> bool {
+        self.visible.contains(layer)
+    }
+
+    /// Where an entity on the given layers comes in the drawing order. Higher ranks are drawn on top.
+    pub(crate) fn rank(&self, layers: Option<&RenderLayers>) -> usize {
+        let layers = layers.copied().unwrap_or_default();
+        (0..RenderLayers::COUNT)
+            .filter(|layer| layers.contains(*layer))
+            .map(|layer| {
+                self.order
+                    .iter()
+                    .position(|ordered| *ordered == layer)
+                    .map_or(0, |index| index + 1)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entities_without_layers_are_on_the_world_layer() {
+        let layers = Layers::default();
+        assert_eq!(layers.rank(None), 2);
+        assert_eq!(layers.rank(Some(&RenderLayers::world())), 2);
+        assert_eq!(layers.rank(Some(&RenderLayers::background())), 1);
+        assert_eq!(layers.rank(Some(&RenderLayers::overlay())), 5);
+    }
+
+    #[test]
+    fn the_highest_layer_wins() {
+        let layers = Layers::default();
+        let both = RenderLayers::ui().with(BACKGROUND_LAYER);
+        assert_eq!(layers.rank(Some(&both)), 4);
+        assert_eq!(layers.rank(Some(&RenderLayers::all())), 5);
+    }
+
+    #[test]
+    fn unordered_layers_are_drawn_beneath() {
+        let layers = Layers {
+            visible: RenderLayers::all(),
+            order: vec![UI_LAYER, WORLD_LAYER],
+        };
+        assert_eq!(layers.rank(Some(&RenderLayers::ui())), 1);
+        assert_eq!(layers.rank(None), 2);
+        assert_eq!(layers.rank(Some(&RenderLayers::layer(7))), 0);
+        assert_eq!(layers.rank(Some(&RenderLayers::none())), 0);
+        assert_eq!(layers.rank(Some(&RenderLayers::layer(7).with(UI_LAYER))), 1);
+    }
+}
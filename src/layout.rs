@@ -0,0 +1,462 @@
+use bevy::prelude::*;
+
+use crate::components::Position;
+use crate::panel::Panel;
+use crate::text::{RichText, Text};
+use crate::CrosstermWindow;
+
+/// The width or height of a layout node
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dimension {
+    /// A fixed number of cells
+    Cells(usize),
+    /// A percentage of the space inside the node's parent, or of the window for root nodes
+    Percent(f32),
+    /// Whatever space is left. In rows and columns, it's shared with the other automatically sized
+    /// nodes
+    Auto,
+}
+
+impl Default for Dimension {
+    fn default() -> Self {
+        Dimension::Auto
+    }
+}
+
+impl Dimension {
+    fn resolve(self, available: usize) -> Option<usize> {
+        match self {
+            Dimension::Cells(cells) => Some(cells),
+            Dimension::Percent(percent) => {
+                Some((available as f32 * percent / 100.0).round().max(0.0) as usize)
+            }
+            Dimension::Auto => None,
+        }
+    }
+}
+
+/// Space around the four sides of a node
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Edges {
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+    pub left: usize,
+}
+
+impl Edges {
+    pub fn all(cells: usize) -> Edges {
+        Edges {
+            top: cells,
+            right: cells,
+            bottom: cells,
+            left: cells,
+        }
+    }
+
+    pub fn symmetric(vertical: usize, horizontal: usize) -> Edges {
+        Edges {
+            top: vertical,
+            right: horizontal,
+            bottom: vertical,
+            left: horizontal,
+        }
+    }
+
+    fn horizontal(&self) -> usize {
+        self.left + self.right
+    }
+
+    fn vertical(&self) -> usize {
+        self.top + self.bottom
+    }
+}
+
+/// How a layout node arranges the layout nodes among its children
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Arrangement {
+    /// Side by side, left to right
+    Row,
+    /// One above the other, top to bottom
+    Column,
+    /// In equally sized cells, filling each row of cells before moving onto the next
+    Grid { columns: usize },
+}
+
+impl Default for Arrangement {
+    fn default() -> Self {
+        Arrangement::Column
+    }
+}
+
+/// Where a node sits inside the space it's given when it's smaller than that space. Root nodes are
+/// given the whole window, so this anchors them to its edges.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Default for Anchor {
+    fn default() -> Self {
+        Anchor::TopLeft
+    }
+}
+
+impl Anchor {
+    /// How far along the free space the node goes, horizontally and vertically
+    fn factors(self) -> (f32, f32) {
+        match self {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// Makes an entity part of a layout. Layout nodes are positioned and sized by the layout node they're
+/// a child of, and nodes without one are laid out inside the window.
+///
+/// Every frame, the resolved position is written to the entity's Position, and the resolved size to
+/// its LayoutSize. Panels are resized to fit, and Text and RichText are bounded by the size.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LayoutNode {
+    pub width: Dimension,
+    pub height: Dimension,
+    pub min_width: Option<usize>,
+    pub max_width: Option<usize>,
+    pub min_height: Option<usize>,
+    pub max_height: Option<usize>,
+    /// Space kept clear around the outside of the node
+    pub margin: Edges,
+    /// Space kept clear between the node's edges and its children
+    pub padding: Edges,
+    /// Space between each of the node's children
+    pub gap: usize,
+    pub arrangement: Arrangement,
+    pub anchor: Anchor,
+}
+
+impl LayoutNode {
+    pub fn row() -> LayoutNode {
+        LayoutNode {
+            arrangement: Arrangement::Row,
+            ..Default::default()
+        }
+    }
+
+    pub fn column() -> LayoutNode {
+        LayoutNode {
+            arrangement: Arrangement::Column,
+            ..Default::default()
+        }
+    }
+
+    pub fn grid(columns: usize) -> LayoutNode {
+        LayoutNode {
+            arrangement: Arrangement::Grid { columns },
+            ..Default::default()
+        }
+    }
+
+    pub fn sized(width: Dimension, height: Dimension) -> LayoutNode {
+        LayoutNode {
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    fn clamp_width(&self, width: usize) -> usize {
+        clamp(width, self.min_width, self.max_width)
+    }
+
+    fn clamp_height(&self, height: usize) -> usize {
+        clamp(height, self.min_height, self.max_height)
+    }
+
+    /// Sizes the node to fit in a slot, and then anchors it inside the slot
+    fn place(&self, slot: Rect) -> Rect {
+        let available_width = slot.width.saturating_sub(self.margin.horizontal());
+        let available_height = slot.height.saturating_sub(self.margin.vertical());
+        let width = self.clamp_width(
+            self.width
+                .resolve(available_width)
+                .unwrap_or(available_width),
+        );
+        let height = self.clamp_height(
+            self.height
+                .resolve(available_height)
+                .unwrap_or(available_height),
+        );
+
+        let (x_factor, y_factor) = self.anchor.factors();
+        Rect {
+            x: slot.x + self.margin.left as i32 + offset(available_width, width, x_factor),
+            y: slot.y + self.margin.top as i32 + offset(available_height, height, y_factor),
+            width,
+            height,
+        }
+    }
+}
+
+/// The size a layout node was given
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LayoutSize {
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+struct Rect {
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+}
+
+impl Rect {
+    fn inside(&self, edges: &Edges) -> Rect {
+        Rect {
+            x: self.x + edges.left as i32,
+            y: self.y + edges.top as i32,
+            width: self.width.saturating_sub(edges.horizontal()),
+            height: self.height.saturating_sub(edges.vertical()),
+        }
+    }
+}
+
+fn clamp(value: usize, min: Option<usize>, max: Option<usize>) -> usize {
+    let value = max.map_or(value, |max| value.min(max));
+    min.map_or(value, |min| value.max(min))
+}
+
+fn offset(available: usize, size: usize, factor: f32) -> i32 {
+    (available.saturating_sub(size) as f32 * factor).round() as i32
+}
+
+/// Lays out nodes in a row or column. The main axis is the one the nodes are lined up along.
+fn arrange_line(nodes: &[&LayoutNode], content: Rect, gap: usize, horizontal: bool) -> Vec<Rect> {
+    // Everything is worked out as if it's a row, and flipped around for columns
+    let (main_size, cross_size) = if horizontal {
+        (content.width, content.height)
+    } else {
+        (content.height, content.width)
+    };
+    let main = |node: &LayoutNode| {
+        if horizontal {
+            (node.width, node.margin.horizontal())
+        } else {
+            (node.height, node.margin.vertical())
+        }
+    };
+    let clamp_main = |node: &LayoutNode, size| {
+        if horizontal {
+            node.clamp_width(size)
+        } else {
+            node.clamp_height(size)
+        }
+    };
+
+    let mut sizes: Vec<Option<usize>> = nodes
+        .iter()
+        .map(|node| {
+            let (size, _) = main(node);
+            size.resolve(main_size).map(|size| clamp_main(node, size))
+        })
+        .collect();
+
+    // Automatically sized nodes share whatever is left over
+    let used: usize = nodes
+        .iter()
+        .zip(sizes.iter())
+        .map(|(node, size)| size.unwrap_or(0) + main(node).1)
+        .sum::<usize>()
+        + gap * nodes.len().saturating_sub(1);
+    let remaining = main_size.saturating_sub(used);
+    let auto_count = sizes.iter().filter(|size| size.is_none()).count();
+    let mut auto_index = 0;
+    for (node, size) in nodes.iter().zip(sizes.iter_mut()) {
+        if size.is_none() {
+            let mut share = remaining / auto_count;
+            if auto_index < remaining % auto_count {
+                share += 1;
+            }
+            auto_index += 1;
+            *size = Some(clamp_main(node, share));
+        }
+    }
+
+    let mut cursor = 0;
+    let mut rects = Vec::with_capacity(nodes.len());
+    for (node, size) in nodes.iter().zip(sizes) {
+        let size = size.unwrap_or(0);
+        let outer = size + main(node).1;
+        // The cross axis is handled just like any other slot, with the main axis already sized
+        let slot = if horizontal {
+            Rect {
+                x: content.x + cursor as i32,
+                y: content.y,
+                width: outer,
+                height: cross_size,
+            }
+        } else {
+            Rect {
+                x: content.x,
+                y: content.y + cursor as i32,
+                width: cross_size,
+                height: outer,
+            }
+        };
+        let mut rect = node.place(slot);
+        if horizontal {
+            rect.x = slot.x + node.margin.left as i32;
+            rect.width = size;
+        } else {
+            rect.y = slot.y + node.margin.top as i32;
+            rect.height = size;
+        }
+        rects.push(rect);
+        cursor += outer + gap;
+    }
+    rects
+}
+
+fn arrange_grid(nodes: &[&LayoutNode], content: Rect, gap: usize, columns: usize) -> Vec<Rect> {
+    let columns = columns.max(1);
+    let rows = (nodes.len() + columns - 1) / columns;
+    let cell_width = content.width.saturating_sub(gap * (columns - 1)) / columns;
+    let cell_height = content.height.saturating_sub(gap * rows.saturating_sub(1)) / rows.max(1);
+
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let (column, row) = (i % columns, i / columns);
+            node.place(Rect {
+                x: content.x + (column * (cell_width + gap)) as i32,
+                y: content.y + (row * (cell_height + gap)) as i32,
+                width: cell_width,
+                height: cell_height,
+            })
+        })
+        .collect()
+}
+
+fn layout_children(
+    entity: Entity,
+    rect: Rect,
+    nodes: &Query<(&LayoutNode, Option<&Children>)>,
+    layout: &mut Vec<(Entity, Rect)>,
+) {
+    layout.push((entity, rect));
+
+    let (node, children) = match nodes.get(entity) {
+        Ok((node, Some(children))) => (node, children),
+        _ => return,
+    };
+    let children: Vec<(Entity, &LayoutNode)> = children
+        .iter()
+        .filter_map(|child| nodes.get(*child).ok().map(|(node, _)| (*child, node)))
+        .collect();
+    if children.is_empty() {
+        return;
+    }
+
+    let content = rect.inside(&node.padding);
+    let child_nodes: Vec<&LayoutNode> = children.iter().map(|(_, node)| *node).collect();
+    let rects = match node.arrangement {
+        Arrangement::Row => arrange_line(&child_nodes, content, node.gap, true),
+        Arrangement::Column => arrange_line(&child_nodes, content, node.gap, false),
+        Arrangement::Grid { columns } => arrange_grid(&child_nodes, content, node.gap, columns),
+    };
+    for ((child, _), rect) in children.into_iter().zip(rects) {
+        layout_children(child, rect, nodes, layout);
+    }
+}
+
+pub(crate) fn update_layout(
+    commands: &mut Commands,
+    window: Res<CrosstermWindow>,
+    roots: Query<(Entity, &LayoutNode, Option<&Parent>)>,
+    nodes: Query<(&LayoutNode, Option<&Children>)>,
+    missing_sizes: Query<Entity, (With<LayoutNode>, Without<LayoutSize>)>,
+    mut targets: Query<(
+        &mut Position,
+        Option<&mut LayoutSize>,
+        Option<&mut Panel>,
+        Option<&mut Text>,
+        Option<&mut RichText>,
+    )>,
+) {
+    for entity in missing_sizes.iter() {
+        commands.insert_one(entity, LayoutSize::default());
+    }
+
+    let window_rect = Rect {
+        x: 0,
+        y: 0,
+        width: window.width() as usize,
+        height: window.height() as usize,
+    };
+    let mut layout = Vec::new();
+    for (entity, node, parent) in roots.iter() {
+        let is_root = parent.map_or(true, |parent| nodes.get(parent.0).is_err());
+        if is_root {
+            layout_children(entity, node.place(window_rect), &nodes, &mut layout);
+        }
+    }
+
+    // Only write what actually changed, so nothing is redrawn or regenerated needlessly
+    for (entity, rect) in layout {
+        let (mut position, size, panel, text, rich_text) = match targets.get_mut(entity) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        if position.x != rect.x || position.y != rect.y {
+            position.x = rect.x;
+            position.y = rect.y;
+        }
+
+        let new_size = LayoutSize {
+            width: rect.width,
+            height: rect.height,
+        };
+        if let Some(mut size) = size {
+            if *size != new_size {
+                *size = new_size;
+            }
+        }
+        if let Some(mut panel) = panel {
+            if panel.width != rect.width || panel.height != rect.height {
+                panel.width = rect.width;
+                panel.height = rect.height;
+            }
+        }
+        if let Some(mut text) = text {
+            if text.max_width != Some(rect.width) || text.max_height != Some(rect.height) {
+                text.max_width = Some(rect.width);
+                text.max_height = Some(rect.height);
+            }
+        }
+        if let Some(mut text) = rich_text {
+            if text.max_width != Some(rect.width) || text.max_height != Some(rect.height) {
+                text.max_width = Some(rect.width);
+                text.max_height = Some(rect.height);
+            }
+        }
+    }
+}
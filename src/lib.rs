@@ -1,24 +1,85 @@
 use bevy::prelude::*;
 
 mod asset_loaders;
+pub mod camera;
+pub mod clip;
 pub mod components;
+pub mod dialog;
+pub mod figlet;
+pub mod focus;
+pub mod fractional;
+pub mod images;
+pub mod layers;
+pub mod layout;
+pub mod panel;
+pub mod particles;
 pub mod prelude;
 mod runner;
+pub mod sprite_transform;
 mod systems;
+pub mod text;
+pub mod text_input;
+pub mod tiled;
+pub mod tilemap;
+pub mod tween;
+pub mod viewport;
+pub mod widgets;
 
 pub struct CrosstermPlugin;
 impl Plugin for CrosstermPlugin {
     fn build(&self, app: &mut bevy::app::AppBuilder) {
+        // Entities can be spawned sharing a handle, so each one that generates its sprite, and its
+        // stylemap if it generates that too, gets its own first. These are added before every other
+        // system, so they run before the ones that do the generating
+        own_generated::<figlet::BigText>(app, false);
+        own_generated::<text::Text>(app, false);
+        own_generated::<text::RichText>(app, true);
+        own_generated::<panel::Panel>(app, true);
+        own_generated::<widgets::Label>(app, false);
+        own_generated::<widgets::Button>(app, true);
+        own_generated::<widgets::Checkbox>(app, false);
+        own_generated::<widgets::List>(app, true);
+        own_generated::<widgets::ProgressBar>(app, false);
+        own_generated::<widgets::Gauge>(app, false);
+        own_generated::<text_input::TextInput>(app, true);
+        own_generated::<dialog::Dialog>(app, true);
+
         app.add_resource(Cursor::default())
             .add_resource(components::PreviousEntityDetails::default())
             .add_resource(components::EntitiesToRedraw::default())
             .add_resource(components::PreviousWindowColors::default())
+            .add_resource(components::StyleOverride::default())
+            .add_resource(components::ScreenCells::default())
             .add_asset::<components::Sprite>()
             .add_asset::<components::StyleMap>()
+            .add_asset::<figlet::FigletFont>()
+            .add_asset::<tilemap::Tileset>()
+            .add_asset::<tiled::TiledMap>()
             .init_asset_loader::<asset_loaders::SpriteLoader>()
             .init_asset_loader::<asset_loaders::StyleMapLoader>()
+            .init_asset_loader::<asset_loaders::ImageLoader>()
+            .init_asset_loader::<asset_loaders::FigletFontLoader>()
+            .init_asset_loader::<asset_loaders::TilesetLoader>()
+            .init_asset_loader::<asset_loaders::TiledMapLoader>()
             .add_event::<crossterm::event::KeyEvent>()
             .add_event::<crossterm::event::MouseEvent>()
+            .add_resource(focus::Focus::default())
+            .add_resource(camera::Camera::default())
+            .add_resource(layers::Layers::default())
+            .add_resource(viewport::Viewports::default())
+            .add_resource(viewport::ActiveViewports::default())
+            .add_resource(sprite_transform::MirrorTable::default())
+            .add_event::<focus::FocusGained>()
+            .add_event::<focus::FocusLost>()
+            .add_event::<focus::FocusedKeyEvent>()
+            .add_event::<widgets::ButtonPressed>()
+            .add_event::<widgets::CheckboxToggled>()
+            .add_event::<widgets::ListSelected>()
+            .add_event::<widgets::ListActivated>()
+            .add_event::<text_input::TextChanged>()
+            .add_event::<text_input::TextSubmitted>()
+            .add_event::<dialog::DialogChoice>()
+            .add_event::<tween::TweenFinished>()
             .set_runner(runner::crossterm_runner)
             // Systems and stages
             // This must be before LAST because change tracking is cleared during LAST, but AssetEvents are published
@@ -34,7 +95,87 @@ impl Plugin for CrosstermPlugin {
                 bevy::app::stage::POST_UPDATE,
                 systems::add_previous_position.system(),
             )
+            // Focus moves first, so the keys it sends on go to the right widget
+            .add_system_to_stage(bevy::app::stage::PRE_UPDATE, focus::focus_input.system())
+            .add_system_to_stage(bevy::app::stage::PRE_UPDATE, focus::update_focus.system())
+            // Widgets handle input before the app's systems run, so their events can be read in UPDATE
+            .add_system_to_stage(bevy::app::stage::PRE_UPDATE, widgets::button_input.system())
+            .add_system_to_stage(bevy::app::stage::PRE_UPDATE, widgets::checkbox_input.system())
+            .add_system_to_stage(bevy::app::stage::PRE_UPDATE, widgets::list_input.system())
+            .add_system_to_stage(
+                bevy::app::stage::PRE_UPDATE,
+                text_input::text_input_input.system(),
+            )
+            .add_system_to_stage(bevy::app::stage::PRE_UPDATE, dialog::dialog_input.system())
+            .add_system_to_stage(bevy::app::stage::PRE_UPDATE, clip::scroll_view_input.system())
+            // Spawned before the app's systems run, so they can find the map's objects straight away
+            .add_system_to_stage(
+                bevy::app::stage::PRE_UPDATE,
+                tiled::spawn_tiled_maps.system(),
+            )
+            // Picks up focus changes made by the app
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, focus::update_focus.system())
+            // Tweens move things before anything that depends on where they are
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, tween::update_tweens.system())
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                fractional::update_fractional_positions.system(),
+            )
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                particles::update_particles.system(),
+            )
+            // Layout goes first, since it resizes the things that generate their sprites afterwards
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, layout::update_layout.system())
+            // Follows entities after they've been moved, and before anything that draws relative to
+            // the camera
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, camera::update_camera.system())
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                viewport::update_active_viewports.system(),
+            )
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                figlet::update_big_text.system(),
+            )
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, text::update_text.system())
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                text::update_rich_text.system(),
+            )
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, panel::update_panels.system())
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, widgets::update_labels.system())
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, widgets::update_buttons.system())
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                widgets::update_checkboxes.system(),
+            )
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, widgets::update_lists.system())
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                widgets::update_progress_bars.system(),
+            )
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, widgets::update_gauges.system())
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                text_input::update_text_inputs.system(),
+            )
+            // Needs the scroll that was just worked out for the input
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                text_input::text_input_cursor.system(),
+            )
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, dialog::update_dialogs.system())
+            // Goes after everything that makes sprites, so it transforms the finished ones
+            .add_system_to_stage(
+                bevy::app::stage::POST_UPDATE,
+                sprite_transform::update_sprite_transforms.system(),
+            )
+            // Goes after everything that moves things around
+            .add_system_to_stage(bevy::app::stage::POST_UPDATE, clip::update_draw_areas.system())
             // Needs asset events, and they aren't created until after POST_UPDATE, so we put them in PRE_RENDER
+            .add_system_to_stage(stage::PRE_RENDER, tilemap::tilemap_changes.system())
+            .add_system_to_stage(stage::PRE_RENDER, particles::particle_changes.system())
             .add_system_to_stage(
                 stage::PRE_RENDER,
                 systems::calculate_entities_to_redraw.system(),
@@ -47,6 +188,21 @@ impl Plugin for CrosstermPlugin {
     }
 }
 
+/// Adds the systems which give entities with a component a sprite of their own, and a stylemap of
+/// their own if the component generates that too
+fn own_generated<T: bevy::ecs::Component>(app: &mut AppBuilder, stylemap: bool) {
+    app.add_system_to_stage(
+        bevy::app::stage::POST_UPDATE,
+        components::own_sprite::<T>.system(),
+    );
+    if stylemap {
+        app.add_system_to_stage(
+            bevy::app::stage::POST_UPDATE,
+            components::own_stylemap::<T>.system(),
+        );
+    }
+}
+
 #[derive(Clone, Eq, PartialEq)]
 pub struct CrosstermWindowSettings {
     colors: components::Colors,
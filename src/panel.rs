@@ -0,0 +1,381 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::components::{self, Position, ScreenRect, Sprite, Style, StyleGrid, StyleMap};
+use crate::text::TextAlign;
+
+// Which directions a box-drawing character has lines going out in
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+
+// Box-drawing characters indexed by the directions their lines go in
+const SINGLE: [char; 16] = [
+    ' ', '╵', '╷', '│', '╴', '┘', '┐', '┤', '╶', '└', '┌', '├', '─', '┴', '┬', '┼',
+];
+const ROUNDED: [char; 16] = [
+    ' ', '╵', '╷', '│', '╴', '╯', '╮', '┤', '╶', '╰', '╭', '├', '─', '┴', '┬', '┼',
+];
+const DOUBLE: [char; 16] = [
+    ' ', '║', '║', '║', '═', '╝', '╗', '╣', '═', '╚', '╔', '╠', '═', '╩', '╦', '╬',
+];
+const HEAVY: [char; 16] = [
+    ' ', '╹', '╻', '┃', '╸', '┛', '┓', '┫', '╺', '┗', '┏', '┣', '━', '┻', '┳', '╋',
+];
+const ASCII: [char; 16] = [
+    ' ', '|', '|', '|', '-', '+', '+', '+', '-', '+', '+', '+', '-', '+', '+', '+',
+];
+
+/// The pieces of a custom border
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BorderChars {
+    pub top_left: char,
+    pub top: char,
+    pub top_right: char,
+    pub left: char,
+    pub right: char,
+    pub bottom_left: char,
+    pub bottom: char,
+    pub bottom_right: char,
+}
+
+/// What a panel's border is drawn with
+#[derive(Clone, Debug, PartialEq)]
+pub enum Border {
+    Single,
+    Double,
+    Rounded,
+    Heavy,
+    Ascii,
+    Chars(BorderChars),
+    /// A sprite which is sliced into nine parts. Its corner cells are the panel's corners, the rest
+    /// of its outer rows and columns are repeated along the panel's edges, and everything inside is
+    /// repeated to fill the panel.
+    NineSlice(Handle<Sprite>),
+}
+
+impl Default for Border {
+    fn default() -> Self {
+        Border::Single
+    }
+}
+
+impl Border {
+    /// The box-drawing characters of the border, if it's drawn with lines which can join up with
+    /// other panels
+    fn lines(&self) -> Option<&'static [char; 16]> {
+        match self {
+            Border::Single => Some(&SINGLE),
+            Border::Double => Some(&DOUBLE),
+            Border::Rounded => Some(&ROUNDED),
+            Border::Heavy => Some(&HEAVY),
+            Border::Ascii => Some(&ASCII),
+            Border::Chars(_) | Border::NineSlice(_) => None,
+        }
+    }
+}
+
+/// A bordered rectangle. The entity's sprite and stylemap are generated from the panel, and are
+/// regenerated whenever it changes.
+///
+/// Where the borders of panels at the same depth touch, their lines are joined up, so two panels
+/// sharing an edge draw it as one line with the right junctions at either end.
+#[derive(Clone, PartialEq)]
+pub struct Panel {
+    pub width: usize,
+    pub height: usize,
+    pub border: Border,
+    /// Text shown in the top edge of the border, in the border's style
+    pub title: Option<String>,
+    pub title_align: TextAlign,
+    /// The character that the inside of the panel is filled with
+    pub fill: char,
+    pub fill_style: Style,
+    pub border_style: Style,
+}
+
+impl Default for Panel {
+    fn default() -> Self {
+        Panel {
+            width: 0,
+            height: 0,
+            border: Border::default(),
+            title: None,
+            title_align: TextAlign::default(),
+            fill: ' ',
+            fill_style: Style::default(),
+            border_style: Style::default(),
+        }
+    }
+}
+
+impl Panel {
+    pub fn new(width: usize, height: usize) -> Panel {
+        Panel {
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_border(width: usize, height: usize, border: Border) -> Panel {
+        Panel {
+            width,
+            height,
+            border,
+            ..Default::default()
+        }
+    }
+
+    fn is_edge(&self, x: usize, y: usize) -> bool {
+        x == 0 || y == 0 || x + 1 == self.width || y + 1 == self.height
+    }
+
+    /// The directions that the border's lines go in from a cell on the edge of the panel
+    fn lines_at(&self, x: usize, y: usize) -> u8 {
+        let horizontal = y == 0 || y + 1 == self.height;
+        let vertical = x == 0 || x + 1 == self.width;
+        let mut lines = 0;
+        if horizontal && x > 0 {
+            lines |= LEFT;
+        }
+        if horizontal && x + 1 < self.width {
+            lines |= RIGHT;
+        }
+        if vertical && y > 0 {
+            lines |= UP;
+        }
+        if vertical && y + 1 < self.height {
+            lines |= DOWN;
+        }
+        lines
+    }
+
+    fn edge_cells(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.height).flat_map(move |y| {
+            (0..self.width)
+                .filter(move |x| self.is_edge(*x, y))
+                .map(move |x| (x, y))
+        })
+    }
+
+    /// The border piece for a cell on the edge of the panel. Lines are the directions that other
+    /// panels' borders go in from cells on this panel's edge, keyed by their offset from the panel's
+    /// position.
+    fn edge_piece(&self, x: usize, y: usize, lines: &HashMap<(usize, usize), u8>) -> char {
+        match &self.border {
+            Border::Chars(chars) => match (x, y) {
+                (0, 0) => chars.top_left,
+                (x, 0) if x + 1 == self.width => chars.top_right,
+                (0, y) if y + 1 == self.height => chars.bottom_left,
+                (x, y) if x + 1 == self.width && y + 1 == self.height => chars.bottom_right,
+                (_, 0) => chars.top,
+                (_, y) if y + 1 == self.height => chars.bottom,
+                (0, _) => chars.left,
+                _ => chars.right,
+            },
+            border => {
+                let joined = self.lines_at(x, y) | lines.get(&(x, y)).copied().unwrap_or(0);
+                border.lines().map_or(' ', |chars| chars[joined as usize])
+            }
+        }
+    }
+
+    /// Generates the panel's sprite and stylemap
+    pub(crate) fn generate(
+        &self,
+        sprites: &Assets<Sprite>,
+        lines: &HashMap<(usize, usize), u8>,
+    ) -> (String, StyleMap) {
+        let nine_slice_sprite = match &self.border {
+            Border::NineSlice(handle) => sprites.get(handle),
+            _ => None,
+        };
+        let mut cells: Vec<Vec<String>> = (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| match nine_slice_sprite {
+                        Some(sprite) => nine_slice(sprite, self.width, self.height, x, y),
+                        None if self.is_edge(x, y) => self.edge_piece(x, y, lines).to_string(),
+                        None => self.fill.to_string(),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        if let Some(title) = self.title.as_ref().filter(|_| self.height > 0) {
+            // Leave room for the corners, and a space on either side of the title
+            let room = self.width.saturating_sub(4);
+            let graphemes: Vec<&str> = title.graphemes(true).take(room).collect();
+            if !graphemes.is_empty() {
+                let len = graphemes.len();
+                let space = room - len;
+                let start = 2 + match self.title_align {
+                    TextAlign::Left => 0,
+                    TextAlign::Center => space / 2,
+                    TextAlign::Right => space,
+                };
+                cells[0][start - 1] = " ".to_string();
+                cells[0][start + len] = " ".to_string();
+                for (i, grapheme) in graphemes.into_iter().enumerate() {
+                    cells[0][start + i] = grapheme.to_string();
+                }
+            }
+        }
+
+        let sprite = cells
+            .iter()
+            .map(|row| row.concat())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let border_style = if self.border_style == self.fill_style {
+            None
+        } else {
+            Some(self.border_style)
+        };
+        let styles = (0..self.height).map(|y| {
+            (0..self.width).map(move |x| {
+                if self.is_edge(x, y) {
+                    border_style
+                } else {
+                    None
+                }
+            })
+        });
+        let map = StyleGrid::from_cells_or_full(styles, self.fill_style);
+
+        (sprite, StyleMap::with_grid(self.fill_style, map))
+    }
+}
+
+/// Picks the cell of a nine-slice sprite which covers the cell at x,y of a panel
+fn nine_slice(sprite: &Sprite, width: usize, height: usize, x: usize, y: usize) -> String {
+    let slice = |at: usize, size: usize, source_size: usize| {
+        if at == 0 || source_size < 3 {
+            at.min(source_size.saturating_sub(1))
+        } else if at + 1 == size {
+            source_size - 1
+        } else {
+            1 + (at - 1) % (source_size - 2)
+        }
+    };
+
+    let row = match sprite.graphemes().get(slice(y, height, sprite.height())) {
+        Some(row) => row,
+        None => return " ".to_string(),
+    };
+    match row.get(slice(x, width, sprite.width())) {
+        Some(grapheme) => sprite.grapheme(grapheme).to_string(),
+        None => " ".to_string(),
+    }
+}
+
+// Where a panel covers the screen, and the z it's at. Borders only join up with panels at the same z
+fn panel_bounds(panel: &Panel, position: &Position) -> (ScreenRect, i32) {
+    let rect = ScreenRect {
+        x: position.x,
+        y: position.y,
+        width: panel.width as i32,
+        height: panel.height as i32,
+    };
+    (rect, position.z)
+}
+
+fn overlaps(a: &(ScreenRect, i32), b: &(ScreenRect, i32)) -> bool {
+    let overlap = a.0.intersect(&b.0);
+    a.1 == b.1 && overlap.width > 0 && overlap.height > 0
+}
+
+pub(crate) fn update_panels(
+    mut known_bounds: Local<HashMap<Entity, (ScreenRect, i32)>>,
+    mut sprite_reader: Local<EventReader<AssetEvent<Sprite>>>,
+    sprite_events: Res<Events<AssetEvent<Sprite>>>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    changed: Query<Entity, (With<Panel>, Or<(Changed<Panel>, Changed<Position>)>)>,
+    mut panels: Query<(
+        Entity,
+        &Panel,
+        &Position,
+        &mut Handle<Sprite>,
+        &mut Handle<StyleMap>,
+    )>,
+) {
+    // Nine-slice borders have to be regenerated once their sprite loads or when it changes
+    let changed_sprites: HashSet<_> = sprite_reader
+        .iter(&sprite_events)
+        .filter_map(|event| match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => Some(handle),
+            AssetEvent::Removed { .. } => None,
+        })
+        .collect();
+
+    // Panels join up with each other, so the panels which changed are regenerated along with every
+    // panel they overlap, both where they are now and where they were. Sprites are only redrawn if
+    // they actually changed
+    let mut regenerate = HashSet::default();
+    let mut dirty = Vec::new();
+    let mut bounds = HashMap::default();
+    for (entity, panel, position, ..) in panels.iter_mut() {
+        let current = panel_bounds(panel, position);
+        let previous = known_bounds.remove(&entity);
+        let nine_slice_changed = match &panel.border {
+            Border::NineSlice(handle) => changed_sprites.contains(handle),
+            _ => false,
+        };
+        if changed.get(entity).is_ok() || nine_slice_changed || previous != Some(current) {
+            regenerate.insert(entity);
+            dirty.push(current);
+            dirty.extend(previous);
+        }
+        bounds.insert(entity, current);
+    }
+    // Whatever's left was despawned, or isn't a panel anymore
+    dirty.extend(known_bounds.drain().map(|(_, previous)| previous));
+    *known_bounds = bounds;
+    if dirty.is_empty() {
+        return;
+    }
+
+    for (entity, current) in known_bounds.iter() {
+        if dirty.iter().any(|dirty| overlaps(current, dirty)) {
+            regenerate.insert(*entity);
+        }
+    }
+
+    // The directions of every line drawn by a panel's border, keyed by where they are on the screen.
+    // Only panels overlapping one that's regenerated can join up with it
+    let mut lines: HashMap<(i32, i32, i32), u8> = HashMap::default();
+    for (entity, panel, position, ..) in panels.iter_mut() {
+        let current = &known_bounds[&entity];
+        let joins = regenerate
+            .iter()
+            .any(|regenerated| overlaps(current, &known_bounds[regenerated]));
+        if panel.border.lines().is_none() || !joins {
+            continue;
+        }
+        for (x, y) in panel.edge_cells() {
+            let cell = (position.x + x as i32, position.y + y as i32, position.z);
+            *lines.entry(cell).or_insert(0) |= panel.lines_at(x, y);
+        }
+    }
+
+    for (entity, panel, position, mut sprite, mut stylemap) in panels.iter_mut() {
+        if !regenerate.contains(&entity) {
+            continue;
+        }
+        let panel_lines = panel
+            .edge_cells()
+            .filter_map(|(x, y)| {
+                let cell = (position.x + x as i32, position.y + y as i32, position.z);
+                lines.get(&cell).map(|lines| ((x, y), *lines))
+            })
+            .collect();
+        let (generated_sprite, generated_stylemap) = panel.generate(&sprites, &panel_lines);
+        components::set_sprite(&mut sprites, &mut sprite, generated_sprite);
+        components::set_stylemap(&mut stylemaps, &mut stylemap, generated_stylemap);
+    }
+}
@@ -0,0 +1,600 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::components::{
+    Color, DrawArea, EntitiesToRedraw, EntityDepth, ParticleDetails, Position, ScreenRect, Visible,
+};
+use crate::layers::{Layers, RenderLayers};
+use crate::tween;
+use crate::viewport::ActiveViewports;
+
+/// How many colors a particle goes through as it fades from one of its emitter's colors to the
+/// next. Particles are only drawn again when they move or their color changes, so the fade is
+/// rounded to these.
+pub const COLOR_STEPS: usize = 8;
+
+/// Spawns particles from around the entity's position, for effects like explosions, rain and sparks.
+/// Every particle changes its grapheme and color as it ages, and falls with gravity.
+///
+/// An emitter's particles aren't entities. They're kept in a Particles component on an entity of
+/// their own, which is spawned for the emitter, and the renderer draws them cell by cell like the
+/// tiles of a tilemap, so only the cells particles move into, out of, or change color in are drawn
+/// again. That entity is on the same render layers as the emitter, or the effects layer if it has
+/// none, and particles are left where they are when the emitter moves. It's despawned once the
+/// emitter is gone and its last particle has died, and spawned again if it's despawned before then.
+///
+/// ```ignore
+/// let mut explosion = ParticleEmitter::new(0.0, Duration::from_millis(800))
+///     .with_velocity((0.0, 0.0), (12.0, 6.0))
+///     .with_gravity((0.0, 10.0))
+///     .with_graphemes(&["*", "+", "."])
+///     .with_colors(vec![Color::Yellow, Color::Red, Color::DarkGrey]);
+/// explosion.burst(60);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParticleEmitter {
+    /// How many particles are spawned a second
+    pub rate: f32,
+    pub lifetime: Duration,
+    /// How much longer or shorter than `lifetime` each particle can live
+    pub lifetime_spread: Duration,
+    /// Where particles are spawned, as the size of the area to the right of and below the emitter's
+    /// position, in cells
+    pub area: (f32, f32),
+    /// How fast particles move when they're spawned, in cells a second
+    pub velocity: (f32, f32),
+    /// How much faster or slower than `velocity` each particle can move, across and down
+    pub velocity_spread: (f32, f32),
+    /// How much faster particles move every second, in cells a second
+    pub gravity: (f32, f32),
+    /// The graphemes a particle goes through over its lifetime, each for an equal part of it. They
+    /// should be one cell wide.
+    pub graphemes: Vec<String>,
+    /// The foreground colors a particle fades through over its lifetime, in `COLOR_STEPS` steps from
+    /// each to the next. RGB, named and ANSI colors are mixed as RGB. Particles without colors are
+    /// drawn in the colors of whatever's underneath.
+    pub colors: Vec<Color>,
+    /// The most particles there can be at once. No more are spawned until some have died.
+    pub max_particles: usize,
+    /// Stops spawning particles while it's false, leaving the ones that are already there to die
+    pub active: bool,
+    // Particles that are owed, from the rate and bursts
+    to_spawn: f32,
+    rng: u64,
+    cloud: Option<Entity>,
+}
+
+impl ParticleEmitter {
+    pub fn new(rate: f32, lifetime: Duration) -> ParticleEmitter {
+        ParticleEmitter {
+            rate,
+            lifetime,
+            lifetime_spread: Duration::from_secs(0),
+            area: (0.0, 0.0),
+            velocity: (0.0, 0.0),
+            velocity_spread: (0.0, 0.0),
+            gravity: (0.0, 0.0),
+            graphemes: vec!["*".to_string()],
+            colors: Vec::new(),
+            max_particles: 500,
+            active: true,
+            to_spawn: 0.0,
+            rng: 0,
+            cloud: None,
+        }
+    }
+
+    pub fn with_lifetime_spread(mut self, spread: Duration) -> ParticleEmitter {
+        self.lifetime_spread = spread;
+        self
+    }
+
+    pub fn with_area(mut self, area: (f32, f32)) -> ParticleEmitter {
+        self.area = area;
+        self
+    }
+
+    pub fn with_velocity(mut self, velocity: (f32, f32), spread: (f32, f32)) -> ParticleEmitter {
+        self.velocity = velocity;
+        self.velocity_spread = spread;
+        self
+    }
+
+    pub fn with_gravity(mut self, gravity: (f32, f32)) -> ParticleEmitter {
+        self.gravity = gravity;
+        self
+    }
+
+    pub fn with_graphemes(mut self, graphemes: &[&str]) -> ParticleEmitter {
+        self.graphemes = graphemes.iter().map(|g| g.to_string()).collect();
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> ParticleEmitter {
+        self.colors = colors;
+        self
+    }
+
+    pub fn with_max_particles(mut self, max_particles: usize) -> ParticleEmitter {
+        self.max_particles = max_particles;
+        self
+    }
+
+    /// Spawns a number of particles all at once, the next time the emitter updates, even if it isn't
+    /// active
+    pub fn burst(&mut self, count: u32) {
+        self.to_spawn += count as f32;
+    }
+
+    // A number from 0 to 1, from an xorshift generator seeded by the emitter's entity
+    fn random(&mut self, entity: Entity) -> f32 {
+        if self.rng == 0 {
+            self.rng = (entity.id() as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // A number up to `spread` either side of `value`
+    fn spread(&mut self, entity: Entity, value: f32, spread: f32) -> f32 {
+        value + (self.random(entity) * 2.0 - 1.0) * spread
+    }
+
+    fn spawn(&mut self, entity: Entity, origin: (f32, f32)) -> Particle {
+        let lifetime = self.spread(
+            entity,
+            self.lifetime.as_secs_f32(),
+            self.lifetime_spread.as_secs_f32(),
+        );
+        Particle {
+            x: origin.0 + self.random(entity) * self.area.0,
+            y: origin.1 + self.random(entity) * self.area.1,
+            velocity: (
+                self.spread(entity, self.velocity.0, self.velocity_spread.0),
+                self.spread(entity, self.velocity.1, self.velocity_spread.1),
+            ),
+            gravity: self.gravity,
+            age: 0.0,
+            lifetime: lifetime.max(0.0),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Particle {
+    x: f32,
+    y: f32,
+    velocity: (f32, f32),
+    gravity: (f32, f32),
+    age: f32,
+    lifetime: f32,
+}
+
+// How a cell of a cloud is drawn, as the index of its grapheme and its foreground color
+type Look = (usize, Option<Color>);
+
+/// The particles of an emitter, on the entity they're drawn by. They're drawn relative to its
+/// position, which starts where the emitter was when it was spawned.
+#[derive(Clone, Debug)]
+pub struct Particles {
+    pub emitter: Entity,
+    particles: Vec<Particle>,
+    graphemes: Vec<String>,
+    colors: Vec<Color>,
+    // How each cell with a particle in it looks. Particles on the same cell are drawn over the ones
+    // spawned before them, so only the last one's kept.
+    cells: HashMap<(i32, i32), Look>,
+    // The cells that changed since they were last drawn
+    dirty: HashSet<(i32, i32)>,
+}
+
+impl Particles {
+    fn new(emitter: Entity, graphemes: Vec<String>, colors: Vec<Color>) -> Particles {
+        Particles {
+            emitter,
+            particles: Vec::new(),
+            graphemes,
+            colors,
+            cells: HashMap::default(),
+            dirty: HashSet::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    // What a particle looks like, from how far through its life it is
+    fn look(&self, particle: &Particle) -> Look {
+        let t = if particle.lifetime > 0.0 {
+            (particle.age / particle.lifetime).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let index = |len: usize| ((t * len as f32) as usize).min(len.saturating_sub(1));
+        let grapheme = index(self.graphemes.len());
+
+        let color = match self.colors.len() {
+            0 => None,
+            1 => Some(self.colors[0]),
+            len => {
+                // Rounded to a step, so a particle's color only changes a few times on its way from
+                // one color to the next
+                let steps = COLOR_STEPS.max(1) as f32;
+                let position = (t * (len - 1) as f32 * steps).floor() / steps;
+                let from = (position as usize).min(len - 2);
+                tween::lerp_color(
+                    Some(self.colors[from]),
+                    Some(self.colors[from + 1]),
+                    position - from as f32,
+                )
+            }
+        };
+        (grapheme, color)
+    }
+
+    /// Works out how each cell looks from the particles, and marks the cells that look different
+    fn update_cells(&mut self) {
+        let mut cells = HashMap::default();
+        for particle in self.particles.iter() {
+            let cell = (particle.x.round() as i32, particle.y.round() as i32);
+            cells.insert(cell, self.look(particle));
+        }
+
+        for (cell, look) in self.cells.iter() {
+            if cells.get(cell) != Some(look) {
+                self.dirty.insert(*cell);
+            }
+        }
+        for (cell, look) in cells.iter() {
+            if self.cells.get(cell) != Some(look) {
+                self.dirty.insert(*cell);
+            }
+        }
+        self.cells = cells;
+    }
+
+    /// The cells with particles in them, relative to the entity's position, with the grapheme and
+    /// foreground color each is drawn with
+    pub(crate) fn cells(&self) -> impl Iterator<Item = ((i32, i32), &str, Option<Color>)> {
+        self.cells.iter().map(move |(cell, (grapheme, color))| {
+            let grapheme = self.graphemes.get(*grapheme).map_or("*", String::as_str);
+            (*cell, grapheme, *color)
+        })
+    }
+}
+
+/// Turns a set of cells into rectangles, one for each run of cells next to each other on a line
+fn cell_rects<I: IntoIterator<Item = (i32, i32)>>(cells: I) -> Vec<ScreenRect> {
+    let mut cells: Vec<(i32, i32)> = cells.into_iter().map(|(x, y)| (y, x)).collect();
+    cells.sort_unstable();
+    cells.dedup();
+
+    let mut rects: Vec<ScreenRect> = Vec::new();
+    for (y, x) in cells {
+        match rects.last_mut() {
+            Some(rect) if rect.y == y && rect.right() == x => rect.width += 1,
+            _ => rects.push(ScreenRect {
+                x,
+                y,
+                width: 1,
+                height: 1,
+            }),
+        }
+    }
+    rects
+}
+
+// What an emitter hands to its particles' entity each frame
+struct Emitted {
+    particles: Vec<Particle>,
+    // Where the particles were spawned from, since they're spawned relative to the emitter and drawn
+    // relative to the entity they're on
+    origin: (i32, i32),
+    z: i32,
+    max_particles: usize,
+    graphemes: Vec<String>,
+    colors: Vec<Color>,
+}
+
+pub(crate) fn update_particles(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut entities: QuerySet<(
+        Query<(
+            Entity,
+            &mut ParticleEmitter,
+            &Position,
+            Option<&RenderLayers>,
+        )>,
+        Query<(Entity, &mut Particles, &mut Position)>,
+    )>,
+) {
+    let seconds = time.delta_seconds();
+
+    // The entities particles are drawn by, so an emitter whose entity was despawned makes another
+    let clouds: HashSet<Entity> = entities.q1().iter().map(|(entity, _, _)| entity).collect();
+
+    // What each emitter spawned, by the entity its particles are drawn by
+    let mut emitted: HashMap<Entity, Emitted> = HashMap::default();
+    let mut emitters: HashSet<Entity> = HashSet::default();
+    for (entity, mut emitter, position, layers) in entities.q0_mut().iter_mut() {
+        emitters.insert(entity);
+        let cloud = match emitter.cloud.filter(|cloud| clouds.contains(cloud)) {
+            Some(cloud) => cloud,
+            None => {
+                // Particles are spawned from the next frame, once there's somewhere to put them
+                commands.spawn((
+                    Particles::new(entity, emitter.graphemes.clone(), emitter.colors.clone()),
+                    Position::new(position.x, position.y, position.z),
+                    Visible::default(),
+                    layers.copied().unwrap_or_else(RenderLayers::effects),
+                ));
+                emitter.cloud = commands.current_entity();
+                continue;
+            }
+        };
+
+        if emitter.active {
+            emitter.to_spawn += emitter.rate * seconds;
+        }
+        let mut particles = Vec::new();
+        while emitter.to_spawn >= 1.0 {
+            emitter.to_spawn -= 1.0;
+            particles.push(emitter.spawn(entity, (0.0, 0.0)));
+        }
+        emitted.insert(
+            cloud,
+            Emitted {
+                particles,
+                origin: (position.x, position.y),
+                z: position.z,
+                max_particles: emitter.max_particles,
+                graphemes: emitter.graphemes.clone(),
+                colors: emitter.colors.clone(),
+            },
+        );
+    }
+
+    for (entity, mut particles, mut position) in entities.q1_mut().iter_mut() {
+        for particle in particles.particles.iter_mut() {
+            particle.age += seconds;
+            particle.velocity.0 += particle.gravity.0 * seconds;
+            particle.velocity.1 += particle.gravity.1 * seconds;
+            particle.x += particle.velocity.0 * seconds;
+            particle.y += particle.velocity.1 * seconds;
+        }
+        particles
+            .particles
+            .retain(|particle| particle.age < particle.lifetime);
+
+        if let Some(emitted) = emitted.remove(&entity) {
+            // Particles that would go over the limit aren't spawned
+            let room = emitted
+                .max_particles
+                .saturating_sub(particles.particles.len());
+            let offset = (
+                (emitted.origin.0 - position.x) as f32,
+                (emitted.origin.1 - position.y) as f32,
+            );
+            particles
+                .particles
+                .extend(
+                    emitted
+                        .particles
+                        .into_iter()
+                        .take(room)
+                        .map(|mut particle| {
+                            particle.x += offset.0;
+                            particle.y += offset.1;
+                            particle
+                        }),
+                );
+            // Every cell is drawn again if the emitter's graphemes or colors changed, since the
+            // cells only keep which of them they're drawn with
+            if particles.graphemes != emitted.graphemes || particles.colors != emitted.colors {
+                let cells: Vec<(i32, i32)> = particles.cells.keys().copied().collect();
+                particles.dirty.extend(cells);
+                particles.graphemes = emitted.graphemes;
+                particles.colors = emitted.colors;
+            }
+            if position.z != emitted.z {
+                position.z = emitted.z;
+            }
+        }
+
+        particles.update_cells();
+
+        // The cells the last particles were in have to be blanked before the entity goes
+        if particles.is_empty()
+            && particles.dirty.is_empty()
+            && !emitters.contains(&particles.emitter)
+        {
+            commands.despawn(entity);
+        }
+    }
+}
+
+/// Finds where particle clouds are on the screen and which of their cells changed, so the redraw
+/// logic only draws those cells again
+pub(crate) fn particle_changes(
+    mut entities: ResMut<EntitiesToRedraw>,
+    mut drawn: Local<HashMap<Entity, Vec<ScreenRect>>>,
+    viewports: Res<ActiveViewports>,
+    layers: Res<Layers>,
+    mut clouds: Query<(
+        Entity,
+        &mut Particles,
+        &Position,
+        &Visible,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
+    changed: Query<
+        Entity,
+        (
+            With<Particles>,
+            Or<(
+                Mutated<Position>,
+                Mutated<Visible>,
+                Changed<DrawArea>,
+                Changed<RenderLayers>,
+            )>,
+        ),
+    >,
+) {
+    // Moving or hiding a cloud moves or hides all of its particles at once, so it's simplest to
+    // redraw everything
+    entities.particles_changed = changed.iter().next().is_some();
+
+    entities.particles.clear();
+    // Clouds that were despawned leave the cells they were last drawn in to be blanked
+    for entity in clouds.removed::<Particles>() {
+        let vacated = drawn.remove(entity).unwrap_or_default();
+        if !vacated.is_empty() {
+            entities.particles.push(ParticleDetails {
+                depth: EntityDepth {
+                    entity: *entity,
+                    layer: 0,
+                    z: 0,
+                },
+                rects: Vec::new(),
+                vacated,
+                covered: Vec::new(),
+            });
+        }
+    }
+
+    for (entity, mut particles, position, visible, area, render_layers) in clouds.iter_mut() {
+        let dirty = std::mem::take(&mut particles.dirty);
+        if !visible.is_visible {
+            drawn.remove(&entity);
+            continue;
+        }
+
+        let screen_rects = |cells: Vec<ScreenRect>| -> Vec<ScreenRect> {
+            cells
+                .iter()
+                .flat_map(|cells| viewports.screen_rects(position, cells, area, render_layers))
+                .collect()
+        };
+        let rects = screen_rects(cell_rects(particles.cells.keys().copied()));
+        // Cells that still have a particle in them are drawn over, and the rest are blanked
+        let (covered, vacated): (Vec<(i32, i32)>, Vec<(i32, i32)>) = dirty
+            .into_iter()
+            .partition(|cell| particles.cells.contains_key(cell));
+        entities.particles.push(ParticleDetails {
+            depth: EntityDepth {
+                entity,
+                layer: layers.rank(render_layers),
+                z: position.z,
+            },
+            rects: rects.clone(),
+            vacated: screen_rects(cell_rects(vacated)),
+            covered: screen_rects(cell_rects(covered)),
+        });
+        drawn.insert(entity, rects);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle(x: f32, y: f32, age: f32) -> Particle {
+        Particle {
+            x,
+            y,
+            velocity: (0.0, 0.0),
+            gravity: (0.0, 0.0),
+            age,
+            lifetime: 1.0,
+        }
+    }
+
+    fn particles(graphemes: &[&str], colors: Vec<Color>) -> Particles {
+        Particles::new(
+            Entity::new(0),
+            graphemes.iter().map(|g| g.to_string()).collect(),
+            colors,
+        )
+    }
+
+    #[test]
+    fn graphemes_go_by_age() {
+        let particles = particles(&["*", "+", "."], Vec::new());
+        assert_eq!(particles.look(&particle(0.0, 0.0, 0.0)), (0, None));
+        assert_eq!(particles.look(&particle(0.0, 0.0, 0.5)), (1, None));
+        assert_eq!(particles.look(&particle(0.0, 0.0, 0.99)), (2, None));
+        assert_eq!(particles.look(&particle(0.0, 0.0, 1.0)), (2, None));
+    }
+
+    #[test]
+    fn colors_fade_in_steps() {
+        let black = Color::Rgb { r: 0, g: 0, b: 0 };
+        let white = Color::Rgb {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let particles = particles(&["*"], vec![black, white]);
+        let color = |age| particles.look(&particle(0.0, 0.0, age)).1;
+
+        assert_eq!(color(0.0), Some(black));
+        assert_eq!(color(1.0), Some(white));
+        // Ages within a step look the same
+        assert_eq!(color(0.5), color(0.6));
+        assert_ne!(color(0.5), color(0.4));
+        let mut colors: Vec<Option<Color>> =
+            (0..=100).map(|age| color(age as f32 / 100.0)).collect();
+        colors.dedup();
+        assert_eq!(colors.len(), COLOR_STEPS + 1);
+    }
+
+    #[test]
+    fn only_cells_that_look_different_are_dirty() {
+        let mut particles = particles(&["*", "."], Vec::new());
+        particles.particles = vec![particle(0.0, 0.0, 0.0), particle(2.0, 0.0, 0.0)];
+        particles.update_cells();
+        particles.dirty.clear();
+
+        // The first moves within its cell, and the second ages into its next grapheme
+        particles.particles = vec![particle(0.2, 0.0, 0.1), particle(2.0, 0.0, 0.6)];
+        particles.update_cells();
+        assert_eq!(particles.dirty, [(2, 0)].iter().copied().collect());
+        particles.dirty.clear();
+
+        // The first moves to another cell, which leaves its old one
+        particles.particles = vec![particle(1.0, 1.0, 0.1), particle(2.0, 0.0, 0.6)];
+        particles.update_cells();
+        assert_eq!(particles.dirty, [(0, 0), (1, 1)].iter().copied().collect());
+    }
+
+    #[test]
+    fn later_particles_are_drawn_over_earlier_ones() {
+        let mut particles = particles(&["*", "."], Vec::new());
+        particles.particles = vec![particle(0.0, 0.0, 0.0), particle(0.0, 0.0, 0.6)];
+        particles.update_cells();
+        let cells: Vec<_> = particles.cells().collect();
+        assert_eq!(cells, vec![((0, 0), ".", None)]);
+    }
+
+    #[test]
+    fn cells_next_to_each_other_make_one_rect() {
+        let rects = cell_rects(vec![(3, 0), (1, 0), (2, 0), (5, 0), (1, 1), (2, 0)]);
+        let rect = |x, y, width| ScreenRect {
+            x,
+            y,
+            width,
+            height: 1,
+        };
+        assert_eq!(rects, vec![rect(1, 0, 3), rect(5, 0, 1), rect(1, 1, 1)]);
+    }
+}
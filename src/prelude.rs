@@ -1,7 +1,36 @@
 pub use crate::{CrosstermPlugin, CrosstermWindow, Cursor, CrosstermWindowSettings};
 
 pub use crate::components::{
-    Color, Colors, Position, Sprite, SpriteBundle, Style, StyleMap, Visible,
+    Alpha, AlphaMask, Blend, Color, Colors, Position, Sprite, SpriteBundle, Style, StyleGrid,
+    StyleIndices, StyleMap, StylePalette, Visible,
+};
+
+pub use crate::camera::{Camera, CameraBounds, ScreenSpace};
+pub use crate::clip::{ClipRect, ScrollView};
+pub use crate::dialog::{Dialog, DialogChoice, DIALOG_Z};
+pub use crate::figlet::{BigText, FigletFont, FigletLayout};
+pub use crate::focus::{Focus, FocusGained, FocusLost, Focusable, Focused, FocusedKeyEvent, Modal};
+pub use crate::fractional::FractionalPosition;
+pub use crate::images::{ImageColors, ImageSettings};
+pub use crate::layers::{
+    Layers, RenderLayers, BACKGROUND_LAYER, EFFECTS_LAYER, OVERLAY_LAYER, UI_LAYER, WORLD_LAYER,
+};
+pub use crate::layout::{Anchor, Arrangement, Dimension, Edges, LayoutNode, LayoutSize};
+pub use crate::panel::{Border, BorderChars, Panel};
+pub use crate::particles::{ParticleEmitter, Particles};
+pub use crate::sprite_transform::{MirrorTable, Rotation, SpriteTransform};
+pub use crate::text::{RichText, Text, TextAlign, TextOverflow, TextSpan};
+pub use crate::text_input::{TextChanged, TextInput, TextSubmitted};
+pub use crate::tiled::{
+    TiledLayer, TiledLayerContent, TiledMap, TiledMapBundle, TiledMapLayer, TiledObject,
+    TiledProperties, TiledProperty,
+};
+pub use crate::tilemap::{Tile, Tilemap, TilemapBundle, Tileset};
+pub use crate::tween::{Ease, Repeat, Tween, TweenFinished, TweenStep, Tweenable};
+pub use crate::viewport::{Viewport, Viewports};
+pub use crate::widgets::{
+    Button, ButtonPressed, ButtonState, ButtonStyles, Checkbox, CheckboxToggled, Gauge, Label,
+    List, ListActivated, ListSelected, ProgressBar,
 };
 
 // Re-export crossterm structs for easier access
@@ -0,0 +1,632 @@
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::components::{Alpha, AlphaMask, Sprite, Style, StyleGrid, StyleMap};
+
+/// How graphemes change when sprites are flipped or rotated, so slopes, brackets and box drawing
+/// corners still point the right way afterwards. The default table covers the common ASCII and box
+/// drawing characters, and more can be added with the `with_` methods.
+///
+/// The MirrorTable resource is the table SpriteTransform uses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MirrorTable {
+    /// What each grapheme becomes when a sprite is flipped left to right
+    pub horizontal: HashMap<String, String>,
+    /// What each grapheme becomes when a sprite is flipped upside down
+    pub vertical: HashMap<String, String>,
+    /// What each grapheme becomes when a sprite is rotated a quarter turn clockwise
+    pub clockwise: HashMap<String, String>,
+}
+
+impl Default for MirrorTable {
+    fn default() -> Self {
+        let horizontal = [
+            ("/", "\\"),
+            ("(", ")"),
+            ("[", "]"),
+            ("{", "}"),
+            ("<", ">"),
+            ("╱", "╲"),
+            ("┌", "┐"),
+            ("└", "┘"),
+            ("├", "┤"),
+            ("┏", "┓"),
+            ("┗", "┛"),
+            ("┣", "┫"),
+            ("╭", "╮"),
+            ("╰", "╯"),
+            ("╔", "╗"),
+            ("╚", "╝"),
+            ("╠", "╣"),
+            ("▌", "▐"),
+            ("▘", "▝"),
+            ("▖", "▗"),
+            ("◀", "▶"),
+            ("←", "→"),
+        ];
+        let vertical = [
+            ("/", "\\"),
+            ("╱", "╲"),
+            ("┌", "└"),
+            ("┐", "┘"),
+            ("┬", "┴"),
+            ("┏", "┗"),
+            ("┓", "┛"),
+            ("┳", "┻"),
+            ("╭", "╰"),
+            ("╮", "╯"),
+            ("╔", "╚"),
+            ("╗", "╝"),
+            ("╦", "╩"),
+            ("▀", "▄"),
+            ("▘", "▖"),
+            ("▝", "▗"),
+            ("▲", "▼"),
+            ("↑", "↓"),
+        ];
+        let clockwise: [&[&str]; 17] = [
+            &["-", "|"],
+            &["/", "\\"],
+            &["╱", "╲"],
+            &["─", "│"],
+            &["━", "┃"],
+            &["═", "║"],
+            &["┌", "┐", "┘", "└"],
+            &["├", "┬", "┤", "┴"],
+            &["┏", "┓", "┛", "┗"],
+            &["┣", "┳", "┫", "┻"],
+            &["╭", "╮", "╯", "╰"],
+            &["╔", "╗", "╝", "╚"],
+            &["╠", "╦", "╣", "╩"],
+            &["▀", "▐", "▄", "▌"],
+            &["▘", "▝", "▗", "▖"],
+            &["▲", "▶", "▼", "◀"],
+            &["↑", "→", "↓", "←"],
+        ];
+
+        let mut table = MirrorTable::empty();
+        for (left, right) in horizontal.iter() {
+            table = table.with_horizontal(left, right);
+        }
+        for (top, bottom) in vertical.iter() {
+            table = table.with_vertical(top, bottom);
+        }
+        for cycle in clockwise.iter() {
+            table = table.with_rotation(cycle);
+        }
+        table
+    }
+}
+
+impl MirrorTable {
+    /// A table which leaves every grapheme as it is
+    pub fn empty() -> MirrorTable {
+        MirrorTable {
+            horizontal: HashMap::default(),
+            vertical: HashMap::default(),
+            clockwise: HashMap::default(),
+        }
+    }
+
+    /// Adds a pair of graphemes which turn into each other when a sprite is flipped left to right
+    pub fn with_horizontal(mut self, left: &str, right: &str) -> MirrorTable {
+        self.horizontal.insert(left.to_string(), right.to_string());
+        self.horizontal.insert(right.to_string(), left.to_string());
+        self
+    }
+
+    /// Adds a pair of graphemes which turn into each other when a sprite is flipped upside down
+    pub fn with_vertical(mut self, top: &str, bottom: &str) -> MirrorTable {
+        self.vertical.insert(top.to_string(), bottom.to_string());
+        self.vertical.insert(bottom.to_string(), top.to_string());
+        self
+    }
+
+    /// Adds graphemes which each turn into the next one when a sprite is rotated a quarter turn
+    /// clockwise, and the last into the first, like `["┌", "┐", "┘", "└"]`
+    pub fn with_rotation(mut self, cycle: &[&str]) -> MirrorTable {
+        for (i, grapheme) in cycle.iter().enumerate() {
+            let next = cycle[(i + 1) % cycle.len()];
+            self.clockwise
+                .insert(grapheme.to_string(), next.to_string());
+        }
+        self
+    }
+}
+
+/// How far a sprite is turned
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Rotation {
+    None,
+    Clockwise,
+    HalfTurn,
+    Counterclockwise,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation::None
+    }
+}
+
+/// Flips and rotates the entity's sprite and stylemap when it's drawn, so the same art can face
+/// different ways. Flips are applied before the rotation, and graphemes are changed to match with
+/// the MirrorTable resource.
+///
+/// The entity keeps its sprite and stylemap, and is drawn with transformed copies of them, which are
+/// made again whenever the transform or the originals change. So it works the same on sprites that
+/// are loaded, set by hand, or made by components like Text. The copies are also what's used for
+/// the entity's size on the screen, so rotating a sprite swaps its width and height.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SpriteTransform {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub rotation: Rotation,
+    /// Moves full blocks (`█`) right by this many eighths of a cell, from 0 to 7, after flipping and
+    /// rotating. The ends of each run of blocks are drawn with eighth blocks, so bars can move
+    /// smoothly. FractionalPosition sets this when its `eighths` is on. Like any change to the
+    /// transform, each new value makes the transformed copies again and redraws the entity.
+    pub eighths: u8,
+}
+
+impl SpriteTransform {
+    pub fn flip_x() -> SpriteTransform {
+        SpriteTransform {
+            flip_x: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn flip_y() -> SpriteTransform {
+        SpriteTransform {
+            flip_y: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn rotated(rotation: Rotation) -> SpriteTransform {
+        SpriteTransform {
+            rotation,
+            ..Default::default()
+        }
+    }
+}
+
+// A cell of a sprite, with its own style if it has one
+#[derive(Copy, Clone)]
+struct Cell<'a> {
+    grapheme: &'a str,
+    style: Option<Style>,
+    alpha: Alpha,
+}
+
+// Every cell of a sprite. Lines are padded out with spaces so the grid is rectangular
+type Grid<'a> = Vec<Vec<Cell<'a>>>;
+
+const FULL_BLOCK: &str = "█";
+// Blocks filled from the left, from one eighth of a cell up to seven
+const LEFT_EIGHTHS: [&str; 7] = ["▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+
+fn to_grid<'a>(sprite: &'a Sprite, stylemap: &StyleMap) -> Grid<'a> {
+    sprite
+        .graphemes()
+        .iter()
+        .enumerate()
+        .map(|(y, line)| {
+            (0..sprite.width())
+                .map(|x| Cell {
+                    grapheme: line
+                        .get(x)
+                        .map_or(" ", |grapheme| sprite.grapheme(grapheme)),
+                    style: stylemap.style_at(x, y).copied(),
+                    alpha: stylemap.alpha_at(x, y),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn from_grid(grid: Grid, stylemap: &StyleMap) -> (Sprite, StyleMap) {
+    let data: Vec<String> = grid
+        .iter()
+        .map(|row| row.iter().map(|cell| cell.grapheme).collect())
+        .collect();
+
+    // Cells can end up without a style anywhere in a row, which only a palette can hold
+    let styled = grid.iter().flatten().any(|cell| cell.style.is_some());
+    let map = if !styled {
+        StyleGrid::default()
+    } else {
+        let cells = grid.iter().map(|row| row.iter().map(|cell| cell.style));
+        StyleGrid::from_cells_or_full(cells, stylemap.style)
+    };
+    let mask = if stylemap.mask.is_empty() {
+        AlphaMask::default()
+    } else {
+        AlphaMask::new(
+            grid.iter()
+                .map(|row| row.iter().map(|cell| cell.alpha).collect())
+                .collect(),
+        )
+    };
+
+    let stylemap = StyleMap::with_grid(stylemap.style, map).with_mask(mask);
+    (Sprite::new(data.join("\n")), stylemap)
+}
+
+fn remap<'a>(cell: Cell<'a>, table: &'a HashMap<String, String>) -> Cell<'a> {
+    Cell {
+        grapheme: table
+            .get(cell.grapheme)
+            .map_or(cell.grapheme, String::as_str),
+        ..cell
+    }
+}
+
+fn flip_grid_horizontal<'a>(grid: Grid<'a>, table: &'a MirrorTable) -> Grid<'a> {
+    grid.into_iter()
+        .map(|row| {
+            row.into_iter()
+                .rev()
+                .map(|cell| remap(cell, &table.horizontal))
+                .collect()
+        })
+        .collect()
+}
+
+fn flip_grid_vertical<'a>(grid: Grid<'a>, table: &'a MirrorTable) -> Grid<'a> {
+    grid.into_iter()
+        .rev()
+        .map(|row| {
+            row.into_iter()
+                .map(|cell| remap(cell, &table.vertical))
+                .collect()
+        })
+        .collect()
+}
+
+fn rotate_grid_clockwise<'a>(grid: Grid<'a>, table: &'a MirrorTable) -> Grid<'a> {
+    let width = grid.first().map_or(0, Vec::len);
+    // The left column becomes the top row, read from the bottom up
+    (0..width)
+        .map(|x| {
+            grid.iter()
+                .rev()
+                .map(|row| remap(row[x], &table.clockwise))
+                .collect()
+        })
+        .collect()
+}
+
+// Each row grows by a cell, for the blocks at the end to poke out into. Only the terminal's
+// foreground can fill part of a cell from the left, so the first cell of a run is reversed, leaving
+// its background in the part the block has moved out of.
+fn shift_grid_right<'a>(grid: Grid<'a>, eighths: u8, base: Style) -> Grid<'a> {
+    let partial = LEFT_EIGHTHS[eighths as usize - 1];
+    let blank = Cell {
+        grapheme: " ",
+        style: None,
+        alpha: Alpha::Auto,
+    };
+    let is_block = |cell: Option<&Cell>| cell.map_or(false, |cell| cell.grapheme == FULL_BLOCK);
+    grid.into_iter()
+        .map(|row| {
+            (0..=row.len())
+                .map(|x| {
+                    let here = row.get(x);
+                    let left = x.checked_sub(1).and_then(|x| row.get(x));
+                    match (is_block(left), is_block(here)) {
+                        // Where a run of blocks pokes out past its end
+                        (true, false) => Cell {
+                            grapheme: partial,
+                            ..*left.unwrap()
+                        },
+                        // Where a run of blocks starts, and is only covered on the right
+                        (false, true) => {
+                            let here = here.unwrap();
+                            let mut style = here.style.unwrap_or(base);
+                            style.attributes.set(crossterm::style::Attribute::Reverse);
+                            Cell {
+                                grapheme: partial,
+                                style: Some(style),
+                                ..*here
+                            }
+                        }
+                        _ => here.copied().unwrap_or(blank),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn transform_grid<'a>(
+    mut grid: Grid<'a>,
+    transform: SpriteTransform,
+    table: &'a MirrorTable,
+    base: Style,
+) -> Grid<'a> {
+    if transform.flip_x {
+        grid = flip_grid_horizontal(grid, table);
+    }
+    if transform.flip_y {
+        grid = flip_grid_vertical(grid, table);
+    }
+    // Turning half way round is the same as flipping both ways, which also sorts out the graphemes
+    // that only mirror, like brackets
+    match transform.rotation {
+        Rotation::None => {}
+        Rotation::Clockwise => grid = rotate_grid_clockwise(grid, table),
+        Rotation::HalfTurn => {
+            grid = flip_grid_vertical(flip_grid_horizontal(grid, table), table);
+        }
+        Rotation::Counterclockwise => {
+            grid = flip_grid_vertical(flip_grid_horizontal(grid, table), table);
+            grid = rotate_grid_clockwise(grid, table);
+        }
+    }
+    match transform.eighths % 8 {
+        0 => {}
+        eighths => grid = shift_grid_right(grid, eighths, base),
+    }
+    grid
+}
+
+/// Flips, rotates and shifts a sprite and its stylemap. Lines shorter than the sprite are padded out
+/// with spaces first, and cells keep their own styles and alpha as they move.
+pub fn transform_sprite(
+    sprite: &Sprite,
+    stylemap: &StyleMap,
+    transform: SpriteTransform,
+    table: &MirrorTable,
+) -> (Sprite, StyleMap) {
+    let grid = transform_grid(to_grid(sprite, stylemap), transform, table, stylemap.style);
+    from_grid(grid, stylemap)
+}
+
+/// Flips a sprite and its stylemap left to right
+pub fn flip_horizontal(
+    sprite: &Sprite,
+    stylemap: &StyleMap,
+    table: &MirrorTable,
+) -> (Sprite, StyleMap) {
+    transform_sprite(sprite, stylemap, SpriteTransform::flip_x(), table)
+}
+
+/// Flips a sprite and its stylemap upside down
+pub fn flip_vertical(
+    sprite: &Sprite,
+    stylemap: &StyleMap,
+    table: &MirrorTable,
+) -> (Sprite, StyleMap) {
+    transform_sprite(sprite, stylemap, SpriteTransform::flip_y(), table)
+}
+
+/// Rotates a sprite and its stylemap. Cells are taller than they are wide, so rotated sprites look
+/// squashed or stretched.
+pub fn rotate(
+    sprite: &Sprite,
+    stylemap: &StyleMap,
+    rotation: Rotation,
+    table: &MirrorTable,
+) -> (Sprite, StyleMap) {
+    transform_sprite(sprite, stylemap, SpriteTransform::rotated(rotation), table)
+}
+
+/// The transformed copies of an entity's sprite and stylemap, which the renderer draws in place of
+/// the originals. The entity's handles are left alone, so whatever generates its sprite keeps
+/// writing to the originals.
+pub(crate) struct TransformedSprite {
+    pub sprite: Sprite,
+    pub stylemap: StyleMap,
+    // The originals the copies were made from, the revision the sprite was at, and the transform
+    source: (HandleId, HandleId),
+    revision: u64,
+    applied: SpriteTransform,
+}
+
+impl TransformedSprite {
+    fn is_current(
+        &self,
+        transform: SpriteTransform,
+        (sprite, stylemap): (&Handle<Sprite>, &Handle<StyleMap>),
+        source_sprite: &Sprite,
+        changed_stylemaps: &HashSet<HandleId>,
+    ) -> bool {
+        self.applied == transform
+            && self.source == (sprite.id, stylemap.id)
+            && self.revision == source_sprite.revision()
+            && !changed_stylemaps.contains(&stylemap.id)
+    }
+}
+
+/// The sprite and stylemap an entity is drawn with, which are the transformed copies if it has them
+pub(crate) fn drawn<'a>(
+    sprite: &'a Sprite,
+    stylemap: &'a StyleMap,
+    transformed: Option<&'a TransformedSprite>,
+) -> (&'a Sprite, &'a StyleMap) {
+    match transformed {
+        Some(transformed) => (&transformed.sprite, &transformed.stylemap),
+        None => (sprite, stylemap),
+    }
+}
+
+/// The sprite an entity is drawn with, for working out how big it is on the screen
+pub(crate) fn drawn_sprite<'a>(
+    sprites: &'a Assets<Sprite>,
+    sprite: &Handle<Sprite>,
+    transformed: Option<&'a TransformedSprite>,
+) -> Option<&'a Sprite> {
+    match transformed {
+        Some(transformed) => Some(&transformed.sprite),
+        None => sprites.get(sprite),
+    }
+}
+
+pub(crate) fn update_sprite_transforms(
+    commands: &mut Commands,
+    table: Res<MirrorTable>,
+    mut stylemap_reader: Local<EventReader<AssetEvent<StyleMap>>>,
+    stylemap_events: Res<Events<AssetEvent<StyleMap>>>,
+    sprites: Res<Assets<Sprite>>,
+    stylemaps: Res<Assets<StyleMap>>,
+    mut entities: Query<(
+        Entity,
+        &SpriteTransform,
+        &Handle<Sprite>,
+        &Handle<StyleMap>,
+        Option<&mut TransformedSprite>,
+    )>,
+    untransformed: Query<Entity, (With<TransformedSprite>, Without<SpriteTransform>)>,
+) {
+    // Sprites have revisions to tell when they've changed, but stylemaps only have their events
+    let mut changed_stylemaps = HashSet::default();
+    for event in stylemap_reader.iter(&stylemap_events) {
+        if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+            changed_stylemaps.insert(handle.id);
+        }
+    }
+
+    for (entity, transform, sprite, stylemap, transformed) in entities.iter_mut() {
+        // The originals load asynchronously, so this is tried again when they show up
+        let (source_sprite, source_stylemap) = match (sprites.get(sprite), stylemaps.get(stylemap))
+        {
+            (Some(source_sprite), Some(source_stylemap)) => (source_sprite, source_stylemap),
+            _ => continue,
+        };
+        let handles = (sprite, stylemap);
+        if let Some(transformed) = &transformed {
+            if transformed.is_current(*transform, handles, source_sprite, &changed_stylemaps) {
+                continue;
+            }
+        }
+
+        let (new_sprite, new_stylemap) =
+            transform_sprite(source_sprite, source_stylemap, *transform, &table);
+        let new_transformed = TransformedSprite {
+            sprite: new_sprite,
+            stylemap: new_stylemap,
+            source: (sprite.id, stylemap.id),
+            revision: source_sprite.revision(),
+            applied: *transform,
+        };
+        match transformed {
+            Some(mut transformed) => *transformed = new_transformed,
+            None => {
+                commands.insert_one(entity, new_transformed);
+            }
+        }
+    }
+
+    // When the transform is removed, the entity's drawn with its originals again
+    for entity in untransformed.iter() {
+        commands.remove_one::<TransformedSprite>(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Color;
+
+    fn transformed(data: &str, transform: SpriteTransform) -> String {
+        let table = MirrorTable::default();
+        let (sprite, _) =
+            transform_sprite(&Sprite::new(data), &StyleMap::default(), transform, &table);
+        sprite.data().to_string()
+    }
+
+    #[test]
+    fn mirrored_graphemes_turn_into_each_other() {
+        let table = MirrorTable::default();
+        for map in [&table.horizontal, &table.vertical].iter() {
+            for (from, to) in map.iter() {
+                assert_eq!(map.get(to), Some(from));
+            }
+        }
+        // Four quarter turns go all the way round
+        for start in table.clockwise.keys() {
+            let mut grapheme = start;
+            for _ in 0..4 {
+                grapheme = &table.clockwise[grapheme];
+            }
+            assert_eq!(grapheme, start);
+        }
+
+        let table = MirrorTable::empty().with_rotation(&["a", "b", "c"]);
+        assert_eq!(table.clockwise["c"], "a");
+        assert!(table.horizontal.is_empty());
+    }
+
+    #[test]
+    fn flips_mirror_graphemes() {
+        assert_eq!(transformed("/ab(", SpriteTransform::flip_x()), ")ba\\");
+        assert_eq!(transformed("┌─\n│▀", SpriteTransform::flip_y()), "│▄\n└─");
+        // Short lines are padded before they're flipped
+        assert_eq!(transformed("ab\nc", SpriteTransform::flip_x()), "ba\n c");
+    }
+
+    #[test]
+    fn rotations_agree_with_each_other() {
+        let art = "┌─┐x\n│▀│\n└─┘-";
+        let half_turn = transformed(art, SpriteTransform::rotated(Rotation::HalfTurn));
+        assert_eq!(
+            half_turn,
+            transformed(
+                art,
+                SpriteTransform {
+                    flip_x: true,
+                    flip_y: true,
+                    ..Default::default()
+                }
+            )
+        );
+        assert_eq!(
+            transformed(art, SpriteTransform::rotated(Rotation::Counterclockwise)),
+            transformed(&half_turn, SpriteTransform::rotated(Rotation::Clockwise))
+        );
+
+        let mut turned = art.to_string();
+        for _ in 0..4 {
+            turned = transformed(&turned, SpriteTransform::rotated(Rotation::Clockwise));
+        }
+        assert_eq!(turned, "┌─┐x\n│▀│ \n└─┘-");
+        assert_eq!(
+            transformed("ab\n-c", SpriteTransform::rotated(Rotation::Clockwise)),
+            "|a\ncb"
+        );
+    }
+
+    #[test]
+    fn styles_move_with_their_cells() {
+        let red = Style::with_fg(Color::Red);
+        let blue = Style::with_fg(Color::Blue);
+        let stylemap = StyleMap::new(Style::default(), vec![vec![red, blue]]);
+        let (sprite, stylemap) = rotate(
+            &Sprite::new("ab"),
+            &stylemap,
+            Rotation::Clockwise,
+            &MirrorTable::default(),
+        );
+        assert_eq!(sprite.data(), "a\nb");
+        assert!(stylemap.style_for(0, 0) == red);
+        assert!(stylemap.style_for(0, 1) == blue);
+    }
+
+    #[test]
+    fn eighths_shift_runs_of_blocks() {
+        let shifted = |eighths| {
+            transformed(
+                "██ █",
+                SpriteTransform {
+                    eighths,
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!(shifted(0), "██ █");
+        assert_eq!(shifted(3), "▍█▍▍▍");
+    }
+}
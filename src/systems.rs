@@ -2,12 +2,19 @@ use std::{convert::TryInto, io::Write};
 
 use crate::components::{self, Style};
 use crate::components::{
-    Colors, EntityDepth, Position, PreviousEntityDetails, PreviousPosition, PreviousSize,
-    PreviousWindowColors, Sprite, StyleMap, Visible,
+    Alpha, Color, Colors, EntityDepth, Position, PreviousEntityDetails, PreviousWindowColors,
+    ScreenCell, ScreenCells, Sprite, StyleMap, StyleOverride, Visible,
 };
+use crate::components::{DrawArea, ScreenRect};
+use crate::layers::{Layers, RenderLayers};
+use crate::particles::Particles;
+use crate::sprite_transform::{self, TransformedSprite};
+use crate::text::merge_styles;
+use crate::tilemap::{Tilemap, Tileset};
+use crate::viewport::ActiveViewports;
 use crate::{CrosstermWindow, Cursor};
 
-use bevy::utils::HashSet;
+use bevy::utils::{HashMap, HashSet};
 
 use bevy::prelude::*;
 use bevy::window::WindowResized;
@@ -16,25 +23,52 @@ use crossterm::{ExecutableCommand, QueueableCommand};
 
 use broccoli::prelude::*;
 
+/// Where on the screen an entity's sprite is drawn in each viewport, which is what gets cleared when
+/// it changes
+fn screen_details(
+    pos: &Position,
+    sprite: &Sprite,
+    area: Option<&DrawArea>,
+    layers: Option<&RenderLayers>,
+    viewports: &ActiveViewports,
+) -> Vec<ScreenRect> {
+    let cells = ScreenRect {
+        x: 0,
+        y: 0,
+        width: sprite.width() as i32,
+        height: sprite.height() as i32,
+    };
+    viewports.screen_rects(pos, &cells, area, layers)
+}
+
 pub(crate) fn add_previous_position(
     mut entities_without_assets: Local<HashSet<Entity>>,
     mut previous_details: ResMut<PreviousEntityDetails>,
     frames: Res<Assets<Sprite>>,
-    entities: Query<(Entity, &Position, &Handle<Sprite>), (Added<Position>, Added<Handle<Sprite>>)>,
-    all: Query<(&Position, &Handle<Sprite>)>,
+    viewports: Res<ActiveViewports>,
+    entities: Query<
+        (
+            Entity,
+            &Position,
+            &Handle<Sprite>,
+            Option<&DrawArea>,
+            Option<&RenderLayers>,
+        ),
+        (Added<Position>, Added<Handle<Sprite>>),
+    >,
+    all: Query<(
+        &Position,
+        &Handle<Sprite>,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
 ) {
-    for (entity, pos, sprite) in entities.iter() {
+    for (entity, pos, sprite, area, layers) in entities.iter() {
         if let Some(sprite) = frames.get(&*sprite) {
-            let prev_pos = components::PreviousPosition {
-                x: pos.x,
-                y: pos.y,
-                z: pos.z,
-            };
-            let prev_size = components::PreviousSize {
-                width: sprite.width() as u16,
-                height: sprite.graphemes().len() as u16,
-            };
-            previous_details.0.insert(entity, (prev_pos, prev_size));
+            previous_details.0.insert(
+                entity,
+                screen_details(pos, sprite, area, layers, &viewports),
+            );
         } else {
             // The asset hasn't loaded yet, so let's make a record of it for later
             entities_without_assets.insert(entity);
@@ -49,19 +83,13 @@ pub(crate) fn add_previous_position(
         if data.is_err() {
             continue;
         }
-        let (pos, sprite) = data.unwrap();
+        let (pos, sprite, area, layers) = data.unwrap();
 
         if let Some(sprite) = frames.get(&*sprite) {
-            let prev_pos = PreviousPosition {
-                x: pos.x,
-                y: pos.y,
-                z: pos.z,
-            };
-            let prev_size = PreviousSize {
-                width: sprite.width() as u16,
-                height: sprite.graphemes().len() as u16,
-            };
-            previous_details.0.insert(*entity, (prev_pos, prev_size));
+            previous_details.0.insert(
+                *entity,
+                screen_details(pos, sprite, area, layers, &viewports),
+            );
 
             // We need to remove this entity now, but can't since it's container is borrowed.
             {
@@ -79,34 +107,69 @@ pub(crate) fn add_previous_position(
 pub(crate) fn update_previous_position(
     mut previous_details: ResMut<PreviousEntityDetails>,
     frames: Res<Assets<Sprite>>,
-    mut positions: Query<(Entity, &Position, &Handle<Sprite>, &Visible)>,
+    viewports: Res<ActiveViewports>,
+    mut positions: Query<(
+        Entity,
+        &Position,
+        &Handle<Sprite>,
+        &Visible,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+        Option<&TransformedSprite>,
+    )>,
 ) {
-    for (entity, new_pos, sprite, _) in positions.iter_mut() {
-        if let Some(sprite) = frames.get(sprite) {
-            let prev_pos = PreviousPosition {
-                x: new_pos.x,
-                y: new_pos.y,
-                z: new_pos.z,
-            };
-            let prev_size = PreviousSize {
-                width: sprite.width() as u16,
-                height: sprite.graphemes().len() as u16,
-            };
-
+    for (entity, new_pos, sprite, _, area, layers, transformed) in positions.iter_mut() {
+        if let Some(sprite) = sprite_transform::drawn_sprite(&frames, sprite, transformed) {
             if let Some(value) = previous_details.0.get_mut(&entity) {
-                *value = (prev_pos, prev_size);
+                *value = screen_details(new_pos, sprite, area, layers, &viewports);
+            }
+        }
+    }
+}
+
+/// Things that change how everything on the screen is drawn
+#[derive(Default, PartialEq)]
+pub(crate) struct DrawSettings {
+    style_override: StyleOverride,
+    /// The cells each viewport covers, where its camera is, and what it shows
+    viewports: Vec<(ScreenRect, (i32, i32), RenderLayers)>,
+    layer_order: Vec<u8>,
+}
+
+impl DrawSettings {
+    /// The parts of the screen shown by cameras that moved, if moving cameras is all that changed
+    fn moved_cameras(&self, previous: &DrawSettings) -> Option<Vec<ScreenRect>> {
+        if self.style_override != previous.style_override
+            || self.layer_order != previous.layer_order
+            || self.viewports.len() != previous.viewports.len()
+        {
+            return None;
+        }
+        let mut moved = Vec::new();
+        for (viewport, previous) in self.viewports.iter().zip(previous.viewports.iter()) {
+            if viewport.0 != previous.0 || viewport.2 != previous.2 {
+                return None;
+            }
+            if viewport.1 != previous.1 {
+                moved.push(viewport.0);
             }
         }
+        Some(moved)
     }
 }
 
 pub(crate) fn calculate_entities_to_redraw(
     mut prev_colors: ResMut<PreviousWindowColors>,
+    mut prev_settings: Local<DrawSettings>,
+    style_override: Res<StyleOverride>,
+    viewports: Res<ActiveViewports>,
+    layers: Res<Layers>,
     mut entities: ResMut<EntitiesToRedraw>,
     previous_details: Res<PreviousEntityDetails>,
     window: Res<CrosstermWindow>,
     resize_events: Res<Events<WindowResized>>,
     sprites: Res<Assets<Sprite>>,
+    stylemaps: Res<Assets<StyleMap>>,
     sprite_asset_events: Res<Events<AssetEvent<Sprite>>>,
     stylemap_asset_events: Res<Events<AssetEvent<StyleMap>>>,
     all: Query<(
@@ -115,6 +178,9 @@ pub(crate) fn calculate_entities_to_redraw(
         &Handle<Sprite>,
         &Position,
         &Visible,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+        Option<&TransformedSprite>,
     )>,
     changed: Query<
         Entity,
@@ -123,6 +189,9 @@ pub(crate) fn calculate_entities_to_redraw(
             Mutated<Handle<StyleMap>>,
             Mutated<Visible>,
             Mutated<Handle<Sprite>>,
+            Changed<DrawArea>,
+            Changed<RenderLayers>,
+            Changed<TransformedSprite>,
         )>,
     >,
     added: Query<
@@ -144,23 +213,71 @@ pub(crate) fn calculate_entities_to_redraw(
     entities.full_redraw = false;
     entities.to_draw.clear();
     entities.to_clear.clear();
+    entities.to_blank.clear();
+    entities.regions.clear();
 
     let mut draw_set = HashSet::default();
 
-    // If a resize happened the whole screen is invalidated
-    if resize_events.get_reader().latest(&resize_events).is_some() || window.colors != prev_colors.0
+    let settings = DrawSettings {
+        style_override: *style_override,
+        viewports: viewports
+            .0
+            .iter()
+            .map(|viewport| (viewport.rect(), viewport.camera.offset(), viewport.layers))
+            .collect(),
+        layer_order: layers.order.clone(),
+    };
+
+    // When a camera moves everything in the world moves with it, so what it shows is blanked and
+    // everything in it is drawn again
+    let moved_cameras = settings.moved_cameras(&prev_settings);
+
+    // If a resize happened the whole screen is invalidated, and so is everything that's drawn with a
+    // different style on top of it. The same goes for when viewports or layers are hidden, shown,
+    // or reordered, and when tilemaps or particle clouds move
+    if resize_events.get_reader().latest(&resize_events).is_some()
+        || window.colors != prev_colors.0
+        || moved_cameras.is_none()
+        || entities.tilemaps_changed
+        || entities.particles_changed
     {
         // We need a full redraw, so flag a full update and bail early
         // No need to do fancy update calculations
         entities.full_redraw = true;
         prev_colors.0 = window.colors;
+        *prev_settings = settings;
+        entities.sprite_revisions = sprites
+            .iter()
+            .map(|(handle, sprite)| (handle, sprite.revision()))
+            .collect();
         // Mark all entities as needed to redraw
-        for (entity, _, _, pos, _) in all.iter() {
-            entities.to_draw.push(EntityDepth { entity, z: pos.z });
+        for (entity, _, _, pos, _, _, render_layers, _) in all.iter() {
+            entities.to_draw.push(EntityDepth {
+                entity,
+                layer: layers.rank(render_layers),
+                z: pos.z,
+            });
         }
-        entities.to_draw.sort_by_key(|item| item.z);
+        let tilemaps: Vec<EntityDepth> = entities
+            .tilemaps
+            .iter()
+            .map(|tilemap| tilemap.depth)
+            .collect();
+        entities.to_draw.extend(tilemaps);
+        let clouds: Vec<EntityDepth> = entities
+            .particles
+            .iter()
+            .filter(|cloud| !cloud.rects.is_empty())
+            .map(|cloud| cloud.depth)
+            .collect();
+        entities.to_draw.extend(clouds);
+        entities.to_draw.sort_by_key(|item| (item.layer, item.z));
         return;
     }
+    let moved_cameras = moved_cameras.unwrap_or_default();
+    if !moved_cameras.is_empty() {
+        *prev_settings = settings;
+    }
 
     // Now check to see which entities actually changed since it's not a full update
 
@@ -177,7 +294,9 @@ pub(crate) fn calculate_entities_to_redraw(
             AssetEvent::Modified { handle } => {
                 changed_sprite_assets.insert(handle.clone());
             }
-            _ => {}
+            AssetEvent::Removed { handle } => {
+                entities.sprite_revisions.remove(&handle.id);
+            }
         }
     }
     for evt in stylemap_asset_events
@@ -196,12 +315,45 @@ pub(crate) fn calculate_entities_to_redraw(
     }
 
     // Collect all the entities that changed this update, either because their asset did,
-    // or their components did
-    for (entity, style_hnd, sprite_hnd, _, _) in all.iter() {
-        if changed_sprite_assets.contains(sprite_hnd) || changed_stylemap_assets.contains(style_hnd)
-        {
+    // or their components did. Sprites that only had some of their cells changed only redraw
+    // those cells, along with the depth they're drawn at if they're drawn over without being
+    // blanked first
+    let mut sprite_regions: HashMap<Entity, (Vec<ScreenRect>, Option<(usize, i32)>)> =
+        HashMap::default();
+    for (entity, style_hnd, sprite_hnd, pos, visible, area, render_layers, transformed) in
+        all.iter()
+    {
+        if changed_stylemap_assets.contains(style_hnd) {
             entities.to_clear.insert(entity);
             draw_set.insert(entity);
+        } else if changed_sprite_assets.contains(sprite_hnd) {
+            // The cells of transformed sprites are moved around, so they're drawn whole
+            let sprite = sprites.get(sprite_hnd).filter(|_| transformed.is_none());
+            let drawn = entities.sprite_revisions.get(&sprite_hnd.id).copied();
+            match sprite.and_then(|sprite| Some((sprite, sprite.dirty_since(drawn?)?))) {
+                Some(_) if !visible.is_visible => {}
+                Some((sprite, cells)) => {
+                    let rects = viewports.screen_rects(pos, &cells, area, render_layers);
+                    // Opaque sprites cover their own cells, so only what's drawn over them has to
+                    // be drawn again, unless the sprite shrank. Blended ones are mixed with what's
+                    // under them, so that has to be drawn again first
+                    let bounds = ScreenRect {
+                        x: 0,
+                        y: 0,
+                        width: sprite.width() as i32,
+                        height: sprite.height() as i32,
+                    };
+                    let blended = stylemaps.get(style_hnd).map_or(false, StyleMap::is_blended);
+                    let over =
+                        !visible.is_transparent && !blended && cells.intersect(&bounds) == cells;
+                    let depth = (layers.rank(render_layers), pos.z);
+                    sprite_regions.insert(entity, (rects, Some(depth).filter(|_| over)));
+                }
+                None => {
+                    entities.to_clear.insert(entity);
+                    draw_set.insert(entity);
+                }
+            }
         }
 
         if created_sprite_assets.contains(sprite_hnd) || created_stylemap_assets.contains(style_hnd)
@@ -219,109 +371,222 @@ pub(crate) fn calculate_entities_to_redraw(
         draw_set.insert(entity);
     }
 
+    for handle in created_sprite_assets
+        .iter()
+        .chain(changed_sprite_assets.iter())
+    {
+        if let Some(sprite) = sprites.get(handle) {
+            entities
+                .sprite_revisions
+                .insert(handle.id, sprite.revision());
+        }
+    }
+
     // Find all entities that either became invisible, or changed their size or moved. (cleared is good enough for now)
     // Figure out what their previous bounding box is and query all current positions to see what sprites are under it
     // Add the collided entities to draw_set
-    let mut new_ents = Vec::new();
     let mut bboxes = Vec::new();
-    for (entity, _, sprite, pos, _) in all.iter() {
-        let sprite_data = sprites.get(sprite);
+    let mut depths = HashMap::default();
+    for (entity, _, sprite, pos, _, area, render_layers, transformed) in all.iter() {
+        let sprite_data = sprite_transform::drawn_sprite(&sprites, sprite, transformed);
         if sprite_data.is_none() {
             continue;
         }
         let sprite = sprite_data.unwrap();
-        let bb = broccoli::bbox(
-            broccoli::rect(
-                pos.x,
-                pos.x + sprite.width() as i32,
-                pos.y,
-                pos.y + sprite.data().len() as i32,
-            ),
-            entity,
+        depths.insert(entity, (layers.rank(render_layers), pos.z));
+        for rect in screen_details(pos, sprite, area, render_layers, &viewports) {
+            let bb = broccoli::bbox(
+                broccoli::rect(rect.x, rect.right(), rect.y, rect.bottom()),
+                entity,
+            );
+            bboxes.push(bb);
+        }
+    }
+    // Tilemaps and particle clouds are only drawn again in the parts of the screen that need it
+    let mut tilemap_entities = HashSet::default();
+    let cell_rects = entities
+        .tilemaps
+        .iter()
+        .map(|tilemap| (tilemap.depth, &tilemap.rects))
+        .chain(
+            entities
+                .particles
+                .iter()
+                .map(|cloud| (cloud.depth, &cloud.rects)),
         );
-        bboxes.push(bb);
+    for (depth, rects) in cell_rects {
+        tilemap_entities.insert(depth.entity);
+        depths.insert(depth.entity, (depth.layer, depth.z));
+        for rect in rects.iter() {
+            let bb = broccoli::bbox(
+                broccoli::rect(rect.x, rect.right(), rect.y, rect.bottom()),
+                depth.entity,
+            );
+            bboxes.push(bb);
+        }
     }
 
-    let broccoli = broccoli::new(&mut bboxes);
-    for ent in changed.iter() {
-        let prev_data = previous_details.0.get(&ent);
-        if prev_data.is_none() {
-            continue;
+    // Entities that lost their sprite or were despawned are cleared too. The blanks below are made
+    // from everything that's cleared, so this has to come first, or what was under them wouldn't be
+    // drawn again and their old cells would be left on the screen
+    let removed = all.removed::<Handle<Sprite>>();
+
+    for entity in removed {
+        entities.to_clear.insert(*entity);
+    }
+
+    // Entities whose transform was taken away are drawn with their original sprites again
+    for entity in all.removed::<TransformedSprite>() {
+        entities.to_clear.insert(*entity);
+        draw_set.insert(*entity);
+    }
+
+    // The parts of the screen that are blanked, and the entity that blanked them. Changed tiles are
+    // blanked too, in case they're empty now. Parts that are drawn over rather than blanked only
+    // affect what's drawn at or above the depth they're drawn at
+    let mut blanks: Vec<(Option<Entity>, ScreenRect, Option<(usize, i32)>)> = Vec::new();
+    blanks.extend(moved_cameras.iter().map(|rect| (None, *rect, None)));
+    entities.to_blank.extend(moved_cameras);
+    for ent in entities.to_clear.iter() {
+        if let Some(prev_rects) = previous_details.0.get(ent) {
+            blanks.extend(prev_rects.iter().map(|rect| (Some(*ent), *rect, None)));
         }
-        let (prev_pos, prev_size) = prev_data.unwrap();
-        let blank_bb = broccoli::rect(
-            prev_pos.x,
-            prev_pos.x + prev_size.width as i32,
-            prev_pos.y,
-            prev_pos.y + prev_size.height as i32,
+    }
+    let dirty_tiles: Vec<ScreenRect> = entities
+        .tilemaps
+        .iter()
+        .flat_map(|tilemap| tilemap.dirty.iter().copied())
+        .collect();
+    blanks.extend(dirty_tiles.iter().map(|rect| (None, *rect, None)));
+    entities.to_blank.extend(dirty_tiles);
+    // Cells particles left are blanked, but the ones they moved into are drawn over, like the dirty
+    // cells of an opaque sprite
+    let mut regions: HashMap<Entity, Vec<ScreenRect>> = HashMap::default();
+    for cloud in entities.particles.iter() {
+        let (entity, depth) = (cloud.depth.entity, (cloud.depth.layer, cloud.depth.z));
+        blanks.extend(cloud.vacated.iter().map(|rect| (Some(entity), *rect, None)));
+        blanks.extend(
+            cloud
+                .covered
+                .iter()
+                .map(|rect| (Some(entity), *rect, Some(depth))),
         );
-        // dbg!("checking for collision", ent, prev_pos);
-        broccoli.for_all_intersect_rect(&blank_bb, |bb| {
-            if ent == bb.inner {
-                return;
-            }
-            // dbg!("Found Entity: ", bb.inner);
-            if !draw_set.contains(&bb.inner) {
-                draw_set.insert(bb.inner);
-                new_ents.push(bb.inner);
-            }
-        });
+        if !cloud.covered.is_empty() {
+            regions.insert(entity, cloud.covered.clone());
+        }
+    }
+    let vacated: Vec<ScreenRect> = entities
+        .particles
+        .iter()
+        .flat_map(|cloud| cloud.vacated.iter().copied())
+        .collect();
+    entities.to_blank.extend(vacated);
+    // Entities that are drawn whole anyway don't need their dirty cells drawn separately
+    sprite_regions.retain(|entity, _| !draw_set.contains(entity));
+    for (entity, (rects, over)) in sprite_regions.iter() {
+        blanks.extend(rects.iter().map(|rect| (Some(*entity), *rect, *over)));
+        if over.is_none() {
+            entities.to_blank.extend(rects.iter().copied());
+        }
     }
 
+    // Everything under a blanked part of the screen has to be drawn again. Tilemaps only draw the
+    // blanked part again, but other entities are drawn whole, which blanks whatever they're drawn
+    // under in turn
+    let broccoli = broccoli::new(&mut bboxes);
     let mut cur_index = 0;
-    while cur_index < new_ents.len() {
-        let ent = new_ents[cur_index];
+    while cur_index < blanks.len() {
+        let (blanked_by, blank, over) = blanks[cur_index];
         cur_index += 1;
 
-        let prev_data = previous_details.0.get(&ent);
-        if prev_data.is_none() {
-            continue;
-        }
-        let (prev_pos, prev_size) = prev_data.unwrap();
-        let blank_bb = broccoli::rect(
-            prev_pos.x,
-            prev_pos.x + prev_size.width as i32,
-            prev_pos.y,
-            prev_pos.y + prev_size.height as i32,
-        );
-        // dbg!("checking for collision", ent, prev_pos);
+        let blank_bb = broccoli::rect(blank.x, blank.right(), blank.y, blank.bottom());
+        // dbg!("checking for collision", blanked_by, blank);
         broccoli.for_all_intersect_rect(&blank_bb, |bb| {
-            if ent == bb.inner {
+            if blanked_by == Some(bb.inner) {
                 return;
             }
+            if let Some(depth) = over {
+                if depths.get(&bb.inner).map_or(true, |other| *other < depth) {
+                    return;
+                }
+            }
             // dbg!("Found Entity: ", bb.inner);
-            if !draw_set.contains(&bb.inner) {
+            if tilemap_entities.contains(&bb.inner) {
+                // A blank can cover many of a particle cloud's cells, but it only has to be drawn in
+                // once
+                let entity_regions = regions.entry(bb.inner).or_default();
+                if entity_regions.last() != Some(&blank) {
+                    entity_regions.push(blank);
+                }
+            } else if !draw_set.contains(&bb.inner) {
                 draw_set.insert(bb.inner);
-                new_ents.push(bb.inner);
+                if let Some(prev_rects) = previous_details.0.get(&bb.inner) {
+                    blanks.extend(prev_rects.iter().map(|rect| (Some(bb.inner), *rect, None)));
+                }
             }
         });
     }
 
-    let removed = all.removed::<Handle<Sprite>>();
-
-    for entity in removed {
-        entities.to_clear.insert(*entity);
-    }
-
     for ent_to_draw in draw_set.iter() {
-        let (entity, _, _, pos, _) = all.get(*ent_to_draw).unwrap();
-        entities
-            .to_draw
-            .push(components::EntityDepth { entity, z: pos.z });
+        // Entities without sprites can still have moved, but there's nothing to draw
+        if let Ok((entity, _, _, pos, _, _, render_layers, _)) = all.get(*ent_to_draw) {
+            entities.to_draw.push(components::EntityDepth {
+                entity,
+                layer: layers.rank(render_layers),
+                z: pos.z,
+            });
+        }
     }
-    entities.to_draw.sort_by_key(|item| item.z);
+    let tilemaps: Vec<EntityDepth> = entities
+        .tilemaps
+        .iter()
+        .filter(|tilemap| regions.contains_key(&tilemap.depth.entity))
+        .map(|tilemap| tilemap.depth)
+        .collect();
+    entities.to_draw.extend(tilemaps);
+    let clouds: Vec<EntityDepth> = entities
+        .particles
+        .iter()
+        .filter(|cloud| regions.contains_key(&cloud.depth.entity))
+        .map(|cloud| cloud.depth)
+        .collect();
+    entities.to_draw.extend(clouds);
+    // Sprites whose dirty cells were under something that's drawn whole are drawn whole too
+    for (entity, (rects, _)) in sprite_regions {
+        if draw_set.contains(&entity) {
+            continue;
+        }
+        if let Ok((_, _, _, pos, _, _, render_layers, _)) = all.get(entity) {
+            entities.to_draw.push(components::EntityDepth {
+                entity,
+                layer: layers.rank(render_layers),
+                z: pos.z,
+            });
+            regions.insert(entity, rects);
+        }
+    }
+    entities.regions = regions;
+    entities.to_draw.sort_by_key(|item| (item.layer, item.z));
 }
 
 /// Helper function for draw_entity which determines whether the style on the terminal should be
 /// changed
 fn change_style_if_needed(
-    term: &mut std::io::StdoutLock,
+    term: &mut impl std::io::Write,
     previous_style: &mut Style,
     current_style: &Style,
 ) -> Result<(), Box<dyn std::error::Error>> {
     if current_style.attributes != previous_style.attributes {
         term.queue(crossterm::style::SetAttributes(current_style.attributes))?;
         previous_style.attributes = current_style.attributes;
+        // Resetting the attributes puts the terminal back to its own colors as well, so whatever
+        // colors were set before are gone, and have to be set again even if they're the same
+        if current_style
+            .attributes
+            .has(crossterm::style::Attribute::Reset)
+        {
+            previous_style.colors = Colors::default();
+        }
     }
     if current_style.colors != previous_style.colors {
         term.queue(crossterm::style::SetColors(
@@ -333,24 +598,31 @@ fn change_style_if_needed(
 }
 
 fn draw_entity(
-    entity: Entity,
+    entity: EntityDepth,
     term: &mut std::io::StdoutLock,
+    screen: &mut ScreenCells,
     window: &CrosstermWindow,
     sprites: &Res<Assets<Sprite>>,
     stylemaps: &Res<Assets<StyleMap>>,
+    style_override: &StyleOverride,
+    viewports: &ActiveViewports,
+    regions: Option<&[ScreenRect]>,
     all: &Query<(
         Entity,
         &Position,
         &Handle<StyleMap>,
         &components::Visible,
         &Handle<Sprite>,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+        Option<&TransformedSprite>,
     )>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let entity_data = all.get(entity);
+    let entity_data = all.get(entity.entity);
     if entity_data.is_err() {
         return Ok(());
     }
-    let (_, pos, style, draw, sprite) = entity_data.unwrap();
+    let (_, pos, style, draw, sprite, area, layers, transformed) = entity_data.unwrap();
 
     // If the entity isn't visible, skip it
     if !draw.is_visible {
@@ -364,21 +636,81 @@ fn draw_entity(
     }
     let sprite = sprite.unwrap();
 
-    // If the entity's not on the screen, skip it
-    if pos.y >= window.height.into()
-        || pos.y + sprite.height() as i32 <= 0
-        || pos.x >= window.width.into()
-        || pos.x + sprite.width() as i32 <= 0
-    {
-        return Ok(());
-    }
-
     let stylemap = stylemaps.get(&*style);
     if stylemap.is_none() {
         // The stylemap asset hasn't loaded yet, this isn't a problem
         return Ok(());
     }
     let stylemap = stylemap.unwrap();
+    let (sprite, stylemap) = sprite_transform::drawn(sprite, stylemap, transformed);
+
+    // Draw it in every viewport it's shown in, or only in the parts of the screen that need it
+    for area in viewports.draw_areas(area, layers) {
+        let areas: Vec<DrawArea> = match regions {
+            Some(regions) => regions
+                .iter()
+                .map(|region| DrawArea {
+                    clip: Some(match area.clip {
+                        Some(clip) => clip.intersect(region),
+                        None => *region,
+                    }),
+                    ..area
+                })
+                .collect(),
+            None => vec![area],
+        };
+        for area in areas {
+            draw_sprite(
+                term,
+                screen,
+                window,
+                sprite,
+                stylemap,
+                draw,
+                pos,
+                area,
+                style_override.style_at(entity.layer, pos.z),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Helper function for draw_entity which draws a sprite in one of the places it's drawn
+fn draw_sprite(
+    term: &mut std::io::StdoutLock,
+    screen: &mut ScreenCells,
+    window: &CrosstermWindow,
+    sprite: &Sprite,
+    stylemap: &StyleMap,
+    draw: &Visible,
+    pos: &Position,
+    area: DrawArea,
+    style_on_top: Option<Style>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Where the sprite is drawn, and the part of the screen it's allowed to draw in
+    let x = pos.x + area.offset_x;
+    let y = pos.y + area.offset_y;
+    let window_rect = ScreenRect {
+        x: 0,
+        y: 0,
+        width: window.width.into(),
+        height: window.height.into(),
+    };
+    let bounds = match area.clip {
+        Some(clip) => clip.intersect(&window_rect),
+        None => window_rect,
+    };
+
+    // If the sprite's not on the screen here, skip it
+    let drawn = area
+        .sprite_rect(pos, sprite.width(), sprite.height())
+        .intersect(&bounds);
+    if drawn.width == 0 || drawn.height == 0 {
+        return Ok(());
+    }
+
     let sprite_colors = stylemap.style.colors.with_default(window.colors);
 
     term.queue(crossterm::style::SetAttribute(
@@ -392,72 +724,235 @@ fn draw_entity(
     for (line_num, line) in sprite.graphemes().iter().enumerate() {
         let line_offset: i32 = line_num.try_into()?;
 
-        // Check to see if this line is on the screen, if not skip it
-        if pos.y + line_offset < 0 {
+        // Check to see if this line is in bounds, if not skip it
+        if y + line_offset < bounds.y {
             continue;
         }
 
-        // If this line is off the bottom of the screen, break out since no lines can ever
-        // be on the screen ever again
-        if pos.y + line_offset >= window.height.into() {
+        // If this line is past the bottom of the bounds, break out since no lines can ever
+        // be in bounds ever again
+        if y + line_offset >= bounds.bottom() {
             break;
         }
 
-        // Calculate the beginning and end of string sprte, to not render things off screen
-        let start: i32 = std::cmp::max(0, pos.x);
-        let end: i32 = std::cmp::min(window.width as i32, pos.x + line.len() as i32);
+        // Calculate the beginning and end of string sprte, to not render things out of bounds
+        let start: i32 = std::cmp::max(bounds.x, x);
+        let end: i32 = std::cmp::min(bounds.right(), x + line.len() as i32).max(start);
 
-        let start_idx: usize = (start - pos.x).try_into()?;
-        let end_idx: usize = (end - pos.x).try_into()?;
+        let start_idx: usize = (start - x).try_into()?;
+        let end_idx: usize = (end - x).try_into()?;
 
         term.queue(crossterm::cursor::MoveTo(
             start.try_into()?,
-            (pos.y + line_offset).try_into()?,
+            (y + line_offset).try_into()?,
         ))?;
 
-        let graphemes = &line[start_idx..end_idx];
+        let graphemes = &line[start_idx.min(line.len())..end_idx.min(line.len())];
         if !graphemes.is_empty() {
             // Go through each grapheme one by one to make sure we have the correct style and color
             // (Cross reference with the stylemap, otherwise default to )
             for (i, grapheme) in graphemes.iter().enumerate() {
                 let idx = start_idx + i;
+                let column = start + i as i32;
+                let grapheme = sprite.grapheme(grapheme);
 
-                // If the grapheme is a transparent space with no style, skip rendering it
-                if draw.is_transparent
-                    && stylemap.style_at(idx, line_num).is_none()
-                    && sprite.grapheme(grapheme) == " "
-                {
+                // If whatever's under the grapheme shows through, skip rendering it
+                if is_see_through(stylemap, draw, idx, line_num, grapheme) {
                     term.queue(crossterm::cursor::MoveRight(1))?;
                     continue;
                 }
 
-                // Get the style we need to render this grapheme with
-                let grapheme_style = stylemap.style_for(idx, line_num);
+                let (grapheme_style, shown) = sprite_cell(
+                    stylemap,
+                    idx,
+                    line_num,
+                    grapheme,
+                    window.colors,
+                    screen.get(column, y + line_offset),
+                    style_on_top,
+                );
+                let shown = shown.as_deref().unwrap_or(grapheme);
                 change_style_if_needed(term, &mut previous_style, &grapheme_style)?;
+                screen.set(column, y + line_offset, shown, grapheme_style.colors);
 
-                term.queue(crossterm::style::Print(&sprite.grapheme(grapheme)))?;
+                term.queue(crossterm::style::Print(shown))?;
             }
         }
 
         // Lines don't have to go to the end of the sprite. Pad them out so the sprite is rectangular
-        if end < window.width as i32 && line.len() < sprite.width() {
-            let unaccounted = sprite.width() - line.len();
-            let blank_length = std::cmp::min(unaccounted, (window.width as i32 - end) as usize);
-            let blank_str = str::repeat(" ", blank_length);
-            for (i, space) in blank_str.chars().enumerate() {
-                let idx = end_idx + i;
-
-                // If the filler space is transparent and has no style, skip it
-                if draw.is_transparent && stylemap.style_at(idx, line_num).is_none() {
-                    term.queue(crossterm::cursor::MoveRight(1))?;
-                    continue;
-                }
+        let padding_end = std::cmp::min(bounds.right(), x + sprite.width() as i32);
+        for column in end..padding_end {
+            let idx: usize = (column - x).try_into()?;
+
+            // If whatever's under the filler space shows through, skip it
+            if is_see_through(stylemap, draw, idx, line_num, " ") {
+                term.queue(crossterm::cursor::MoveRight(1))?;
+                continue;
+            }
 
-                // Get the style we need to render this space with
-                let grapheme_style = stylemap.style_for(idx, line_num);
-                change_style_if_needed(term, &mut previous_style, &grapheme_style)?;
+            let (grapheme_style, shown) = sprite_cell(
+                stylemap,
+                idx,
+                line_num,
+                " ",
+                window.colors,
+                screen.get(column, y + line_offset),
+                style_on_top,
+            );
+            let shown = shown.as_deref().unwrap_or(" ");
+            change_style_if_needed(term, &mut previous_style, &grapheme_style)?;
+            screen.set(column, y + line_offset, shown, grapheme_style.colors);
+
+            term.queue(crossterm::style::Print(shown))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a cell of a sprite is left undrawn, so whatever's under it shows through
+fn is_see_through(stylemap: &StyleMap, draw: &Visible, x: usize, y: usize, grapheme: &str) -> bool {
+    match stylemap.alpha_at(x, y) {
+        // Transparent sprites let spaces with no style of their own through
+        Alpha::Auto => draw.is_transparent && grapheme == " " && stylemap.style_at(x, y).is_none(),
+        Alpha::Transparent => true,
+        Alpha::Opaque | Alpha::TransparentBackground => false,
+    }
+}
+
+/// How a cell is drawn over whatever's already on the screen there, as its style and the grapheme
+/// that's shown instead of its own, if there is one. Colors the style leaves unset are the colors
+/// underneath, or the window's colors where nothing's been drawn, and blended styles are mixed with
+/// them. Spaces in blended cells show the grapheme underneath.
+fn composite(
+    mut style: Style,
+    grapheme: &str,
+    window_colors: Colors,
+    underneath: Option<&ScreenCell>,
+) -> (Style, Option<String>) {
+    let below = underneath
+        .map_or(Colors::default(), |cell| cell.colors)
+        .with_default(window_colors);
+    style.colors = style.blend.apply(style.colors, below);
+    let shown = match underneath {
+        Some(cell) if !style.blend.is_replace() && grapheme == " " => Some(cell.grapheme.clone()),
+        _ => None,
+    };
+    (style, shown)
+}
+
+/// How a cell of a sprite is drawn. Colors the cell's own style leaves unset come from the sprite
+/// before whatever's underneath, and cells that let the background through take it from the cell
+/// underneath.
+fn sprite_cell(
+    stylemap: &StyleMap,
+    x: usize,
+    y: usize,
+    grapheme: &str,
+    window_colors: Colors,
+    underneath: Option<&ScreenCell>,
+    style_on_top: Option<Style>,
+) -> (Style, Option<String>) {
+    let mut style = stylemap.style_for(x, y);
+    style.colors = style.colors.with_default(stylemap.style.colors);
+    let (mut style, shown) = composite(style, grapheme, window_colors, underneath);
+    if stylemap.alpha_at(x, y) == Alpha::TransparentBackground {
+        // Blanked cells show the terminal's own background
+        let background = underneath.and_then(|cell| cell.colors.background);
+        style.colors.background = Some(background.unwrap_or(Color::Reset));
+    }
+    if let Some(style_on_top) = style_on_top {
+        style = merge_styles(style, style_on_top);
+    }
+    (style, shown)
+}
+
+/// Draws the tiles of a tilemap in one of the places it's drawn, either everywhere or only in some
+/// parts of the screen
+fn draw_tilemap(
+    term: &mut std::io::StdoutLock,
+    screen: &mut ScreenCells,
+    window: &CrosstermWindow,
+    tilemap: &Tilemap,
+    tileset: &Tileset,
+    pos: &Position,
+    area: DrawArea,
+    regions: Option<&[ScreenRect]>,
+    style_on_top: Option<Style>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let window_rect = ScreenRect {
+        x: 0,
+        y: 0,
+        width: window.width.into(),
+        height: window.height.into(),
+    };
+    let bounds = match area.clip {
+        Some(clip) => clip.intersect(&window_rect),
+        None => window_rect,
+    };
+    let regions = match regions {
+        Some(regions) => regions
+            .iter()
+            .map(|region| region.intersect(&bounds))
+            .collect(),
+        None => vec![bounds],
+    };
 
-                term.queue(crossterm::style::Print(space))?;
+    term.queue(crossterm::style::SetAttribute(
+        crossterm::style::Attribute::Reset,
+    ))?
+    .queue(crossterm::style::SetColors(window.colors.to_crossterm()))?;
+    let mut previous_style = Style::new(window.colors, crossterm::style::Attribute::Reset.into());
+
+    // Where the tilemap's top left cell is on the screen
+    let (x, y) = (pos.x + area.offset_x, pos.y + area.offset_y);
+    for region in regions {
+        // The region as cells of the tilemap, so only the chunks in it are looked at
+        let cells = ScreenRect {
+            x: region.x - x,
+            y: region.y - y,
+            ..region
+        };
+        for chunk in tilemap.chunks_in(&cells) {
+            for row in chunk.y..chunk.bottom() {
+                let mut moved = false;
+                for column in chunk.x..chunk.right() {
+                    let tile = tilemap
+                        .get(column as usize, row as usize)
+                        .and_then(|id| tileset.get(id));
+                    let tile = match tile {
+                        Some(tile) => tile,
+                        None => {
+                            // Empty cells are transparent
+                            moved = false;
+                            continue;
+                        }
+                    };
+                    if !moved {
+                        term.queue(crossterm::cursor::MoveTo(
+                            (x + column).try_into()?,
+                            (y + row).try_into()?,
+                        ))?;
+                        moved = true;
+                    }
+
+                    let mut glyph = [0; 4];
+                    let glyph = tile.glyph.encode_utf8(&mut glyph);
+                    let (mut tile_style, shown) = composite(
+                        tile.style,
+                        glyph,
+                        window.colors,
+                        screen.get(x + column, y + row),
+                    );
+                    let shown = shown.as_deref().unwrap_or(glyph);
+                    if let Some(style_on_top) = style_on_top {
+                        tile_style = merge_styles(tile_style, style_on_top);
+                    }
+                    change_style_if_needed(term, &mut previous_style, &tile_style)?;
+                    screen.set(x + column, y + row, shown, tile_style.colors);
+
+                    term.queue(crossterm::style::Print(shown))?;
+                }
             }
         }
     }
@@ -465,10 +960,72 @@ fn draw_entity(
     Ok(())
 }
 
+/// Draws the cells of a particle cloud in one of the places it's drawn, either everywhere or only in
+/// some parts of the screen
+fn draw_particles(
+    term: &mut std::io::StdoutLock,
+    screen: &mut ScreenCells,
+    window: &CrosstermWindow,
+    particles: &Particles,
+    pos: &Position,
+    area: DrawArea,
+    regions: Option<&[ScreenRect]>,
+    style_on_top: Option<Style>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let window_rect = ScreenRect {
+        x: 0,
+        y: 0,
+        width: window.width.into(),
+        height: window.height.into(),
+    };
+    let bounds = match area.clip {
+        Some(clip) => clip.intersect(&window_rect),
+        None => window_rect,
+    };
+
+    term.queue(crossterm::style::SetAttribute(
+        crossterm::style::Attribute::Reset,
+    ))?
+    .queue(crossterm::style::SetColors(window.colors.to_crossterm()))?;
+    let mut previous_style = Style::new(window.colors, crossterm::style::Attribute::Reset.into());
+
+    // Where the cloud's position is on the screen
+    let (x, y) = (pos.x + area.offset_x, pos.y + area.offset_y);
+    for ((column, row), grapheme, color) in particles.cells() {
+        let (column, row) = (x + column, y + row);
+        let in_regions = regions.map_or(true, |regions| {
+            regions.iter().any(|region| region.contains(column, row))
+        });
+        if !bounds.contains(column, row) || !in_regions {
+            continue;
+        }
+
+        // Particles without a color take the colors of whatever's underneath
+        let style = color.map_or(Style::default(), Style::with_fg);
+        let (mut style, shown) = composite(style, grapheme, window.colors, screen.get(column, row));
+        let shown = shown.as_deref().unwrap_or(grapheme);
+        if let Some(style_on_top) = style_on_top {
+            style = merge_styles(style, style_on_top);
+        }
+        change_style_if_needed(term, &mut previous_style, &style)?;
+        screen.set(column, row, shown, style.colors);
+
+        term.queue(crossterm::cursor::MoveTo(
+            column.try_into()?,
+            row.try_into()?,
+        ))?
+        .queue(crossterm::style::Print(shown))?;
+    }
+
+    Ok(())
+}
+
 fn clear_entity(
     entity: Entity,
     term: &mut std::io::StdoutLock,
+    screen: &mut ScreenCells,
     window: &CrosstermWindow,
+    style_override: &StyleOverride,
     previous_details: &PreviousEntityDetails,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let prev_details = previous_details.0.get(&entity);
@@ -477,59 +1034,93 @@ fn clear_entity(
         // Since it's sprite is most likely still loading
         return Ok(());
     }
-    let (prev_pos, prev_size) = prev_details.unwrap();
-
-    for height in 0..prev_size.height {
-        let y = prev_pos.y + height as i32;
-
-        if y < 0 {
-            continue;
-        }
 
-        if prev_pos.y >= window.height.into()
-            || prev_pos.y + prev_size.height as i32 <= 0
-            || prev_pos.x >= window.width.into()
-            || prev_pos.x + prev_size.width as i32 <= 0
-        {
-            break;
-        }
+    for prev_rect in prev_details.unwrap() {
+        blank_rect(prev_rect, term, screen, window, style_override)?;
+    }
 
-        let x_start: i32 = std::cmp::max(0, prev_pos.x);
-        let x_end: i32 = std::cmp::min(window.width as i32, prev_pos.x + prev_size.width as i32);
+    Ok(())
+}
 
-        let actual_width = x_end - x_start;
-        let blank_string = " ".repeat(actual_width as usize);
+/// Blanks a part of the screen with the terminal's colors, and whatever style is drawn on top of
+/// everything beneath a dialog
+fn blank_rect(
+    rect: &ScreenRect,
+    term: &mut std::io::StdoutLock,
+    screen: &mut ScreenCells,
+    window: &CrosstermWindow,
+    style_override: &StyleOverride,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Only clear the part that's still in the window
+    let window_rect = ScreenRect {
+        x: 0,
+        y: 0,
+        width: window.width.into(),
+        height: window.height.into(),
+    };
+    let rect = rect.intersect(&window_rect);
+    if rect.width == 0 {
+        return Ok(());
+    }
+    let blank_string = " ".repeat(rect.width as usize);
+    let style = style_override.style.map_or(Style::default(), |style| {
+        merge_styles(Style::default(), style)
+    });
 
+    for y in rect.y..rect.bottom() {
         term.queue(crossterm::style::SetAttribute(
             crossterm::style::Attribute::Reset,
         ))?
+        .queue(crossterm::style::SetAttributes(style.attributes))?
         .queue(crossterm::style::SetColors(
-            Colors::term_colors().to_crossterm(),
-        ))?
-        .queue(crossterm::cursor::MoveTo(
-            x_start.try_into()?,
-            y.try_into()?,
+            style
+                .colors
+                .with_default(Colors::term_colors())
+                .to_crossterm(),
         ))?
-        .queue(crossterm::style::Print(blank_string))?;
+        .queue(crossterm::cursor::MoveTo(rect.x.try_into()?, y.try_into()?))?
+        .queue(crossterm::style::Print(&blank_string))?;
+        for x in rect.x..rect.right() {
+            screen.set(x, y, " ", style.colors);
+        }
     }
 
     Ok(())
 }
 
-
 pub(crate) fn crossterm_render(
     changed_entities: Res<EntitiesToRedraw>,
+    mut screen: ResMut<ScreenCells>,
     window: Res<CrosstermWindow>,
     cursor: Res<Cursor>,
     previous_details: Res<PreviousEntityDetails>,
     sprites: Res<Assets<Sprite>>,
     stylemaps: Res<Assets<StyleMap>>,
+    style_override: Res<StyleOverride>,
+    viewports: Res<ActiveViewports>,
+    tilesets: Res<Assets<Tileset>>,
     all: Query<(
         Entity,
         &Position,
         &Handle<StyleMap>,
         &Visible,
         &Handle<Sprite>,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+        Option<&TransformedSprite>,
+    )>,
+    tilemaps: Query<(
+        &Tilemap,
+        &Handle<Tileset>,
+        &Position,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
+    clouds: Query<(
+        &Particles,
+        &Position,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
     )>,
 ) {
     let stdout = std::io::stdout();
@@ -551,20 +1142,109 @@ pub(crate) fn crossterm_render(
         ))
         .unwrap();
     }
+    let size = (window.width as usize, window.height as usize);
+    if changed_entities.full_redraw || screen.size() != size {
+        screen.reset(size.0, size.1);
+    }
+    // Clearing only blanks the screen with the terminal's colors, which leaves out the backdrop
+    if changed_entities.full_redraw && style_override.style.is_some() {
+        let window_rect = ScreenRect {
+            x: 0,
+            y: 0,
+            width: window.width.into(),
+            height: window.height.into(),
+        };
+        blank_rect(
+            &window_rect,
+            &mut term,
+            &mut screen,
+            &window,
+            &style_override,
+        )
+        .unwrap();
+    }
 
     // Blank out all the previous locations of sprites that changed either their position or their size
     for entity in changed_entities.to_clear.iter() {
-        clear_entity(*entity, &mut term, &window, &previous_details).unwrap();
+        clear_entity(
+            *entity,
+            &mut term,
+            &mut screen,
+            &window,
+            &style_override,
+            &previous_details,
+        )
+        .unwrap();
+    }
+    for rect in changed_entities.to_blank.iter() {
+        blank_rect(rect, &mut term, &mut screen, &window, &style_override).unwrap();
     }
 
     // Redraw all the changed sprites, either because they moved, or because they changed their shape
     for entity in changed_entities.to_draw.iter() {
+        if let Ok((tilemap, tileset, pos, area, layers)) = tilemaps.get(entity.entity) {
+            let tileset = match tilesets.get(tileset) {
+                Some(tileset) => tileset,
+                // The tileset asset hasn't loaded yet, this isn't a problem
+                None => continue,
+            };
+            let regions = if changed_entities.full_redraw {
+                None
+            } else {
+                changed_entities.regions.get(&entity.entity)
+            };
+            for area in viewports.draw_areas(area, layers) {
+                draw_tilemap(
+                    &mut term,
+                    &mut screen,
+                    &window,
+                    tilemap,
+                    tileset,
+                    pos,
+                    area,
+                    regions.map(Vec::as_slice),
+                    style_override.style_at(entity.layer, pos.z),
+                )
+                .unwrap();
+            }
+            continue;
+        }
+        if let Ok((particles, pos, area, layers)) = clouds.get(entity.entity) {
+            let regions = if changed_entities.full_redraw {
+                None
+            } else {
+                changed_entities.regions.get(&entity.entity)
+            };
+            for area in viewports.draw_areas(area, layers) {
+                draw_particles(
+                    &mut term,
+                    &mut screen,
+                    &window,
+                    particles,
+                    pos,
+                    area,
+                    regions.map(Vec::as_slice),
+                    style_override.style_at(entity.layer, pos.z),
+                )
+                .unwrap();
+            }
+            continue;
+        }
+        let regions = if changed_entities.full_redraw {
+            None
+        } else {
+            changed_entities.regions.get(&entity.entity)
+        };
         draw_entity(
-            entity.entity,
+            *entity,
             &mut term,
+            &mut screen,
             &window,
             &sprites,
             &stylemaps,
+            &style_override,
+            &viewports,
+            regions.map(Vec::as_slice),
             &all,
         )
         .unwrap();
@@ -587,3 +1267,76 @@ pub(crate) fn crossterm_render(
 
     term.flush().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(cameras: &[(i32, i32)]) -> DrawSettings {
+        DrawSettings {
+            viewports: cameras
+                .iter()
+                .enumerate()
+                .map(|(i, camera)| {
+                    let rect = ScreenRect {
+                        x: i as i32 * 10,
+                        y: 0,
+                        width: 10,
+                        height: 5,
+                    };
+                    (rect, *camera, RenderLayers::all())
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn moving_a_camera_only_redraws_its_viewport() {
+        let previous = settings(&[(0, 0), (0, 0)]);
+        assert_eq!(
+            settings(&[(0, 0), (0, 0)]).moved_cameras(&previous),
+            Some(vec![])
+        );
+
+        let moved = settings(&[(0, 0), (-3, 1)]).moved_cameras(&previous);
+        assert_eq!(moved, Some(vec![previous.viewports[1].0]));
+
+        // Anything else changing redraws the whole screen
+        assert_eq!(settings(&[(0, 0)]).moved_cameras(&previous), None);
+        let mut reordered = settings(&[(0, 0), (-3, 1)]);
+        reordered.layer_order = vec![1, 0];
+        assert_eq!(reordered.moved_cameras(&previous), None);
+    }
+
+    #[test]
+    fn colors_are_set_again_after_a_reset() {
+        use crossterm::style::{Attribute, Attributes, Color};
+
+        let colors = Colors::new(Color::Red, Color::Blue);
+        let bold = Style::new(colors, Attributes::from(Attribute::Bold));
+        let reset = Style::new(colors, Attributes::from(Attribute::Reset));
+
+        let mut previous_style = bold;
+        let mut term = Vec::new();
+        change_style_if_needed(&mut term, &mut previous_style, &reset).unwrap();
+
+        let mut expected = Vec::new();
+        expected
+            .queue(crossterm::style::SetAttributes(reset.attributes))
+            .unwrap()
+            .queue(crossterm::style::SetColors(colors.to_crossterm()))
+            .unwrap();
+        assert_eq!(term, expected);
+        assert!(previous_style == reset);
+
+        // Without a reset, the colors are left as they are
+        let mut term = Vec::new();
+        change_style_if_needed(&mut term, &mut previous_style, &bold).unwrap();
+        let mut expected = Vec::new();
+        expected
+            .queue(crossterm::style::SetAttributes(bold.attributes))
+            .unwrap();
+        assert_eq!(term, expected);
+    }
+}
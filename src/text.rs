@@ -0,0 +1,638 @@
+use bevy::prelude::*;
+use bevy::window::WindowResized;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::components::{self, Color, Position, Sprite, Style, StyleGrid, StyleMap};
+use crate::CrosstermWindow;
+
+const ELLIPSIS: &str = "…";
+
+/// Where each line of a Text sits horizontally
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl Default for TextAlign {
+    fn default() -> Self {
+        TextAlign::Left
+    }
+}
+
+/// What happens to lines which are wider than a Text's bounds
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TextOverflow {
+    /// Break lines between words, or inside a word if it doesn't fit on a line by itself
+    Wrap,
+    /// Cut lines off at the edge
+    Truncate,
+    /// Cut lines off at the edge and end them with an ellipsis. If there are more lines than fit,
+    /// the last line gets an ellipsis too.
+    Ellipsis,
+}
+
+impl Default for TextOverflow {
+    fn default() -> Self {
+        TextOverflow::Wrap
+    }
+}
+
+/// Text which is laid out inside some bounds. The entity's sprite is generated from the text, and it
+/// is laid out again whenever the text, its bounds, or the window size changes.
+///
+/// Bounds are measured in terminal columns and rows, so wide characters take up two columns. When a
+/// bound is `None`, the text can use all the space between its position and the edge of the window.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Text {
+    pub text: String,
+    pub max_width: Option<usize>,
+    pub max_height: Option<usize>,
+    pub align: TextAlign,
+    pub overflow: TextOverflow,
+}
+
+impl Text {
+    pub fn new<T: std::string::ToString>(text: T) -> Text {
+        Text {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn bounded<T: std::string::ToString>(text: T, max_width: usize, max_height: usize) -> Text {
+        Text {
+            text: text.to_string(),
+            max_width: Some(max_width),
+            max_height: Some(max_height),
+            ..Default::default()
+        }
+    }
+
+    /// Lays out the text for the given position in the window, returning what the sprite should be
+    pub fn layout(&self, position: &Position, window: &CrosstermWindow) -> String {
+        self.layout_from_lines(&self.layout_lines(position, window))
+    }
+
+    fn layout_from_lines(&self, lines: &[TextLine]) -> String {
+        let mut result = String::with_capacity(self.text.len());
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                result.push('\n');
+            }
+            result.push_str(&" ".repeat(line.indent));
+            for grapheme in line.graphemes.iter() {
+                result.push_str(&self.text[grapheme.0..grapheme.1]);
+            }
+            if line.ellipsis {
+                result.push_str(ELLIPSIS);
+            }
+        }
+        result
+    }
+
+    /// Lays out the text into lines of graphemes, which are byte ranges into the text
+    pub(crate) fn layout_lines(
+        &self,
+        position: &Position,
+        window: &CrosstermWindow,
+    ) -> Vec<TextLine> {
+        let max_width = self
+            .max_width
+            .unwrap_or_else(|| (window.width() as i32 - position.x).max(0) as usize);
+        let max_height = self
+            .max_height
+            .unwrap_or_else(|| (window.height() as i32 - position.y).max(0) as usize);
+
+        let mut lines = Vec::new();
+        if max_width == 0 || max_height == 0 {
+            return lines;
+        }
+        for paragraph in paragraphs(&self.text) {
+            match self.overflow {
+                TextOverflow::Wrap => wrap(&self.text, paragraph, max_width, &mut lines),
+                TextOverflow::Truncate | TextOverflow::Ellipsis => {
+                    let mut line = TextLine::default();
+                    for (start, grapheme) in graphemes(&self.text, paragraph) {
+                        line.push((start, start + grapheme.len()), grapheme.width());
+                    }
+                    if line.width > max_width {
+                        line.truncate(max_width, self.overflow == TextOverflow::Ellipsis);
+                    }
+                    lines.push(line);
+                }
+            }
+        }
+
+        if lines.len() > max_height {
+            lines.truncate(max_height);
+            if self.overflow == TextOverflow::Ellipsis {
+                if let Some(line) = lines.last_mut() {
+                    line.truncate(max_width.min(line.width + ELLIPSIS.width()), true);
+                }
+            }
+        }
+
+        // Explicit bounds are aligned within, otherwise lines are aligned to the widest one
+        let align_width = match self.max_width {
+            Some(max_width) => max_width,
+            None => lines.iter().map(TextLine::width).max().unwrap_or(0),
+        };
+        for line in lines.iter_mut() {
+            let space = align_width.saturating_sub(line.width());
+            line.indent = match self.align {
+                TextAlign::Left => 0,
+                TextAlign::Center => space / 2,
+                TextAlign::Right => space,
+            };
+        }
+
+        lines
+    }
+}
+
+/// A piece of rich text. Spans without a style of their own use the rich text's style.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct TextSpan {
+    pub text: String,
+    pub style: Option<Style>,
+}
+
+impl TextSpan {
+    pub fn new<T: std::string::ToString>(text: T) -> TextSpan {
+        TextSpan {
+            text: text.to_string(),
+            style: None,
+        }
+    }
+
+    pub fn styled<T: std::string::ToString>(text: T, style: Style) -> TextSpan {
+        TextSpan {
+            text: text.to_string(),
+            style: Some(style),
+        }
+    }
+}
+
+/// Text made of differently styled spans, which is laid out just like a Text. The entity's sprite and
+/// stylemap are both generated from the spans, so styles stay with their words when lines wrap.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct RichText {
+    pub spans: Vec<TextSpan>,
+    /// The style of everything outside a styled span. Span styles are applied on top of it.
+    pub style: Style,
+    pub max_width: Option<usize>,
+    pub max_height: Option<usize>,
+    pub align: TextAlign,
+    pub overflow: TextOverflow,
+}
+
+impl RichText {
+    pub fn new(spans: Vec<TextSpan>) -> RichText {
+        RichText {
+            spans,
+            ..Default::default()
+        }
+    }
+
+    /// Parses markup, where styles are given in brackets and apply until they're closed with `[/]`.
+    /// Tags are made of attribute names, colors for the foreground, and colors after "on" for the
+    /// background, like `"[bold red]warning:[/] disk is [yellow on blue]almost full[/]"`. Tags can
+    /// be nested, and a literal bracket is written as `[[`.
+    pub fn from_markup(markup: &str) -> Result<RichText, anyhow::Error> {
+        Ok(RichText::new(parse_markup(markup)?))
+    }
+
+    fn to_text(&self) -> Text {
+        Text {
+            text: self.spans.iter().map(|span| span.text.as_str()).collect(),
+            max_width: self.max_width,
+            max_height: self.max_height,
+            align: self.align,
+            overflow: self.overflow,
+        }
+    }
+
+    /// Lays out the spans for the given position in the window, returning the sprite and the
+    /// stylemap which colors it
+    pub fn layout(&self, position: &Position, window: &CrosstermWindow) -> (String, StyleMap) {
+        let text = self.to_text();
+        let lines = text.layout_lines(position, window);
+
+        // Where each span starts in the combined text, so graphemes can be matched to their span
+        let mut span_starts = Vec::with_capacity(self.spans.len());
+        let mut start = 0;
+        for span in self.spans.iter() {
+            span_starts.push(start);
+            start += span.text.len();
+        }
+        let style_of = |byte: usize| {
+            let span = match span_starts.binary_search(&byte) {
+                Ok(mut span) => {
+                    // Empty spans start at the same place as the span after them
+                    while self.spans[span].text.is_empty() {
+                        span += 1;
+                    }
+                    span
+                }
+                Err(span) => span - 1,
+            };
+            self.spans[span]
+                .style
+                .map(|style| merge_styles(self.style, style))
+        };
+
+        let mut cells = Vec::with_capacity(lines.len());
+        for line in lines.iter() {
+            let mut row = vec![None; line.indent];
+            row.extend(line.graphemes.iter().map(|grapheme| style_of(grapheme.0)));
+            if line.ellipsis {
+                row.push(row.last().copied().flatten());
+            }
+            cells.push(row);
+        }
+
+        let map = StyleGrid::from_cells_or_full(cells, self.style);
+        let stylemap = StyleMap::with_grid(self.style, map);
+
+        (text.layout_from_lines(&lines), stylemap)
+    }
+}
+
+/// Applies a span's style on top of the style around it
+pub(crate) fn merge_styles(base: Style, style: Style) -> Style {
+    let mut merged = base;
+    merged.colors = style.colors.with_default(base.colors);
+    for attribute in crossterm::style::Attribute::iterator() {
+        if attribute != crossterm::style::Attribute::Reset && style.attributes.has(attribute) {
+            merged.attributes.set(attribute);
+        }
+    }
+    merged
+}
+
+fn parse_tag(tag: &str) -> Result<Style, anyhow::Error> {
+    let parse_color = |name: &str| {
+        name.parse::<u8>()
+            .map(Color::AnsiValue)
+            .ok()
+            .or_else(|| components::color_parser::parse(name))
+            .ok_or_else(|| anyhow::anyhow!("unknown color \"{}\" in markup", name))
+    };
+
+    let mut style = Style::default();
+    let mut words = tag.split_whitespace();
+    while let Some(word) = words.next() {
+        if word.eq_ignore_ascii_case("on") {
+            let color = words
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("expected a background color after \"on\""))?;
+            style.colors.background = Some(parse_color(color)?);
+        } else if let Some(attribute) = components::attribute_parser::parse_name(word) {
+            style.attributes.set(attribute);
+        } else {
+            style.colors.foreground = Some(parse_color(word)?);
+        }
+    }
+    Ok(style)
+}
+
+fn parse_markup(markup: &str) -> Result<Vec<TextSpan>, anyhow::Error> {
+    let mut spans = Vec::new();
+    // The styles of the tags which are currently open, innermost last
+    let mut styles: Vec<Style> = Vec::new();
+    let mut current = String::new();
+
+    let mut rest = markup;
+    while let Some(open) = rest.find('[') {
+        current.push_str(&rest[..open]);
+        rest = &rest[open + 1..];
+        if let Some(after) = rest.strip_prefix('[') {
+            current.push('[');
+            rest = after;
+            continue;
+        }
+
+        let close = rest
+            .find(']')
+            .ok_or_else(|| anyhow::anyhow!("markup tag is never closed, use [[ for a bracket"))?;
+        let tag = &rest[..close];
+        rest = &rest[close + 1..];
+
+        if !current.is_empty() {
+            spans.push(TextSpan {
+                text: std::mem::take(&mut current),
+                style: styles.last().copied(),
+            });
+        }
+        if tag.starts_with('/') {
+            styles
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("[{}] doesn't close any tag", tag))?;
+        } else {
+            let style = parse_tag(tag)?;
+            let outer = styles.last().copied().unwrap_or_default();
+            styles.push(merge_styles(outer, style));
+        }
+    }
+    current.push_str(rest);
+    if !current.is_empty() {
+        spans.push(TextSpan {
+            text: current,
+            style: styles.last().copied(),
+        });
+    }
+
+    Ok(spans)
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TextLine {
+    /// How many spaces come before the line's graphemes
+    pub(crate) indent: usize,
+    pub(crate) graphemes: Vec<(usize, usize)>,
+    /// Whether the line ends with an ellipsis
+    pub(crate) ellipsis: bool,
+    width: usize,
+    widths: Vec<usize>,
+}
+
+impl TextLine {
+    fn push(&mut self, grapheme: (usize, usize), width: usize) {
+        self.graphemes.push(grapheme);
+        self.widths.push(width);
+        self.width += width;
+    }
+
+    /// The line's display width, not counting the indent
+    fn width(&self) -> usize {
+        self.width + if self.ellipsis { ELLIPSIS.width() } else { 0 }
+    }
+
+    fn truncate(&mut self, max_width: usize, ellipsis: bool) {
+        let ellipsis = ellipsis && max_width >= ELLIPSIS.width();
+        let max_width = if ellipsis {
+            max_width - ELLIPSIS.width()
+        } else {
+            max_width
+        };
+        while self.width > max_width {
+            self.graphemes.pop();
+            self.width -= self.widths.pop().unwrap_or(0);
+        }
+        self.ellipsis = ellipsis;
+    }
+
+    fn append(&mut self, other: &mut TextLine) {
+        for (grapheme, width) in std::mem::take(other).into_graphemes() {
+            self.push(grapheme, width);
+        }
+    }
+
+    fn into_graphemes(self) -> impl Iterator<Item = ((usize, usize), usize)> {
+        self.graphemes.into_iter().zip(self.widths)
+    }
+}
+
+/// Splits text into byte ranges for each of its lines
+fn paragraphs(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut start = 0;
+    text.split_terminator('\n').map(move |line| {
+        let range = (start, start + line.trim_end_matches('\r').len());
+        start += line.len() + 1;
+        range
+    })
+}
+
+fn graphemes(text: &str, range: (usize, usize)) -> impl Iterator<Item = (usize, &str)> {
+    UnicodeSegmentation::grapheme_indices(&text[range.0..range.1], true)
+        .map(move |(start, grapheme)| (range.0 + start, grapheme))
+}
+
+/// Greedily wraps a paragraph into lines that are at most max_width wide. Whitespace is dropped where
+/// lines are broken, and words which are too wide for a line get broken up.
+fn wrap(text: &str, paragraph: (usize, usize), max_width: usize, lines: &mut Vec<TextLine>) {
+    let mut line = TextLine::default();
+    // The whitespace since the last word, which is only kept if the next word fits on the same line
+    let mut gap = TextLine::default();
+    let mut word = TextLine::default();
+
+    let mut finish_word = |line: &mut TextLine, gap: &mut TextLine, word: &mut TextLine| {
+        if word.graphemes.is_empty() {
+            return;
+        }
+        if !line.graphemes.is_empty() && line.width + gap.width + word.width > max_width {
+            lines.push(std::mem::take(line));
+            *gap = TextLine::default();
+        }
+        line.append(gap);
+
+        for (grapheme, width) in std::mem::take(word).into_graphemes() {
+            if line.width + width > max_width && !line.graphemes.is_empty() {
+                lines.push(std::mem::take(line));
+            }
+            line.push(grapheme, width);
+        }
+    };
+
+    for (start, grapheme) in graphemes(text, paragraph) {
+        let range = (start, start + grapheme.len());
+        if grapheme.trim().is_empty() {
+            finish_word(&mut line, &mut gap, &mut word);
+            gap.push(range, grapheme.width());
+        } else {
+            word.push(range, grapheme.width());
+        }
+    }
+    finish_word(&mut line, &mut gap, &mut word);
+
+    // Keep indentation and blank lines, since whitespace only gets dropped where lines are broken
+    if line.graphemes.is_empty() {
+        line.append(&mut gap);
+        if line.width > max_width {
+            line.truncate(max_width, false);
+        }
+    }
+    lines.push(line);
+}
+
+pub(crate) fn update_text(
+    window: Res<CrosstermWindow>,
+    mut resize_reader: Local<EventReader<WindowResized>>,
+    resize_events: Res<Events<WindowResized>>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut texts: QuerySet<(
+        Query<(&Text, &Position, &mut Handle<Sprite>), Or<(Changed<Text>, Changed<Position>)>>,
+        Query<(&Text, &Position, &mut Handle<Sprite>)>,
+    )>,
+) {
+    let mut relayout = |text: &Text, position: &Position, mut sprite: Mut<Handle<Sprite>>| {
+        components::set_sprite(&mut sprites, &mut sprite, text.layout(position, &window));
+    };
+
+    if resize_reader.iter(&resize_events).next().is_some() {
+        for (text, position, sprite) in texts.q1_mut().iter_mut() {
+            relayout(text, position, sprite);
+        }
+    } else {
+        for (text, position, sprite) in texts.q0_mut().iter_mut() {
+            relayout(text, position, sprite);
+        }
+    }
+}
+
+pub(crate) fn update_rich_text(
+    window: Res<CrosstermWindow>,
+    mut resize_reader: Local<EventReader<WindowResized>>,
+    resize_events: Res<Events<WindowResized>>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut texts: QuerySet<(
+        Query<
+            (
+                &RichText,
+                &Position,
+                &mut Handle<Sprite>,
+                &mut Handle<StyleMap>,
+            ),
+            Or<(Changed<RichText>, Changed<Position>)>,
+        >,
+        Query<(
+            &RichText,
+            &Position,
+            &mut Handle<Sprite>,
+            &mut Handle<StyleMap>,
+        )>,
+    )>,
+) {
+    let mut relayout = |text: &RichText,
+                        position: &Position,
+                        mut sprite: Mut<Handle<Sprite>>,
+                        mut stylemap: Mut<Handle<StyleMap>>| {
+        let (laid_out, styles) = text.layout(position, &window);
+        components::set_sprite(&mut sprites, &mut sprite, laid_out);
+        components::set_stylemap(&mut stylemaps, &mut stylemap, styles);
+    };
+
+    if resize_reader.iter(&resize_events).next().is_some() {
+        for (text, position, sprite, stylemap) in texts.q1_mut().iter_mut() {
+            relayout(text, position, sprite, stylemap);
+        }
+    } else {
+        for (text, position, sprite, stylemap) in texts.q0_mut().iter_mut() {
+            relayout(text, position, sprite, stylemap);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Colors;
+    use crossterm::style::Attribute;
+
+    fn window() -> CrosstermWindow {
+        CrosstermWindow {
+            width: 80,
+            height: 24,
+            colors: Colors::default(),
+            title: None,
+        }
+    }
+
+    fn layout(text: Text) -> String {
+        text.layout(&Position::default(), &window())
+    }
+
+    #[test]
+    fn markup_becomes_spans() {
+        let spans = parse_markup("[bold red]warning:[/] disk is [[ [yellow on 4]full[/]").unwrap();
+        let texts: Vec<&str> = spans.iter().map(|span| span.text.as_str()).collect();
+        assert_eq!(texts, ["warning:", " disk is [ ", "full"]);
+
+        let warning = spans[0].style.unwrap();
+        assert!(warning.attributes.has(Attribute::Bold));
+        assert_eq!(warning.colors, Colors::fg(Color::Red));
+        assert!(spans[1].style.is_none());
+        assert_eq!(
+            spans[2].style.unwrap().colors,
+            Colors::new(Color::Yellow, Color::AnsiValue(4))
+        );
+    }
+
+    #[test]
+    fn nested_tags_build_on_each_other() {
+        let spans = parse_markup("[red]a[bold]b[on #000000]c[/]d[/]e[/]").unwrap();
+        let style = |i: usize| spans[i].style.unwrap();
+        assert_eq!(style(0).colors, Colors::fg(Color::Red));
+        assert!(style(1).attributes.has(Attribute::Bold));
+        assert_eq!(style(1).colors, Colors::fg(Color::Red));
+        assert_eq!(
+            style(2).colors,
+            Colors::new(Color::Red, Color::Rgb { r: 0, g: 0, b: 0 })
+        );
+        assert!(style(3).attributes.has(Attribute::Bold));
+        assert!(!style(4).attributes.has(Attribute::Bold));
+    }
+
+    #[test]
+    fn bad_markup_is_rejected() {
+        assert!(parse_markup("[red").is_err());
+        assert!(parse_markup("text[/]").is_err());
+        assert!(parse_markup("[purple]text").is_err());
+        assert!(parse_markup("[red on]text").is_err());
+    }
+
+    #[test]
+    fn text_wraps_between_words() {
+        assert_eq!(
+            layout(Text::bounded("the quick brown fox", 10, 5)),
+            "the quick\nbrown fox"
+        );
+        assert_eq!(layout(Text::bounded("abcdefghij", 4, 5)), "abcd\nefgh\nij");
+        assert_eq!(layout(Text::bounded("a\n\n  b", 4, 5)), "a\n\n  b");
+        assert_eq!(layout(Text::bounded("one two three", 5, 2)), "one\ntwo");
+    }
+
+    #[test]
+    fn text_overflows() {
+        let mut text = Text::bounded("a long line\nand more\nand more", 6, 2);
+        text.overflow = TextOverflow::Truncate;
+        assert_eq!(layout(text.clone()), "a long\nand mo");
+        text.overflow = TextOverflow::Ellipsis;
+        assert_eq!(layout(text), "a lon…\nand m…");
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(layout(Text::bounded("日本語", 5, 5)), "日本\n語");
+    }
+
+    #[test]
+    fn text_is_aligned() {
+        let mut text = Text::bounded("ab\nabcd", 6, 5);
+        text.align = TextAlign::Right;
+        assert_eq!(layout(text.clone()), "    ab\n  abcd");
+        text.align = TextAlign::Center;
+        assert_eq!(layout(text), "  ab\n abcd");
+    }
+
+    #[test]
+    fn rich_text_styles_follow_their_words() {
+        let mut text = RichText::from_markup("plain [red]red words[/]").unwrap();
+        text.max_width = Some(8);
+        let (laid_out, stylemap) = text.layout(&Position::default(), &window());
+        assert_eq!(laid_out, "plain\nred\nwords");
+        assert!(stylemap.style_at(0, 0).is_none());
+        assert_eq!(
+            stylemap.style_at(0, 1).map(|style| style.colors),
+            Some(Colors::fg(Color::Red))
+        );
+        assert_eq!(
+            stylemap.style_at(4, 2).map(|style| style.colors),
+            Some(Colors::fg(Color::Red))
+        );
+    }
+}
@@ -0,0 +1,568 @@
+use bevy::prelude::*;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::components::{self, DrawArea, Position, Sprite, Style, StyleGrid, StyleMap};
+use crate::focus::{Focus, Focused, FocusedKeyEvent};
+use crate::layers::RenderLayers;
+use crate::viewport::ActiveViewports;
+use crate::Cursor;
+
+/// A single line of editable text. While it's focused, it takes typed text, moves the cursor with
+/// the arrow keys, home and end, selects text while shift is held, and goes through previously
+/// submitted text with up and down. Enter submits the text.
+///
+/// The entity's sprite and stylemap are generated from the input, and the `Cursor` is moved to the
+/// input while it's focused. Text inputs use the arrow keys, so they should be made focusable with
+/// `Focusable::with_arrow_keys`.
+#[derive(Clone, PartialEq)]
+pub struct TextInput {
+    /// How many columns are shown. The text scrolls sideways to keep the cursor in view.
+    pub width: usize,
+    /// Shown while there's no text
+    pub placeholder: String,
+    /// When set, every character is shown as this character instead, for passwords
+    pub mask: Option<char>,
+    /// How many submitted texts are remembered
+    pub max_history: usize,
+    pub style: Style,
+    pub placeholder_style: Style,
+    pub selection_style: Style,
+    text: String,
+    // Positions are counted in graphemes
+    cursor: usize,
+    // Where the selection started, the other end of the selection is the cursor
+    anchor: Option<usize>,
+    scroll: usize,
+    history: Vec<String>,
+    history_index: Option<usize>,
+    // What was being typed before going back through the history
+    draft: String,
+}
+
+impl TextInput {
+    pub fn new(width: usize) -> TextInput {
+        TextInput {
+            width,
+            placeholder: String::new(),
+            mask: None,
+            max_history: 50,
+            style: Style::default(),
+            placeholder_style: Style::with_attrib(crossterm::style::Attribute::Dim),
+            selection_style: Style::with_attrib(crossterm::style::Attribute::Reverse),
+            text: String::new(),
+            cursor: 0,
+            anchor: None,
+            scroll: 0,
+            history: Vec::new(),
+            history_index: None,
+            draft: String::new(),
+        }
+    }
+
+    pub fn with_placeholder<T: std::string::ToString>(width: usize, placeholder: T) -> TextInput {
+        TextInput {
+            placeholder: placeholder.to_string(),
+            ..TextInput::new(width)
+        }
+    }
+
+    pub fn password(width: usize) -> TextInput {
+        TextInput {
+            mask: Some('*'),
+            ..TextInput::new(width)
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the text and puts the cursor at the end of it
+    pub fn set_text<T: std::string::ToString>(&mut self, text: T) {
+        self.text = text.to_string();
+        self.cursor = self.len();
+        self.anchor = None;
+    }
+
+    /// Where the cursor is, in graphemes
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The start and end of the selected graphemes, if anything is selected
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        let anchor = self.anchor?;
+        if anchor == self.cursor {
+            None
+        } else {
+            Some((anchor.min(self.cursor), anchor.max(self.cursor)))
+        }
+    }
+
+    pub fn selected_text(&self) -> &str {
+        match self.selection() {
+            Some((start, end)) => {
+                let offsets = self.offsets();
+                &self.text[offsets[start]..offsets[end]]
+            }
+            None => "",
+        }
+    }
+
+    /// Previously submitted text, oldest first
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    fn len(&self) -> usize {
+        self.text.graphemes(true).count()
+    }
+
+    /// The byte offset where each grapheme starts, followed by the length of the text
+    fn offsets(&self) -> Vec<usize> {
+        self.text
+            .grapheme_indices(true)
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(self.text.len()))
+            .collect()
+    }
+
+    fn move_to(&mut self, index: usize, extend_selection: bool) {
+        if extend_selection {
+            self.anchor.get_or_insert(self.cursor);
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = index.min(self.len());
+    }
+
+    /// Replaces the selection, if there is one, with some text
+    fn insert(&mut self, text: &str) {
+        let (start, end) = self.selection().unwrap_or((self.cursor, self.cursor));
+        let offsets = self.offsets();
+        let inserted_end = offsets[start] + text.len();
+        self.text.replace_range(offsets[start]..offsets[end], text);
+        self.anchor = None;
+        // The text can join onto the grapheme before it, like a combining accent does, so the
+        // cursor goes after the grapheme the end of the text ends up in
+        self.cursor = self
+            .text
+            .grapheme_indices(true)
+            .take_while(|(offset, _)| *offset < inserted_end)
+            .count();
+    }
+
+    /// Deletes the selection, or if nothing is selected, the grapheme before or after the cursor
+    fn delete(&mut self, forwards: bool) -> bool {
+        if self.selection().is_none() {
+            let other_end = if forwards {
+                (self.cursor + 1).min(self.len())
+            } else {
+                self.cursor.saturating_sub(1)
+            };
+            if other_end == self.cursor {
+                return false;
+            }
+            self.anchor = Some(other_end);
+        }
+        self.insert("");
+        true
+    }
+
+    /// Finds the start of the word before the cursor, or the end of the word after it
+    fn word_boundary(&self, forwards: bool) -> usize {
+        let graphemes: Vec<&str> = self.text.graphemes(true).collect();
+        let is_space = |index: usize| graphemes[index].trim().is_empty();
+        let mut index = self.cursor;
+        if forwards {
+            while index < graphemes.len() && is_space(index) {
+                index += 1;
+            }
+            while index < graphemes.len() && !is_space(index) {
+                index += 1;
+            }
+        } else {
+            while index > 0 && is_space(index - 1) {
+                index -= 1;
+            }
+            while index > 0 && !is_space(index - 1) {
+                index -= 1;
+            }
+        }
+        index
+    }
+
+    /// Replaces the text with an older or newer entry in the history, or with the draft once there
+    /// are no newer entries. Returns whether the text changed
+    fn browse_history(&mut self, older: bool) -> bool {
+        let index = match (self.history_index, older) {
+            (None, true) if !self.history.is_empty() => {
+                self.draft = self.text.clone();
+                Some(self.history.len() - 1)
+            }
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < self.history.len() => Some(index + 1),
+            (Some(_), false) => None,
+            (None, _) => return false,
+        };
+        self.history_index = index;
+        let text = match index {
+            Some(index) => self.history[index].clone(),
+            None => std::mem::take(&mut self.draft),
+        };
+        if text == self.text {
+            return false;
+        }
+        self.set_text(text);
+        true
+    }
+
+    /// Takes the text and remembers it in the history
+    fn submit(&mut self) -> String {
+        let text = std::mem::take(&mut self.text);
+        if !text.is_empty() && self.history.last() != Some(&text) && self.max_history > 0 {
+            self.history.push(text.clone());
+            if self.history.len() > self.max_history {
+                self.history.remove(0);
+            }
+        }
+        self.history_index = None;
+        self.draft.clear();
+        self.cursor = 0;
+        self.anchor = None;
+        text
+    }
+
+    /// Edits the input for a key press. Returns whether the text changed
+    fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('a') if control => {
+                self.anchor = Some(0);
+                self.cursor = self.len();
+            }
+            KeyCode::Char(c) if !control => {
+                self.insert(c.encode_utf8(&mut [0; 4]));
+                return true;
+            }
+            KeyCode::Backspace => return self.delete(false),
+            KeyCode::Delete => return self.delete(true),
+            KeyCode::Left if control => self.move_to(self.word_boundary(false), shift),
+            KeyCode::Right if control => self.move_to(self.word_boundary(true), shift),
+            KeyCode::Left => match self.selection() {
+                Some((start, _)) if !shift => self.move_to(start, false),
+                _ => self.move_to(self.cursor.saturating_sub(1), shift),
+            },
+            KeyCode::Right => match self.selection() {
+                Some((_, end)) if !shift => self.move_to(end, false),
+                _ => self.move_to(self.cursor + 1, shift),
+            },
+            KeyCode::Home => self.move_to(0, shift),
+            KeyCode::End => self.move_to(self.len(), shift),
+            KeyCode::Up => return self.browse_history(true),
+            KeyCode::Down => return self.browse_history(false),
+            KeyCode::Esc => self.anchor = None,
+            _ => {}
+        }
+        false
+    }
+
+    /// What's shown for each grapheme of the text
+    fn shown_graphemes(&self) -> Vec<String> {
+        self.text
+            .graphemes(true)
+            .map(|grapheme| match self.mask {
+                Some(mask) => mask.to_string(),
+                None => grapheme.to_string(),
+            })
+            .collect()
+    }
+
+    /// Scrolls as little as possible to keep the cursor in view, leaving room for it at the end.
+    /// Scrolls back when the text after the scroll no longer fills the input.
+    fn scroll_to_cursor(&self) -> usize {
+        let shown = self.shown_graphemes();
+        let columns = |from: usize, to: usize| -> usize {
+            shown[from..to]
+                .iter()
+                .map(|grapheme| grapheme.width())
+                .sum()
+        };
+        let mut scroll = self.scroll.min(self.cursor);
+        while scroll > 0 && columns(scroll - 1, shown.len()) < self.width {
+            scroll -= 1;
+        }
+        while scroll < self.cursor && columns(scroll, self.cursor) >= self.width {
+            scroll += 1;
+        }
+        scroll
+    }
+
+    /// Which grapheme is shown at a column of the input
+    fn grapheme_at_column(&self, column: usize) -> usize {
+        let mut columns = 0;
+        for (index, grapheme) in self.shown_graphemes().iter().enumerate().skip(self.scroll) {
+            columns += grapheme.width();
+            if columns > column {
+                return index;
+            }
+        }
+        self.len()
+    }
+
+    /// How many columns the cursor is from the start of the input
+    fn cursor_column(&self) -> usize {
+        self.shown_graphemes()[self.scroll.min(self.cursor)..self.cursor]
+            .iter()
+            .map(|grapheme| grapheme.width())
+            .sum()
+    }
+}
+
+/// Sent whenever the text of a text input changes
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TextChanged {
+    pub entity: Entity,
+    pub text: String,
+}
+
+/// Sent when enter is pressed in a text input. The input is cleared afterwards.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TextSubmitted {
+    pub entity: Entity,
+    pub text: String,
+}
+
+pub(crate) fn text_input_input(
+    mut key_reader: Local<EventReader<FocusedKeyEvent>>,
+    key_events: Res<Events<FocusedKeyEvent>>,
+    mut mouse_reader: Local<EventReader<MouseEvent>>,
+    mouse_events: Res<Events<MouseEvent>>,
+    focus: Res<Focus>,
+    viewports: Res<ActiveViewports>,
+    mut changed_events: ResMut<Events<TextChanged>>,
+    mut submitted_events: ResMut<Events<TextSubmitted>>,
+    mut inputs: Query<(
+        Entity,
+        &mut TextInput,
+        &Position,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
+) {
+    for event in key_reader.iter(&key_events) {
+        let (entity, mut input, ..) = match inputs.get_mut(event.entity) {
+            Ok(input) => input,
+            Err(_) => continue,
+        };
+        if event.key.code == KeyCode::Enter {
+            let text = input.submit();
+            // Submitting clears the text, which is only a change if there was any
+            let cleared = !text.is_empty();
+            submitted_events.send(TextSubmitted { entity, text });
+            if cleared {
+                changed_events.send(TextChanged {
+                    entity,
+                    text: String::new(),
+                });
+            }
+        } else if input.handle_key(&event.key) {
+            changed_events.send(TextChanged {
+                entity,
+                text: input.text.clone(),
+            });
+        }
+    }
+
+    // Clicking puts the cursor under the mouse, and dragging selects
+    for event in mouse_reader.iter(&mouse_events) {
+        let (column, row, extend_selection) = match *event {
+            MouseEvent::Down(MouseButton::Left, column, row, _) => (column, row, false),
+            MouseEvent::Drag(MouseButton::Left, column, row, _) => (column, row, true),
+            _ => continue,
+        };
+        for (entity, mut input, position, area, layers) in inputs.iter_mut() {
+            if focus.entity != Some(entity) {
+                continue;
+            }
+            let size = (input.width, 1);
+            let column = match viewports.cell_at(position, size, area, layers, (column, row)) {
+                Some((column, _)) => column,
+                None => continue,
+            };
+            let index = input.grapheme_at_column(column);
+            if !extend_selection || input.cursor != index {
+                input.move_to(index, extend_selection);
+            }
+        }
+    }
+}
+
+pub(crate) fn update_text_inputs(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut inputs: Query<
+        (&mut TextInput, &mut Handle<Sprite>, &mut Handle<StyleMap>),
+        Changed<TextInput>,
+    >,
+) {
+    for (mut input, mut sprite, mut stylemap) in inputs.iter_mut() {
+        let scroll = input.scroll_to_cursor();
+        if input.scroll != scroll {
+            input.scroll = scroll;
+        }
+
+        let (graphemes, base_style) = if input.text.is_empty() {
+            let placeholder = input.placeholder.graphemes(true).map(str::to_string);
+            (placeholder.collect(), input.placeholder_style)
+        } else {
+            (input.shown_graphemes(), input.style)
+        };
+
+        let mut shown = String::new();
+        let mut cells = Vec::new();
+        let mut columns = 0;
+        let selection = input.selection();
+        for (index, grapheme) in graphemes.iter().enumerate().skip(scroll) {
+            columns += grapheme.width();
+            if columns > input.width {
+                break;
+            }
+            shown.push_str(grapheme);
+            let selected = selection.map_or(false, |(start, end)| index >= start && index < end);
+            cells.push(if selected {
+                Some(input.selection_style)
+            } else {
+                None
+            });
+        }
+        shown.push_str(&" ".repeat(input.width.saturating_sub(columns.min(input.width))));
+
+        components::set_sprite(&mut sprites, &mut sprite, shown);
+        components::set_stylemap(
+            &mut stylemaps,
+            &mut stylemap,
+            StyleMap::with_grid(
+                base_style,
+                StyleGrid::from_cells_or_full(std::iter::once(cells), base_style),
+            ),
+        );
+    }
+}
+
+/// Moves the terminal's cursor to the focused text input. The cursor is hidden again once no text
+/// input is focused, but is left alone otherwise so it can still be used for other things.
+pub(crate) fn text_input_cursor(
+    mut showing_cursor: Local<bool>,
+    mut cursor: ResMut<Cursor>,
+    viewports: Res<ActiveViewports>,
+    inputs: Query<
+        (
+            &TextInput,
+            &Position,
+            Option<&DrawArea>,
+            Option<&RenderLayers>,
+        ),
+        With<Focused>,
+    >,
+) {
+    // The cursor goes where the input is drawn in the first viewport that shows it
+    let focused = inputs.iter().find_map(|(input, position, area, layers)| {
+        let area = viewports.draw_areas(area, layers).next()?;
+        Some((input, position, area))
+    });
+    match focused {
+        Some((input, position, area)) => {
+            let x = position.x + area.offset_x + input.cursor_column() as i32;
+            let y = position.y + area.offset_y;
+            if cursor.x != x || cursor.y != y || cursor.hidden {
+                cursor.x = x;
+                cursor.y = y;
+                cursor.hidden = false;
+            }
+            *showing_cursor = true;
+        }
+        None => {
+            if *showing_cursor {
+                cursor.hidden = true;
+                *showing_cursor = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(input: &mut TextInput, code: KeyCode) -> bool {
+        input.handle_key(&KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn typed(text: &str) -> TextInput {
+        let mut input = TextInput::new(10);
+        for c in text.chars() {
+            press(&mut input, KeyCode::Char(c));
+        }
+        input
+    }
+
+    #[test]
+    fn keys_edit_the_text() {
+        let mut input = typed("héllo");
+        assert!(!press(&mut input, KeyCode::Left));
+        assert!(press(&mut input, KeyCode::Backspace));
+        assert_eq!(input.text, "hélo");
+        assert_eq!(input.cursor(), 3);
+        assert!(press(&mut input, KeyCode::Delete));
+        assert!(!press(&mut input, KeyCode::Delete));
+        assert_eq!(input.text, "hél");
+
+        let shift_home = KeyEvent::new(KeyCode::Home, KeyModifiers::SHIFT);
+        assert!(!input.handle_key(&shift_home));
+        assert_eq!(input.selection(), Some((0, 3)));
+        assert!(press(&mut input, KeyCode::Char('x')));
+        assert_eq!(input.text, "x");
+    }
+
+    #[test]
+    fn combining_marks_join_the_grapheme_before_them() {
+        let mut input = typed("e\u{301}");
+        assert_eq!(input.text, "e\u{301}");
+        assert_eq!(input.cursor(), 1);
+        assert_eq!(input.cursor_column(), 1);
+        assert!(press(&mut input, KeyCode::Backspace));
+        assert_eq!(input.text, "");
+        assert!(!press(&mut input, KeyCode::Left));
+        assert_eq!(input.cursor(), 0);
+
+        let mut input = typed("ae\u{301}b");
+        assert!(press(&mut input, KeyCode::Backspace));
+        assert!(press(&mut input, KeyCode::Backspace));
+        assert!(!press(&mut input, KeyCode::Left));
+        assert_eq!(input.text, "a");
+        assert_eq!(input.cursor(), 0);
+    }
+
+    #[test]
+    fn history_only_changes_the_text_when_theres_something_else_to_show() {
+        let mut input = typed("first");
+        input.submit();
+        assert!(!press(&mut input, KeyCode::Down));
+
+        input.set_text("draft");
+        assert!(press(&mut input, KeyCode::Up));
+        assert_eq!(input.text, "first");
+        // There's nothing older, so the text stays the same
+        assert!(!press(&mut input, KeyCode::Up));
+        assert!(press(&mut input, KeyCode::Down));
+        assert_eq!(input.text, "draft");
+        assert!(!press(&mut input, KeyCode::Down));
+
+        let mut empty = TextInput::new(10);
+        assert!(!press(&mut empty, KeyCode::Up));
+    }
+}
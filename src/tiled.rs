@@ -0,0 +1,1151 @@
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::{HashMap, HashSet};
+use serde::Deserialize;
+
+use crate::components::{attribute_parser, color_parser, Color, Colors, Position, Style, Visible};
+use crate::tilemap::{Tile, Tilemap, TilemapBundle, Tileset};
+
+/// The label of the tileset that's made for a Tiled map when it's loaded, so "level.tmx#tileset" is
+/// the tileset the level's tiles are drawn with
+pub const TILED_TILESET_LABEL: &str = "tileset";
+
+// The top bits of a gid say how the tile is flipped or rotated, which the terminal can't show
+const GID_MASK: u32 = 0x0fff_ffff;
+
+/// A custom property set on a map, layer, object or tile in Tiled
+#[derive(Clone, Debug, PartialEq)]
+pub enum TiledProperty {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// A color, or None if it was left unset
+    Color(Option<Color>),
+    /// A path to a file, relative to the map
+    File(String),
+    /// The id of an object in the map, or 0 for none
+    Object(u32),
+}
+
+impl TiledProperty {
+    /// Parses a property from the type and value Tiled writes for it in a .tmx file. Properties of
+    /// custom classes are skipped, since they need the Tiled project to make sense of.
+    fn parse(kind: &str, value: &str) -> anyhow::Result<Option<TiledProperty>> {
+        let property = match kind {
+            "string" => TiledProperty::String(value.to_string()),
+            "bool" => TiledProperty::Bool(value == "true"),
+            "int" => TiledProperty::Int(parse_value(value, "int")?),
+            "float" => TiledProperty::Float(parse_value(value, "float")?),
+            "color" => TiledProperty::Color(parse_color(value)),
+            "file" => TiledProperty::File(value.to_string()),
+            "object" => TiledProperty::Object(parse_value(value, "object")?),
+            _ => return Ok(None),
+        };
+        Ok(Some(property))
+    }
+}
+
+/// The custom properties of something in a Tiled map, by name
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TiledProperties(pub HashMap<String, TiledProperty>);
+
+impl TiledProperties {
+    pub fn get(&self, name: &str) -> Option<&TiledProperty> {
+        self.0.get(name)
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            TiledProperty::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            TiledProperty::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// A float property, or an int property as a float
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        match self.get(name)? {
+            TiledProperty::Float(value) => Some(*value),
+            TiledProperty::Int(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    /// A string or file property
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            TiledProperty::String(value) | TiledProperty::File(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// A color property, or a string property with a color name or hex code in it
+    pub fn get_color(&self, name: &str) -> Option<Color> {
+        match self.get(name)? {
+            TiledProperty::Color(color) => *color,
+            TiledProperty::String(value) => parse_color(value),
+            _ => None,
+        }
+    }
+}
+
+/// An object from one of a Tiled map's object layers. Each object is spawned as an entity with this
+/// component and a Position, so the app can find them and give them whatever else they need.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiledObject {
+    pub id: u32,
+    pub name: String,
+    /// The object's type, which newer versions of Tiled call its class
+    pub kind: String,
+    /// Where the object is, in cells from the top left of its layer
+    pub x: i32,
+    pub y: i32,
+    /// The object's size in cells, rounded from its size in Tiled
+    pub width: usize,
+    pub height: usize,
+    /// The tile a tile object is drawn with, as an id in the map's tileset
+    pub tile: Option<u32>,
+    pub visible: bool,
+    pub properties: TiledProperties,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TiledLayerContent {
+    Tiles(Tilemap),
+    Objects(Vec<TiledObject>),
+}
+
+/// A layer of a Tiled map. Layers in groups are flattened out, with the offsets and visibility of
+/// their groups applied. Image layers are skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiledLayer {
+    pub name: String,
+    /// Where the layer's top left corner is, in cells from the top left of the map
+    pub x: i32,
+    pub y: i32,
+    pub visible: bool,
+    pub properties: TiledProperties,
+    pub content: TiledLayerContent,
+}
+
+/// A map made with the Tiled editor, loaded from a .tmx file or a .json or .tmj file.
+///
+/// Tiled tiles are images, so the glyph a tile is drawn with comes from a "glyph" property set on it
+/// in the tileset. Its colors come from "foreground" and "background" properties (or "fg" and "bg"),
+/// which can be colors or strings like "dark_red", and its attributes from an "attributes" property
+/// like "bold, underlined". Tiles without a glyph are left empty.
+///
+/// Spawn it with a TiledMapBundle, and each layer is spawned as a child of the map's entity. The map
+/// is spawned again whenever it's reloaded. Editing a tileset that's in a file of its own doesn't
+/// reload the map, only editing the map does.
+///
+/// The layers and objects are placed from the map entity's Position when they're spawned, and don't
+/// follow it after that. To move a map, move its layers' entities, or set the entity's map handle
+/// again to spawn it afresh where the entity is now (which replaces the objects' entities too).
+#[derive(Clone, TypeUuid)]
+#[uuid = "b2f1d6a4-7c39-4e0b-8a5d-3c9e61f0d2b7"]
+pub struct TiledMap {
+    /// The size of the map in tiles. Infinite maps may have tiles outside of it.
+    pub width: usize,
+    pub height: usize,
+    /// The tileset made from the tile properties of all the map's tilesets
+    pub tileset: Handle<Tileset>,
+    pub layers: Vec<TiledLayer>,
+    pub properties: TiledProperties,
+}
+
+/// Marks an entity spawned for a layer of a Tiled map. Tile layers are spawned as tilemaps, and the
+/// objects of object layers are spawned as children of their layer's entity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiledMapLayer {
+    pub name: String,
+    /// Where the layer is in the map, counting from the bottom. It's added to the map's z.
+    pub index: usize,
+    pub properties: TiledProperties,
+}
+
+#[derive(Bundle, Default)]
+pub struct TiledMapBundle {
+    pub map: Handle<TiledMap>,
+    pub position: Position,
+}
+
+/// A tileset a map uses, which is either in the map or in a file of its own
+pub(crate) struct TilesetRef {
+    first_gid: u32,
+    /// The tileset's file, relative to the map, until it's been read
+    pub source: Option<String>,
+    /// The tiles' ids and properties
+    pub tiles: Vec<(u32, TiledProperties)>,
+}
+
+/// A map that's been read, but whose tiles are still gids from Tiled's tilesets
+pub(crate) struct ParsedMap {
+    map: TiledMap,
+    pub tilesets: Vec<TilesetRef>,
+}
+
+impl ParsedMap {
+    /// Makes a tileset from the properties of the tiles in the map's tilesets, and swaps the map's
+    /// gids for ids in it. The map's handle to the tileset still has to be set.
+    pub(crate) fn finish(self) -> (TiledMap, Tileset) {
+        let mut tileset = Tileset::default();
+        let mut ids = HashMap::default();
+        for tileset_ref in self.tilesets.iter() {
+            for (id, properties) in tileset_ref.tiles.iter() {
+                if let Some(tile) = tile_from_properties(properties) {
+                    ids.insert(tileset_ref.first_gid + id, tileset.tiles.len() as u32);
+                    tileset.tiles.push(tile);
+                }
+            }
+        }
+
+        let mut map = self.map;
+        for layer in map.layers.iter_mut() {
+            match &mut layer.content {
+                TiledLayerContent::Tiles(tilemap) => {
+                    tilemap.map_tiles(|gid| ids.get(&gid).copied());
+                }
+                TiledLayerContent::Objects(objects) => {
+                    for object in objects.iter_mut() {
+                        object.tile = object.tile.and_then(|gid| ids.get(&gid).copied());
+                    }
+                }
+            }
+        }
+        (map, tileset)
+    }
+}
+
+fn tile_from_properties(properties: &TiledProperties) -> Option<Tile> {
+    let glyph = properties.get_str("glyph")?.chars().next()?;
+    let color = |names: [&str; 2]| names.iter().find_map(|name| properties.get_color(name));
+    let colors = Colors {
+        foreground: color(["foreground", "fg"]),
+        background: color(["background", "bg"]),
+    };
+    let mut attributes: crossterm::style::Attributes = crossterm::style::Attribute::Reset.into();
+    if let Some(names) = properties.get_str("attributes") {
+        let names = names.split(|c: char| c == ',' || c.is_whitespace());
+        for attribute in names.filter_map(attribute_parser::parse_name) {
+            attributes.set(attribute);
+        }
+    }
+    Some(Tile::new(glyph, Style::new(colors, attributes)))
+}
+
+// Tiled writes colors as "#aarrggbb", and there's no alpha in a terminal
+fn parse_color(value: &str) -> Option<Color> {
+    match value.strip_prefix('#') {
+        Some(hex) if hex.len() == 8 && hex.is_ascii() => {
+            color_parser::parse(&format!("#{}", &hex[2..]))
+        }
+        _ => color_parser::parse(value),
+    }
+}
+
+fn parse_value<T: FromStr>(value: &str, kind: &str) -> anyhow::Result<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("\"{}\" isn't a valid {}", value, kind))
+}
+
+// Tiled measures offsets and objects in pixels
+fn cells(pixels: f32, tile_size: u32) -> i32 {
+    (pixels / tile_size.max(1) as f32).round() as i32
+}
+
+/// Where an object is and how big it is in cells. Tile objects are positioned by their bottom left
+/// corner rather than their top left.
+fn object_cells(
+    (x, y, width, height): (f32, f32, f32, f32),
+    is_tile: bool,
+    (tile_width, tile_height): (u32, u32),
+) -> (i32, i32, usize, usize) {
+    let (width, height) = match is_tile {
+        true if width == 0.0 && height == 0.0 => (tile_width as f32, tile_height as f32),
+        _ => (width, height),
+    };
+    let y = if is_tile { y - height } else { y };
+    (
+        cells(x, tile_width),
+        cells(y, tile_height),
+        cells(width, tile_width).max(0) as usize,
+        cells(height, tile_height).max(0) as usize,
+    )
+}
+
+/// A rectangle of a tile layer's gids. Infinite maps split their layers into chunks, and finite maps
+/// have one chunk for the whole layer.
+struct Chunk {
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+    gids: Vec<u32>,
+}
+
+/// Puts a layer's chunks together into a tilemap of gids, which starts at the top left of the top
+/// and left most chunks
+fn chunks_to_tilemap(chunks: &[Chunk]) -> anyhow::Result<(Tilemap, i32, i32)> {
+    let left = chunks.iter().map(|chunk| chunk.x).min().unwrap_or(0);
+    let top = chunks.iter().map(|chunk| chunk.y).min().unwrap_or(0);
+    let right = chunks
+        .iter()
+        .map(|chunk| chunk.x + chunk.width as i32)
+        .max()
+        .unwrap_or(0);
+    let bottom = chunks
+        .iter()
+        .map(|chunk| chunk.y + chunk.height as i32)
+        .max()
+        .unwrap_or(0);
+
+    let mut tilemap = Tilemap::new((right - left) as usize, (bottom - top) as usize);
+    for chunk in chunks {
+        if chunk.gids.len() != chunk.width * chunk.height {
+            anyhow::bail!(
+                "expected {} tiles in a {}x{} layer, found {}",
+                chunk.width * chunk.height,
+                chunk.width,
+                chunk.height,
+                chunk.gids.len()
+            );
+        }
+        for (index, gid) in chunk.gids.iter().enumerate() {
+            let gid = gid & GID_MASK;
+            if gid != 0 {
+                let x = (chunk.x - left) as usize + index % chunk.width;
+                let y = (chunk.y - top) as usize + index / chunk.width;
+                tilemap.set(x, y, Some(gid));
+            }
+        }
+    }
+    Ok((tilemap, left, top))
+}
+
+fn decode_csv(text: &str) -> anyhow::Result<Vec<u32>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|gid| !gid.is_empty())
+        .map(|gid| parse_value(gid, "tile"))
+        .collect()
+}
+
+/// Decodes base64 tile data, which is a little endian u32 for each gid and may be compressed
+fn decode_base64(text: &str, compression: Option<&str>) -> anyhow::Result<Vec<u32>> {
+    let bytes = base64::decode(text.trim())?;
+    let bytes = match compression {
+        None | Some("") => bytes,
+        Some("zlib") => {
+            let mut decompressed = Vec::new();
+            flate2::read::ZlibDecoder::new(&bytes[..]).read_to_end(&mut decompressed)?;
+            decompressed
+        }
+        Some("gzip") => {
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut decompressed)?;
+            decompressed
+        }
+        Some(compression) => anyhow::bail!("{} compressed layers aren't supported", compression),
+    };
+    if bytes.len() % 4 != 0 {
+        anyhow::bail!(
+            "tile data is {} bytes, which isn't a whole number of tiles",
+            bytes.len()
+        );
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+        .collect())
+}
+
+/// Reads a .tmx map
+pub(crate) fn parse_tmx(bytes: &[u8]) -> anyhow::Result<ParsedMap> {
+    let document = roxmltree::Document::parse(std::str::from_utf8(bytes)?)?;
+    let root = document.root_element();
+    if !root.has_tag_name("map") {
+        anyhow::bail!("expected a <map>, found <{}>", root.tag_name().name());
+    }
+    let tile_size = (
+        xml_attribute(root, "tilewidth")?,
+        xml_attribute(root, "tileheight")?,
+    );
+
+    let mut tilesets = Vec::new();
+    for tileset in root.children().filter(|node| node.has_tag_name("tileset")) {
+        tilesets.push(TilesetRef {
+            first_gid: xml_attribute(tileset, "firstgid")?,
+            source: tileset.attribute("source").map(str::to_string),
+            tiles: xml_tiles(tileset)?,
+        });
+    }
+
+    let mut layers = Vec::new();
+    xml_layers(root, tile_size, (0, 0), true, &mut layers)?;
+    Ok(ParsedMap {
+        map: TiledMap {
+            width: xml_attribute(root, "width")?,
+            height: xml_attribute(root, "height")?,
+            tileset: Handle::default(),
+            layers,
+            properties: xml_properties(root)?,
+        },
+        tilesets,
+    })
+}
+
+/// Reads the tiles of a tileset in its own file, either a .tsx or a .json/.tsj file
+pub(crate) fn parse_tileset_file(
+    path: &Path,
+    bytes: &[u8],
+) -> anyhow::Result<Vec<(u32, TiledProperties)>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") | Some("tsj") => {
+            let tileset: JsonTileset = serde_json::from_slice(bytes)?;
+            json_tiles(&tileset)
+        }
+        _ => {
+            let document = roxmltree::Document::parse(std::str::from_utf8(bytes)?)?;
+            let root = document.root_element();
+            if !root.has_tag_name("tileset") {
+                anyhow::bail!("expected a <tileset>, found <{}>", root.tag_name().name());
+            }
+            xml_tiles(root)
+        }
+    }
+}
+
+fn xml_attribute<T: FromStr>(node: roxmltree::Node, name: &str) -> anyhow::Result<T> {
+    let value = node
+        .attribute(name)
+        .ok_or_else(|| anyhow::anyhow!("<{}> is missing its {}", node.tag_name().name(), name))?;
+    parse_value(value, name)
+}
+
+fn xml_optional_attribute<T: FromStr>(
+    node: roxmltree::Node,
+    name: &str,
+    default: T,
+) -> anyhow::Result<T> {
+    match node.attribute(name) {
+        Some(value) => parse_value(value, name),
+        None => Ok(default),
+    }
+}
+
+fn xml_properties(node: roxmltree::Node) -> anyhow::Result<TiledProperties> {
+    let mut properties = TiledProperties::default();
+    let list = match node
+        .children()
+        .find(|child| child.has_tag_name("properties"))
+    {
+        Some(list) => list,
+        None => return Ok(properties),
+    };
+    for property in list
+        .children()
+        .filter(|child| child.has_tag_name("property"))
+    {
+        let name: String = xml_attribute(property, "name")?;
+        // Strings with more than one line are written as the element's text
+        let value = property
+            .attribute("value")
+            .or_else(|| property.text())
+            .unwrap_or_default();
+        let kind = property.attribute("type").unwrap_or("string");
+        let parsed = TiledProperty::parse(kind, value)
+            .with_context(|| format!("couldn't read property {}", name))?;
+        if let Some(parsed) = parsed {
+            properties.0.insert(name, parsed);
+        }
+    }
+    Ok(properties)
+}
+
+fn xml_tiles(tileset: roxmltree::Node) -> anyhow::Result<Vec<(u32, TiledProperties)>> {
+    tileset
+        .children()
+        .filter(|node| node.has_tag_name("tile"))
+        .map(|tile| Ok((xml_attribute(tile, "id")?, xml_properties(tile)?)))
+        .collect()
+}
+
+fn xml_gids(
+    node: roxmltree::Node,
+    encoding: Option<&str>,
+    compression: Option<&str>,
+) -> anyhow::Result<Vec<u32>> {
+    match encoding {
+        Some("csv") => decode_csv(node.text().unwrap_or_default()),
+        Some("base64") => decode_base64(node.text().unwrap_or_default(), compression),
+        // Old maps have an element for each tile
+        None => node
+            .children()
+            .filter(|child| child.has_tag_name("tile"))
+            .map(|tile| xml_optional_attribute(tile, "gid", 0))
+            .collect(),
+        Some(encoding) => anyhow::bail!("unknown tile encoding {}", encoding),
+    }
+}
+
+fn xml_chunks(layer: roxmltree::Node) -> anyhow::Result<Vec<Chunk>> {
+    let data = layer
+        .children()
+        .find(|node| node.has_tag_name("data"))
+        .ok_or_else(|| anyhow::anyhow!("<layer> is missing its <data>"))?;
+    let encoding = data.attribute("encoding");
+    let compression = data.attribute("compression");
+
+    let chunks: Vec<_> = data
+        .children()
+        .filter(|node| node.has_tag_name("chunk"))
+        .collect();
+    if chunks.is_empty() {
+        return Ok(vec![Chunk {
+            x: 0,
+            y: 0,
+            width: xml_attribute(layer, "width")?,
+            height: xml_attribute(layer, "height")?,
+            gids: xml_gids(data, encoding, compression)?,
+        }]);
+    }
+    chunks
+        .into_iter()
+        .map(|chunk| {
+            Ok(Chunk {
+                x: xml_attribute(chunk, "x")?,
+                y: xml_attribute(chunk, "y")?,
+                width: xml_attribute(chunk, "width")?,
+                height: xml_attribute(chunk, "height")?,
+                gids: xml_gids(chunk, encoding, compression)?,
+            })
+        })
+        .collect()
+}
+
+fn xml_object(node: roxmltree::Node, tile_size: (u32, u32)) -> anyhow::Result<TiledObject> {
+    let tile = match node.attribute("gid") {
+        Some(gid) => Some(parse_value::<u32>(gid, "gid")? & GID_MASK),
+        None => None,
+    };
+    let rect = (
+        xml_optional_attribute(node, "x", 0.0)?,
+        xml_optional_attribute(node, "y", 0.0)?,
+        xml_optional_attribute(node, "width", 0.0)?,
+        xml_optional_attribute(node, "height", 0.0)?,
+    );
+    let (x, y, width, height) = object_cells(rect, tile.is_some(), tile_size);
+    Ok(TiledObject {
+        id: xml_optional_attribute(node, "id", 0)?,
+        name: node.attribute("name").unwrap_or_default().to_string(),
+        kind: node
+            .attribute("type")
+            .or_else(|| node.attribute("class"))
+            .unwrap_or_default()
+            .to_string(),
+        x,
+        y,
+        width,
+        height,
+        tile,
+        visible: xml_optional_attribute::<u8>(node, "visible", 1)? != 0,
+        properties: xml_properties(node)?,
+    })
+}
+
+fn xml_layers(
+    parent: roxmltree::Node,
+    tile_size: (u32, u32),
+    offset: (i32, i32),
+    visible: bool,
+    layers: &mut Vec<TiledLayer>,
+) -> anyhow::Result<()> {
+    for node in parent.children().filter(|node| node.is_element()) {
+        let name = node.attribute("name").unwrap_or_default().to_string();
+        let mut x = offset.0 + cells(xml_optional_attribute(node, "offsetx", 0.0)?, tile_size.0);
+        let mut y = offset.1 + cells(xml_optional_attribute(node, "offsety", 0.0)?, tile_size.1);
+        let visible = visible && xml_optional_attribute::<u8>(node, "visible", 1)? != 0;
+
+        let content = match node.tag_name().name() {
+            "layer" => {
+                let chunks = xml_chunks(node).with_context(|| format!("in layer {}", name))?;
+                let (tilemap, left, top) = chunks_to_tilemap(&chunks)?;
+                x += left;
+                y += top;
+                TiledLayerContent::Tiles(tilemap)
+            }
+            "objectgroup" => TiledLayerContent::Objects(
+                node.children()
+                    .filter(|child| child.has_tag_name("object"))
+                    .map(|object| xml_object(object, tile_size))
+                    .collect::<anyhow::Result<_>>()
+                    .with_context(|| format!("in layer {}", name))?,
+            ),
+            "group" => {
+                xml_layers(node, tile_size, (x, y), visible, layers)?;
+                continue;
+            }
+            // Image layers can't be drawn in a terminal
+            _ => continue,
+        };
+        layers.push(TiledLayer {
+            name,
+            x,
+            y,
+            visible,
+            properties: xml_properties(node)?,
+            content,
+        });
+    }
+    Ok(())
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct JsonProperty {
+    name: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct JsonTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    #[serde(default)]
+    firstgid: u32,
+    source: Option<String>,
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonData {
+    Csv(Vec<u32>),
+    Base64(String),
+}
+
+#[derive(Deserialize)]
+struct JsonChunk {
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+    data: JsonData,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    id: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    #[serde(default)]
+    x: f32,
+    #[serde(default)]
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    gid: Option<u32>,
+    #[serde(default = "default_visible")]
+    visible: bool,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    offsetx: f32,
+    #[serde(default)]
+    offsety: f32,
+    #[serde(default = "default_visible")]
+    visible: bool,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+    #[serde(default)]
+    width: usize,
+    #[serde(default)]
+    height: usize,
+    data: Option<JsonData>,
+    #[serde(default)]
+    chunks: Vec<JsonChunk>,
+    compression: Option<String>,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonMap {
+    width: usize,
+    height: usize,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    tilesets: Vec<JsonTileset>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+/// Reads a map saved in Tiled's JSON format
+pub(crate) fn parse_json(bytes: &[u8]) -> anyhow::Result<ParsedMap> {
+    let json: JsonMap = serde_json::from_slice(bytes)?;
+    let tile_size = (json.tilewidth, json.tileheight);
+
+    let tilesets = json
+        .tilesets
+        .iter()
+        .map(|tileset| {
+            Ok(TilesetRef {
+                first_gid: tileset.firstgid,
+                source: tileset.source.clone(),
+                tiles: json_tiles(tileset)?,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut layers = Vec::new();
+    json_layers(&json.layers, tile_size, (0, 0), true, &mut layers)?;
+    Ok(ParsedMap {
+        map: TiledMap {
+            width: json.width,
+            height: json.height,
+            tileset: Handle::default(),
+            layers,
+            properties: json_properties(&json.properties)?,
+        },
+        tilesets,
+    })
+}
+
+fn json_properties(list: &[JsonProperty]) -> anyhow::Result<TiledProperties> {
+    let mut properties = TiledProperties::default();
+    for property in list {
+        let invalid =
+            || anyhow::anyhow!("property {} isn't a valid {}", property.name, property.kind);
+        let value = &property.value;
+        let parsed = match property.kind.as_str() {
+            "string" | "" => TiledProperty::String(value.as_str().ok_or_else(invalid)?.to_string()),
+            "bool" => TiledProperty::Bool(value.as_bool().ok_or_else(invalid)?),
+            "int" => TiledProperty::Int(value.as_i64().ok_or_else(invalid)?),
+            "float" => TiledProperty::Float(value.as_f64().ok_or_else(invalid)?),
+            "color" => TiledProperty::Color(parse_color(value.as_str().ok_or_else(invalid)?)),
+            "file" => TiledProperty::File(value.as_str().ok_or_else(invalid)?.to_string()),
+            "object" => TiledProperty::Object(value.as_u64().ok_or_else(invalid)? as u32),
+            // Custom classes need the Tiled project to make sense of
+            _ => continue,
+        };
+        properties.0.insert(property.name.clone(), parsed);
+    }
+    Ok(properties)
+}
+
+fn json_tiles(tileset: &JsonTileset) -> anyhow::Result<Vec<(u32, TiledProperties)>> {
+    tileset
+        .tiles
+        .iter()
+        .map(|tile| Ok((tile.id, json_properties(&tile.properties)?)))
+        .collect()
+}
+
+fn json_gids(data: &JsonData, compression: Option<&str>) -> anyhow::Result<Vec<u32>> {
+    match data {
+        JsonData::Csv(gids) => Ok(gids.clone()),
+        JsonData::Base64(text) => decode_base64(text, compression),
+    }
+}
+
+fn json_chunks(layer: &JsonLayer) -> anyhow::Result<Vec<Chunk>> {
+    let compression = layer.compression.as_deref();
+    if layer.chunks.is_empty() {
+        let data = layer
+            .data
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("tile layer is missing its data"))?;
+        return Ok(vec![Chunk {
+            x: 0,
+            y: 0,
+            width: layer.width,
+            height: layer.height,
+            gids: json_gids(data, compression)?,
+        }]);
+    }
+    layer
+        .chunks
+        .iter()
+        .map(|chunk| {
+            Ok(Chunk {
+                x: chunk.x,
+                y: chunk.y,
+                width: chunk.width,
+                height: chunk.height,
+                gids: json_gids(&chunk.data, compression)?,
+            })
+        })
+        .collect()
+}
+
+fn json_object(object: &JsonObject, tile_size: (u32, u32)) -> anyhow::Result<TiledObject> {
+    let tile = object.gid.map(|gid| gid & GID_MASK);
+    let rect = (object.x, object.y, object.width, object.height);
+    let (x, y, width, height) = object_cells(rect, tile.is_some(), tile_size);
+    let kind = if object.kind.is_empty() {
+        &object.class
+    } else {
+        &object.kind
+    };
+    Ok(TiledObject {
+        id: object.id,
+        name: object.name.clone(),
+        kind: kind.clone(),
+        x,
+        y,
+        width,
+        height,
+        tile,
+        visible: object.visible,
+        properties: json_properties(&object.properties)?,
+    })
+}
+
+fn json_layers(
+    list: &[JsonLayer],
+    tile_size: (u32, u32),
+    offset: (i32, i32),
+    visible: bool,
+    layers: &mut Vec<TiledLayer>,
+) -> anyhow::Result<()> {
+    for layer in list {
+        let mut x = offset.0 + cells(layer.offsetx, tile_size.0);
+        let mut y = offset.1 + cells(layer.offsety, tile_size.1);
+        let visible = visible && layer.visible;
+
+        let content = match layer.kind.as_str() {
+            "tilelayer" => {
+                let chunks =
+                    json_chunks(layer).with_context(|| format!("in layer {}", layer.name))?;
+                let (tilemap, left, top) = chunks_to_tilemap(&chunks)?;
+                x += left;
+                y += top;
+                TiledLayerContent::Tiles(tilemap)
+            }
+            "objectgroup" => TiledLayerContent::Objects(
+                layer
+                    .objects
+                    .iter()
+                    .map(|object| json_object(object, tile_size))
+                    .collect::<anyhow::Result<_>>()
+                    .with_context(|| format!("in layer {}", layer.name))?,
+            ),
+            "group" => {
+                json_layers(&layer.layers, tile_size, (x, y), visible, layers)?;
+                continue;
+            }
+            // Image layers can't be drawn in a terminal
+            _ => continue,
+        };
+        layers.push(TiledLayer {
+            name: layer.name.clone(),
+            x,
+            y,
+            visible,
+            properties: json_properties(&layer.properties)?,
+            content,
+        });
+    }
+    Ok(())
+}
+
+fn visibility(visible: bool) -> Visible {
+    if visible {
+        Visible::default()
+    } else {
+        Visible::invisible()
+    }
+}
+
+/// Spawns a map's layers and objects where the map's origin is now, and returns the layers' entities
+fn spawn_map(commands: &mut Commands, map: &TiledMap, origin: &Position) -> Vec<Entity> {
+    let mut layer_entities = Vec::new();
+    for (index, layer) in map.layers.iter().enumerate() {
+        let x = origin.x + layer.x;
+        let y = origin.y + layer.y;
+        let z = origin.z + index as i32;
+        let info = TiledMapLayer {
+            name: layer.name.clone(),
+            index,
+            properties: layer.properties.clone(),
+        };
+
+        match &layer.content {
+            TiledLayerContent::Tiles(tilemap) => {
+                commands
+                    .spawn(TilemapBundle {
+                        tilemap: tilemap.clone(),
+                        tileset: map.tileset.clone(),
+                        position: Position::new(x, y, z),
+                        visible: visibility(layer.visible),
+                    })
+                    .with(info);
+                layer_entities.extend(commands.current_entity());
+            }
+            TiledLayerContent::Objects(objects) => {
+                let mut object_entities = Vec::new();
+                for object in objects {
+                    let position = Position::new(x + object.x, y + object.y, z);
+                    match object.tile {
+                        // Tile objects are drawn as a tilemap of the one tile
+                        Some(tile) => commands.spawn(TilemapBundle {
+                            tilemap: Tilemap::filled(1, 1, tile),
+                            tileset: map.tileset.clone(),
+                            position,
+                            visible: visibility(layer.visible && object.visible),
+                        }),
+                        None => commands.spawn((position,)),
+                    };
+                    commands.with(object.clone());
+                    object_entities.extend(commands.current_entity());
+                }
+
+                commands.spawn((Position::new(x, y, z), info));
+                if let Some(layer_entity) = commands.current_entity() {
+                    commands.push_children(layer_entity, &object_entities);
+                    layer_entities.push(layer_entity);
+                }
+            }
+        }
+    }
+    layer_entities
+}
+
+/// Spawns the layers of Tiled maps once they've loaded, and spawns them again whenever a map is
+/// reloaded or an entity's map is swapped for another
+pub(crate) fn spawn_tiled_maps(
+    commands: &mut Commands,
+    mut map_reader: Local<EventReader<AssetEvent<TiledMap>>>,
+    map_events: Res<Events<AssetEvent<TiledMap>>>,
+    maps: Res<Assets<TiledMap>>,
+    changed: Query<Entity, Changed<Handle<TiledMap>>>,
+    roots: Query<(Entity, &Handle<TiledMap>, &Position, Option<&Children>)>,
+    layers: Query<Entity, With<TiledMapLayer>>,
+) {
+    let mut changed_maps = HashSet::default();
+    for event in map_reader.iter(&map_events) {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                changed_maps.insert(handle.clone());
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    let mut to_spawn: HashSet<Entity> = changed.iter().collect();
+    for (entity, handle, ..) in roots.iter() {
+        if changed_maps.contains(handle) {
+            to_spawn.insert(entity);
+        }
+    }
+
+    for entity in to_spawn {
+        let (_, handle, origin, children) = match roots.get(entity) {
+            Ok(root) => root,
+            Err(_) => continue,
+        };
+        // Maps load asynchronously, so this is tried again when it shows up
+        let map = match maps.get(handle) {
+            Some(map) => map,
+            None => continue,
+        };
+
+        // Whatever was spawned for the map before is replaced, but anything else the app gave the
+        // entity as a child is left alone
+        for child in children.iter().flat_map(|children| children.iter()) {
+            if layers.get(*child).is_ok() {
+                commands.despawn_recursive(*child);
+            }
+        }
+        let layer_entities = spawn_map(commands, map, origin);
+        commands.push_children(entity, &layer_entities);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn tmx(layers: &str) -> TiledMap {
+        let text = format!(
+            r#"<map width="4" height="4" tilewidth="8" tileheight="8">{}</map>"#,
+            layers
+        );
+        parse_tmx(text.as_bytes()).unwrap().map
+    }
+
+    fn tiles(layer: &TiledLayer) -> Vec<Vec<Option<u32>>> {
+        match &layer.content {
+            TiledLayerContent::Tiles(tilemap) => (0..tilemap.height())
+                .map(|y| (0..tilemap.width()).map(|x| tilemap.get(x, y)).collect())
+                .collect(),
+            TiledLayerContent::Objects(_) => panic!("{} isn't a tile layer", layer.name),
+        }
+    }
+
+    fn objects(layer: &TiledLayer) -> &[TiledObject] {
+        match &layer.content {
+            TiledLayerContent::Objects(objects) => objects,
+            TiledLayerContent::Tiles(_) => panic!("{} isn't an object layer", layer.name),
+        }
+    }
+
+    #[test]
+    fn csv_layers() {
+        let map = tmx(r#"<layer name="ground" width="3" height="2">
+                <data encoding="csv">
+                    1,0,2,
+                    0,3,0
+                </data>
+            </layer>"#);
+        assert_eq!(
+            tiles(&map.layers[0]),
+            vec![vec![Some(1), None, Some(2)], vec![None, Some(3), None]]
+        );
+        assert!(decode_csv("1,x").is_err());
+    }
+
+    #[test]
+    fn base64_zlib_layers() {
+        let mut bytes = Vec::new();
+        for gid in [1u32, 0, 0, 258].iter() {
+            bytes.extend_from_slice(&gid.to_le_bytes());
+        }
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        let data = base64::encode(encoder.finish().unwrap());
+
+        let json = format!(
+            r#"{{
+                "width": 2, "height": 2, "tilewidth": 8, "tileheight": 8,
+                "layers": [{{
+                    "type": "tilelayer", "name": "ground", "width": 2, "height": 2,
+                    "encoding": "base64", "compression": "zlib", "data": "{}"
+                }}]
+            }}"#,
+            data
+        );
+        let map = parse_json(json.as_bytes()).unwrap().map;
+        assert_eq!(
+            tiles(&map.layers[0]),
+            vec![vec![Some(1), None], vec![None, Some(258)]]
+        );
+
+        assert!(decode_base64(&data, Some("zstd")).is_err());
+    }
+
+    #[test]
+    fn infinite_maps_are_put_together_from_their_chunks() {
+        let map = tmx(r#"<layer name="ground" width="4" height="4">
+                <data encoding="csv">
+                    <chunk x="-2" y="-1" width="2" height="1">1,2</chunk>
+                    <chunk x="0" y="0" width="2" height="1">0,3</chunk>
+                </data>
+            </layer>"#);
+        let layer = &map.layers[0];
+        assert_eq!((layer.x, layer.y), (-2, -1));
+        assert_eq!(
+            tiles(layer),
+            vec![
+                vec![Some(1), Some(2), None, None],
+                vec![None, None, None, Some(3)],
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_offset_and_hide_their_layers() {
+        let map = tmx(r#"<group name="group" offsetx="16" offsety="8" visible="0">
+                <layer name="inner" width="1" height="1" offsety="16">
+                    <data encoding="csv">1</data>
+                </layer>
+            </group>
+            <objectgroup name="outer" offsetx="-8"/>"#);
+        assert_eq!(map.layers.len(), 2);
+        let inner = &map.layers[0];
+        assert_eq!((inner.name.as_str(), inner.x, inner.y), ("inner", 2, 3));
+        assert!(!inner.visible);
+        let outer = &map.layers[1];
+        assert_eq!((outer.x, outer.y), (-1, 0));
+        assert!(outer.visible);
+    }
+
+    #[test]
+    fn tile_objects_are_anchored_at_their_bottom_left() {
+        let map = tmx(r#"<objectgroup name="things">
+                <object id="1" gid="1" x="16" y="16"/>
+                <object id="2" x="16" y="16" width="24" height="8"/>
+            </objectgroup>"#);
+        let objects = objects(&map.layers[0]);
+        let tile = &objects[0];
+        assert_eq!((tile.x, tile.y, tile.width, tile.height), (2, 1, 1, 1));
+        let rect = &objects[1];
+        assert_eq!((rect.x, rect.y, rect.width, rect.height), (2, 2, 3, 1));
+    }
+
+    #[test]
+    fn flip_flags_are_stripped_from_gids() {
+        let flipped = 0x8000_0000u32 | 0x4000_0000 | 5;
+        let map = tmx(&format!(
+            r#"<layer name="ground" width="2" height="1">
+                <data encoding="csv">{},{}</data>
+            </layer>
+            <objectgroup name="things">
+                <object id="1" gid="{}" x="0" y="8"/>
+            </objectgroup>"#,
+            flipped,
+            0x2000_0000u32 | 7,
+            flipped
+        ));
+        assert_eq!(tiles(&map.layers[0]), vec![vec![Some(5), Some(7)]]);
+        assert_eq!(objects(&map.layers[1])[0].tile, Some(5));
+    }
+}
@@ -0,0 +1,407 @@
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::HashSet;
+use serde::{Deserialize, Serialize};
+
+use crate::components::{
+    DrawArea, EntitiesToRedraw, EntityDepth, Position, ScreenRect, Style, TilemapDetails, Visible,
+};
+use crate::layers::{Layers, RenderLayers};
+use crate::viewport::ActiveViewports;
+
+/// How many cells across and down each chunk of a tilemap is. Chunks that aren't on the screen are
+/// skipped when the tilemap is drawn.
+pub const CHUNK_SIZE: usize = 16;
+
+/// How a tile looks. The glyph should be one cell wide.
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct Tile {
+    pub glyph: char,
+    #[serde(default)]
+    pub style: Style,
+}
+
+impl Tile {
+    pub fn new(glyph: char, style: Style) -> Tile {
+        Tile { glyph, style }
+    }
+}
+
+/// The tiles a tilemap is drawn with, where a tile's id is its index. Tilesets can be loaded from
+/// .tileset files, which are written in RON like `(tiles: [(glyph: '#'), (glyph: '.', style: ...)])`.
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq, TypeUuid)]
+#[uuid = "3b0bd7c4-64d8-4a5e-9f0e-1d7f6c4b2a91"]
+pub struct Tileset {
+    pub tiles: Vec<Tile>,
+}
+
+impl Tileset {
+    pub fn new(tiles: Vec<Tile>) -> Tileset {
+        Tileset { tiles }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Tile> {
+        self.tiles.get(id as usize)
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Chunk {
+    tiles: Vec<Option<u32>>,
+}
+
+/// A grid of tiles drawn as one entity, for maps too big to make out of an entity for each cell. The
+/// tiles are ids into the Tileset the entity's `Handle<Tileset>` points to, and empty cells are
+/// transparent.
+///
+/// Setting a tile only redraws that cell, and whatever's drawn on top of it. Moving the tilemap,
+/// hiding it, or changing its tileset redraws the whole screen.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Tilemap {
+    width: usize,
+    height: usize,
+    // Chunks of cells, a row of chunks at a time
+    chunks: Vec<Chunk>,
+    // The cells that changed since the tilemap was last drawn
+    dirty: HashSet<(usize, usize)>,
+    all_dirty: bool,
+}
+
+impl Tilemap {
+    /// A tilemap with every cell empty
+    pub fn new(width: usize, height: usize) -> Tilemap {
+        let chunks_across = (width + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let chunks_down = (height + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let chunk = Chunk {
+            tiles: vec![None; CHUNK_SIZE * CHUNK_SIZE],
+        };
+        Tilemap {
+            width,
+            height,
+            chunks: vec![chunk; chunks_across * chunks_down],
+            dirty: HashSet::default(),
+            all_dirty: true,
+        }
+    }
+
+    /// A tilemap with every cell set to the same tile
+    pub fn filled(width: usize, height: usize, tile: u32) -> Tilemap {
+        let mut tilemap = Tilemap::new(width, height);
+        tilemap.fill(Some(tile));
+        tilemap
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Where a cell's tile is kept, as the chunk and the index within it
+    fn index(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let chunks_across = (self.width + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let chunk = (y / CHUNK_SIZE) * chunks_across + x / CHUNK_SIZE;
+        Some((chunk, (y % CHUNK_SIZE) * CHUNK_SIZE + x % CHUNK_SIZE))
+    }
+
+    /// The tile in a cell, or None if it's empty or outside the map
+    pub fn get(&self, x: usize, y: usize) -> Option<u32> {
+        let (chunk, index) = self.index(x, y)?;
+        self.chunks[chunk].tiles[index]
+    }
+
+    /// Sets the tile in a cell, or empties it. Cells outside the map are ignored.
+    pub fn set(&mut self, x: usize, y: usize, tile: Option<u32>) {
+        if let Some((chunk, index)) = self.index(x, y) {
+            let cell = &mut self.chunks[chunk].tiles[index];
+            if *cell != tile {
+                *cell = tile;
+                if !self.all_dirty {
+                    self.dirty.insert((x, y));
+                }
+            }
+        }
+    }
+
+    /// Sets every cell to the same tile, or empties them all
+    pub fn fill(&mut self, tile: Option<u32>) {
+        for chunk in self.chunks.iter_mut() {
+            for cell in chunk.tiles.iter_mut() {
+                *cell = tile;
+            }
+        }
+        self.all_dirty = true;
+        self.dirty.clear();
+    }
+
+    /// Swaps every tile for another one, or empties it
+    pub(crate) fn map_tiles(&mut self, mut f: impl FnMut(u32) -> Option<u32>) {
+        for chunk in self.chunks.iter_mut() {
+            for cell in chunk.tiles.iter_mut() {
+                *cell = cell.and_then(&mut f);
+            }
+        }
+        self.all_dirty = true;
+        self.dirty.clear();
+    }
+
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.all_dirty || !self.dirty.is_empty()
+    }
+
+    /// The parts of the map that changed since it was last drawn, as rectangles of cells, and marks
+    /// them as drawn. Cells next to each other in a row are merged into one rectangle.
+    pub(crate) fn take_dirty(&mut self) -> Vec<ScreenRect> {
+        if self.all_dirty {
+            self.all_dirty = false;
+            self.dirty.clear();
+            return vec![ScreenRect {
+                x: 0,
+                y: 0,
+                width: self.width as i32,
+                height: self.height as i32,
+            }];
+        }
+
+        let mut cells: Vec<(usize, usize)> = self.dirty.drain().collect();
+        cells.sort_by_key(|(x, y)| (*y, *x));
+        let mut rects: Vec<ScreenRect> = Vec::new();
+        for (x, y) in cells {
+            let (x, y) = (x as i32, y as i32);
+            match rects.last_mut() {
+                Some(rect) if rect.y == y && rect.right() == x => rect.width += 1,
+                _ => rects.push(ScreenRect {
+                    x,
+                    y,
+                    width: 1,
+                    height: 1,
+                }),
+            }
+        }
+        rects
+    }
+
+    /// The chunks which overlap a rectangle of cells, as the cells each of them covers
+    pub(crate) fn chunks_in(&self, cells: &ScreenRect) -> Vec<ScreenRect> {
+        let map = ScreenRect {
+            x: 0,
+            y: 0,
+            width: self.width as i32,
+            height: self.height as i32,
+        };
+        let cells = cells.intersect(&map);
+        if cells.width == 0 || cells.height == 0 {
+            return Vec::new();
+        }
+
+        let size = CHUNK_SIZE as i32;
+        let mut chunks = Vec::new();
+        for chunk_y in cells.y / size..=(cells.bottom() - 1) / size {
+            for chunk_x in cells.x / size..=(cells.right() - 1) / size {
+                let chunk = ScreenRect {
+                    x: chunk_x * size,
+                    y: chunk_y * size,
+                    width: size,
+                    height: size,
+                };
+                chunks.push(chunk.intersect(&cells));
+            }
+        }
+        chunks
+    }
+}
+
+#[derive(Bundle, Default)]
+pub struct TilemapBundle {
+    pub tilemap: Tilemap,
+    pub tileset: Handle<Tileset>,
+    pub position: Position,
+    pub visible: Visible,
+}
+
+/// Finds where tilemaps are on the screen and which of their tiles changed, so the redraw logic can
+/// treat a tilemap's tiles separately rather than redrawing the whole thing
+pub(crate) fn tilemap_changes(
+    mut entities: ResMut<EntitiesToRedraw>,
+    viewports: Res<ActiveViewports>,
+    layers: Res<Layers>,
+    mut tileset_reader: Local<EventReader<AssetEvent<Tileset>>>,
+    tileset_events: Res<Events<AssetEvent<Tileset>>>,
+    mut tilemaps: Query<(
+        Entity,
+        &mut Tilemap,
+        &Position,
+        &Visible,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
+    changed: Query<
+        Entity,
+        (
+            With<Tilemap>,
+            Or<(
+                Added<Tilemap>,
+                Mutated<Position>,
+                Mutated<Visible>,
+                Mutated<Handle<Tileset>>,
+                Changed<DrawArea>,
+                Changed<RenderLayers>,
+            )>,
+        ),
+    >,
+) {
+    // Tilemaps are big and rarely move, so when one does, or its tileset changes, it's simplest to
+    // redraw everything
+    entities.tilemaps_changed = changed.iter().next().is_some()
+        || tileset_reader.iter(&tileset_events).count() > 0
+        || tilemaps.removed::<Tilemap>().first().is_some();
+
+    entities.tilemaps.clear();
+    for (entity, mut tilemap, position, visible, area, render_layers) in tilemaps.iter_mut() {
+        let cells = ScreenRect {
+            x: 0,
+            y: 0,
+            width: tilemap.width() as i32,
+            height: tilemap.height() as i32,
+        };
+        let rects = viewports.screen_rects(position, &cells, area, render_layers);
+        let dirty = if tilemap.is_dirty() {
+            tilemap
+                .take_dirty()
+                .iter()
+                .flat_map(|cells| viewports.screen_rects(position, cells, area, render_layers))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if !visible.is_visible || rects.is_empty() {
+            continue;
+        }
+        entities.tilemaps.push(TilemapDetails {
+            depth: EntityDepth {
+                entity,
+                layer: layers.rank(render_layers),
+                z: position.z,
+            },
+            rects,
+            dirty,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> ScreenRect {
+        ScreenRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn cells_are_kept_across_chunks() {
+        let mut tilemap = Tilemap::new(40, 20);
+        tilemap.set(15, 15, Some(1));
+        tilemap.set(16, 15, Some(2));
+        tilemap.set(39, 19, Some(3));
+        tilemap.set(40, 0, Some(4));
+        assert_eq!(tilemap.get(15, 15), Some(1));
+        assert_eq!(tilemap.get(16, 15), Some(2));
+        assert_eq!(tilemap.get(15, 16), None);
+        assert_eq!(tilemap.get(39, 19), Some(3));
+        assert_eq!(tilemap.get(40, 0), None);
+        assert_eq!(tilemap.get(0, 0), None);
+    }
+
+    #[test]
+    fn only_changed_cells_are_dirty() {
+        let mut tilemap = Tilemap::new(40, 20);
+        assert_eq!(tilemap.take_dirty(), vec![rect(0, 0, 40, 20)]);
+        assert!(!tilemap.is_dirty());
+
+        tilemap.set(3, 2, None);
+        tilemap.set(50, 2, Some(1));
+        assert!(!tilemap.is_dirty());
+
+        tilemap.set(4, 1, Some(1));
+        tilemap.set(2, 1, Some(1));
+        tilemap.set(3, 1, Some(2));
+        tilemap.set(16, 1, Some(1));
+        tilemap.set(15, 1, Some(1));
+        tilemap.set(7, 1, Some(1));
+        tilemap.set(2, 5, Some(1));
+        assert_eq!(
+            tilemap.take_dirty(),
+            vec![
+                rect(2, 1, 3, 1),
+                rect(7, 1, 1, 1),
+                rect(15, 1, 2, 1),
+                rect(2, 5, 1, 1),
+            ]
+        );
+        assert!(tilemap.take_dirty().is_empty());
+
+        tilemap.set(2, 1, Some(1));
+        assert!(!tilemap.is_dirty());
+    }
+
+    #[test]
+    fn fill_dirties_the_whole_map() {
+        let mut tilemap = Tilemap::new(40, 20);
+        tilemap.take_dirty();
+        tilemap.set(1, 1, Some(1));
+        tilemap.fill(Some(2));
+        tilemap.set(2, 2, Some(3));
+        assert_eq!(tilemap.take_dirty(), vec![rect(0, 0, 40, 20)]);
+        assert_eq!(tilemap.get(1, 1), Some(2));
+        assert_eq!(tilemap.get(39, 19), Some(2));
+        assert_eq!(tilemap.get(2, 2), Some(3));
+    }
+
+    #[test]
+    fn chunks_are_clipped_to_the_map() {
+        let tilemap = Tilemap::new(40, 20);
+        assert_eq!(
+            tilemap.chunks_in(&rect(-5, -5, 30, 10)),
+            vec![rect(0, 0, 16, 5), rect(16, 0, 9, 5)]
+        );
+        assert_eq!(
+            tilemap.chunks_in(&rect(35, 18, 10, 10)),
+            vec![rect(35, 18, 5, 2)]
+        );
+        assert!(tilemap.chunks_in(&rect(40, 0, 5, 5)).is_empty());
+        assert!(tilemap.chunks_in(&rect(-5, -5, 5, 30)).is_empty());
+    }
+
+    #[test]
+    fn chunks_split_at_their_edges() {
+        let tilemap = Tilemap::new(40, 20);
+        assert_eq!(
+            tilemap.chunks_in(&rect(10, 10, 10, 10)),
+            vec![
+                rect(10, 10, 6, 6),
+                rect(16, 10, 4, 6),
+                rect(10, 16, 6, 4),
+                rect(16, 16, 4, 4),
+            ]
+        );
+        assert_eq!(
+            tilemap.chunks_in(&rect(0, 0, 40, 20)).len(),
+            6,
+            "the last column and row of chunks are partly off the map"
+        );
+        assert_eq!(
+            tilemap.chunks_in(&rect(16, 0, 16, 16)),
+            vec![rect(16, 0, 16, 16)]
+        );
+    }
+}
@@ -0,0 +1,523 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::time::Duration;
+
+use crate::components::{self, Color, Colors, Position, StyleMap, Visible};
+
+/// How a tween speeds up and slows down between where it starts and where it ends
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Ease {
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    /// Pulls back a little before it sets off
+    BackIn,
+    /// Overshoots the end a little before it settles
+    BackOut,
+    /// Bounces against the end like a dropped ball
+    BounceOut,
+}
+
+impl Default for Ease {
+    fn default() -> Self {
+        Ease::Linear
+    }
+}
+
+impl Ease {
+    /// How far along the tween is after `t` of its time has passed, both from 0 to 1. Some curves go
+    /// a little past either end on the way.
+    pub fn apply(self, t: f32) -> f32 {
+        use std::f32::consts::PI;
+        const BACK: f32 = 1.70158;
+
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Ease::Linear => t,
+            Ease::QuadIn => t * t,
+            Ease::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Ease::QuadInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Ease::CubicIn => t * t * t,
+            Ease::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Ease::CubicInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+            Ease::SineIn => 1.0 - (t * PI / 2.0).cos(),
+            Ease::SineOut => (t * PI / 2.0).sin(),
+            Ease::SineInOut => -((t * PI).cos() - 1.0) / 2.0,
+            Ease::BackIn => (BACK + 1.0) * t * t * t - BACK * t * t,
+            Ease::BackOut => 1.0 + (BACK + 1.0) * (t - 1.0).powi(3) + BACK * (t - 1.0).powi(2),
+            Ease::BounceOut => {
+                const N: f32 = 7.5625;
+                const D: f32 = 2.75;
+                if t < 1.0 / D {
+                    N * t * t
+                } else if t < 2.0 / D {
+                    let t = t - 1.5 / D;
+                    N * t * t + 0.75
+                } else if t < 2.5 / D {
+                    let t = t - 2.25 / D;
+                    N * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / D;
+                    N * t * t + 0.984375
+                }
+            }
+        }
+    }
+}
+
+/// What a step of a tween changes
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Tweenable {
+    /// Moves the entity's `Position` between two cells, leaving its z alone
+    Position { from: (i32, i32), to: (i32, i32) },
+    /// Changes the colors of the entity's `StyleMap`. RGB, named and ANSI colors are mixed as RGB,
+    /// and other colors switch over half way. The entity is given a copy of its stylemap the first
+    /// time this plays, so other entities using the same stylemap don't change with it.
+    Colors { from: Colors, to: Colors },
+    /// Shows or hides the entity half way through the step
+    Visible { from: bool, to: bool },
+    /// Waits, to put a pause between other steps
+    Wait,
+}
+
+/// One part of a tween, which plays after the one before it
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TweenStep {
+    pub value: Tweenable,
+    pub duration: Duration,
+    pub ease: Ease,
+}
+
+/// How many times a tween plays
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Repeat {
+    Times(u32),
+    Forever,
+}
+
+impl Default for Repeat {
+    fn default() -> Self {
+        Repeat::Times(1)
+    }
+}
+
+/// Animates an entity's position, colors and visibility over time, as a sequence of steps. A
+/// TweenFinished event is sent when it's played as many times as it repeats, and it's left on the
+/// entity so it can be restarted. How far it's got is kept outside of it, so a playing tween only
+/// changes when it finishes.
+///
+/// ```ignore
+/// let slide_in = Tween::new(
+///     Tweenable::Position { from: (-20, 2), to: (2, 2) },
+///     Duration::from_millis(400),
+///     Ease::BackOut,
+/// )
+/// .then(Tweenable::Wait, Duration::from_secs(2), Ease::Linear)
+/// .then(
+///     Tweenable::Visible { from: true, to: false },
+///     Duration::from_millis(0),
+///     Ease::Linear,
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tween {
+    pub steps: Vec<TweenStep>,
+    pub repeat: Repeat,
+    /// Plays every other time backwards, so the tween goes back and forth
+    pub yoyo: bool,
+    pub paused: bool,
+    /// Sent back in TweenFinished, to tell an entity's tweens apart
+    pub id: u32,
+    finished: bool,
+    // Counts the times the tween's been restarted, so its progress is started again too
+    restarts: u32,
+}
+
+/// How far through its tween an entity is
+#[derive(Default)]
+pub(crate) struct Playback {
+    // How far into the current play the tween is, in seconds
+    elapsed: f32,
+    plays: u32,
+    // The restart of the tween this is a play of
+    restarts: u32,
+    // The copy of the entity's stylemap that color steps change
+    stylemap: Option<Handle<StyleMap>>,
+}
+
+impl Tween {
+    pub fn new(value: Tweenable, duration: Duration, ease: Ease) -> Tween {
+        Tween {
+            steps: vec![TweenStep {
+                value,
+                duration,
+                ease,
+            }],
+            repeat: Repeat::default(),
+            yoyo: false,
+            paused: false,
+            id: 0,
+            finished: false,
+            restarts: 0,
+        }
+    }
+
+    /// Adds a step which plays after the others
+    pub fn then(mut self, value: Tweenable, duration: Duration, ease: Ease) -> Tween {
+        self.steps.push(TweenStep {
+            value,
+            duration,
+            ease,
+        });
+        self
+    }
+
+    pub fn repeat(mut self, repeat: Repeat) -> Tween {
+        self.repeat = repeat;
+        self
+    }
+
+    pub fn yoyo(mut self) -> Tween {
+        self.yoyo = true;
+        self
+    }
+
+    pub fn with_id(mut self, id: u32) -> Tween {
+        self.id = id;
+        self
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Plays the tween again from the start
+    pub fn restart(&mut self) {
+        self.finished = false;
+        self.restarts = self.restarts.wrapping_add(1);
+    }
+
+    fn length(&self) -> f32 {
+        self.steps
+            .iter()
+            .map(|step| step.duration.as_secs_f32())
+            .sum()
+    }
+
+    /// Moves the tween on, and adds the steps it passed through and how far through each of them it
+    /// got to `passed`, in the order it went through them. Steps it went all the way through are
+    /// included, so they end up where they finish even when a frame is long. Returns whether the
+    /// tween finished.
+    fn advance(
+        &self,
+        playback: &mut Playback,
+        seconds: f32,
+        passed: &mut Vec<(usize, f32)>,
+    ) -> bool {
+        let length = self.length();
+        let mut remaining = seconds;
+        loop {
+            let from = playback.elapsed;
+            let to = (from + remaining).min(length);
+            remaining -= to - from;
+            playback.elapsed = to;
+
+            // Backwards plays go through the same steps from the other end
+            let backwards = self.yoyo && playback.plays % 2 == 1;
+            let (from, to) = if backwards {
+                (length - from, length - to)
+            } else {
+                (from, to)
+            };
+            let (low, high) = (from.min(to), from.max(to));
+            let mut start = 0.0;
+            let first = passed.len();
+            for (index, step) in self.steps.iter().enumerate() {
+                let duration = step.duration.as_secs_f32();
+                if start <= high && start + duration >= low {
+                    let t = if duration > 0.0 {
+                        ((to - start) / duration).clamp(0.0, 1.0)
+                    } else if to >= start && !backwards {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                    passed.push((index, t));
+                }
+                start += duration;
+            }
+            if backwards {
+                passed[first..].reverse();
+            }
+
+            if playback.elapsed < length {
+                return false;
+            }
+            playback.plays += 1;
+            playback.elapsed = 0.0;
+            if let Repeat::Times(times) = self.repeat {
+                if playback.plays >= times {
+                    return true;
+                }
+            }
+            // A tween with no length plays once a frame, rather than forever
+            if remaining <= 0.0 || length <= 0.0 {
+                return false;
+            }
+        }
+    }
+}
+
+/// Sent when a tween has played as many times as it repeats
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TweenFinished {
+    pub entity: Entity,
+    pub id: u32,
+}
+
+fn lerp(from: i32, to: i32, amount: f32) -> i32 {
+    from + ((to - from) as f32 * amount).round() as i32
+}
+
+pub(crate) fn lerp_color(from: Option<Color>, to: Option<Color>, amount: f32) -> Option<Color> {
+    let rgb = (
+        from.and_then(components::to_rgb),
+        to.and_then(components::to_rgb),
+    );
+    match rgb {
+        (Some(from), Some(to)) => {
+            let channel =
+                |from: u8, to: u8| lerp(from as i32, to as i32, amount).clamp(0, 255) as u8;
+            Some(Color::Rgb {
+                r: channel(from.0, to.0),
+                g: channel(from.1, to.1),
+                b: channel(from.2, to.2),
+            })
+        }
+        _ if amount < 0.5 => from,
+        _ => to,
+    }
+}
+
+// Only changes things that are different, so the renderer doesn't redraw entities that stay put
+fn apply_step(
+    step: &TweenStep,
+    t: f32,
+    position: &mut Option<Mut<Position>>,
+    visible: &mut Option<Mut<Visible>>,
+    stylemap: &mut Option<Mut<Handle<StyleMap>>>,
+    copy: &mut Option<Handle<StyleMap>>,
+    stylemaps: &mut Assets<StyleMap>,
+) {
+    let amount = step.ease.apply(t);
+    match step.value {
+        Tweenable::Position { from, to } => {
+            if let Some(position) = position {
+                let (x, y) = (lerp(from.0, to.0, amount), lerp(from.1, to.1, amount));
+                if position.x != x || position.y != y {
+                    position.x = x;
+                    position.y = y;
+                }
+            }
+        }
+        Tweenable::Colors { from, to } => {
+            let colors = Colors {
+                foreground: lerp_color(from.foreground, to.foreground, amount),
+                background: lerp_color(from.background, to.background, amount),
+            };
+            let handle = match stylemap {
+                Some(handle) => handle,
+                None => return,
+            };
+            let stylemap = match stylemaps.get(&**handle) {
+                Some(stylemap) if stylemap.style.colors != colors => stylemap,
+                _ => return,
+            };
+            // The first time, or if the app's given the entity another stylemap since, the entity
+            // gets a copy of its own to change
+            if copy.as_ref() != Some(&**handle) {
+                let new_copy = stylemaps.add(stylemap.clone());
+                **handle = new_copy.clone();
+                *copy = Some(new_copy);
+            }
+            if let Some(stylemap) = stylemaps.get_mut(&**handle) {
+                stylemap.style.colors = colors;
+            }
+        }
+        Tweenable::Visible { from, to } => {
+            if let Some(visible) = visible {
+                let is_visible = if amount < 0.5 { from } else { to };
+                if visible.is_visible != is_visible {
+                    visible.is_visible = is_visible;
+                }
+            }
+        }
+        Tweenable::Wait => {}
+    }
+}
+
+pub(crate) fn update_tweens(
+    time: Res<Time>,
+    mut playbacks: Local<HashMap<Entity, Playback>>,
+    mut passed: Local<Vec<(usize, f32)>>,
+    mut finished_events: ResMut<Events<TweenFinished>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut tweens: Query<(
+        Entity,
+        &mut Tween,
+        Option<&mut Position>,
+        Option<&mut Visible>,
+        Option<&mut Handle<StyleMap>>,
+    )>,
+    added: Query<Entity, Added<Tween>>,
+) {
+    // Tweens that are taken off an entity, or put on one, start again from the beginning
+    for entity in tweens.removed::<Tween>() {
+        playbacks.remove(entity);
+    }
+    for entity in added.iter() {
+        playbacks.remove(&entity);
+    }
+
+    for (entity, mut tween, mut position, mut visible, mut stylemap) in tweens.iter_mut() {
+        if tween.paused || tween.finished {
+            continue;
+        }
+
+        let playback = playbacks.entry(entity).or_default();
+        if playback.restarts != tween.restarts {
+            *playback = Playback {
+                restarts: tween.restarts,
+                stylemap: playback.stylemap.take(),
+                ..Default::default()
+            };
+        }
+
+        passed.clear();
+        let finished = tween.advance(playback, time.delta_seconds(), &mut passed);
+        for (index, t) in passed.iter() {
+            apply_step(
+                &tween.steps[*index],
+                *t,
+                &mut position,
+                &mut visible,
+                &mut stylemap,
+                &mut playback.stylemap,
+                &mut stylemaps,
+            );
+        }
+
+        if finished {
+            tween.finished = true;
+            finished_events.send(TweenFinished {
+                entity,
+                id: tween.id,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(tween: &Tween, playback: &mut Playback, seconds: f32) -> (Vec<(usize, f32)>, bool) {
+        let mut passed = Vec::new();
+        let finished = tween.advance(playback, seconds, &mut passed);
+        (passed, finished)
+    }
+
+    fn two_steps() -> Tween {
+        Tween::new(Tweenable::Wait, Duration::from_secs(1), Ease::Linear).then(
+            Tweenable::Wait,
+            Duration::from_secs(1),
+            Ease::Linear,
+        )
+    }
+
+    #[test]
+    fn eases_start_and_end_in_place() {
+        let eases = [
+            Ease::Linear,
+            Ease::QuadIn,
+            Ease::QuadOut,
+            Ease::QuadInOut,
+            Ease::CubicIn,
+            Ease::CubicOut,
+            Ease::CubicInOut,
+            Ease::SineIn,
+            Ease::SineOut,
+            Ease::SineInOut,
+            Ease::BackIn,
+            Ease::BackOut,
+            Ease::BounceOut,
+        ];
+        for ease in eases.iter() {
+            assert!(ease.apply(0.0).abs() < 1e-4, "{:?}", ease);
+            assert!((ease.apply(1.0) - 1.0).abs() < 1e-4, "{:?}", ease);
+        }
+        assert!(Ease::BackIn.apply(0.2) < 0.0);
+        assert_eq!(Ease::Linear.apply(2.0), 1.0);
+    }
+
+    #[test]
+    fn long_frames_finish_the_steps_they_pass() {
+        let tween = two_steps();
+        let mut playback = Playback::default();
+        assert_eq!(steps(&tween, &mut playback, 0.5), (vec![(0, 0.5)], false));
+        assert_eq!(
+            steps(&tween, &mut playback, 1.0),
+            (vec![(0, 1.0), (1, 0.5)], false)
+        );
+        // The last step finishes the tween, which only plays once
+        assert_eq!(steps(&tween, &mut playback, 5.0), (vec![(1, 1.0)], true));
+    }
+
+    #[test]
+    fn yoyos_play_backwards_every_other_time() {
+        let tween = two_steps().repeat(Repeat::Forever).yoyo();
+        let mut playback = Playback::default();
+        let (passed, finished) = steps(&tween, &mut playback, 2.5);
+        assert!(!finished);
+        assert_eq!(passed, vec![(0, 1.0), (1, 1.0), (1, 0.5)]);
+        assert_eq!(
+            steps(&tween, &mut playback, 1.0),
+            (vec![(1, 0.0), (0, 0.5)], false)
+        );
+    }
+
+    #[test]
+    fn colors_are_mixed_as_rgb() {
+        let black = Some(Color::Rgb { r: 0, g: 0, b: 0 });
+        assert_eq!(
+            lerp_color(black, Some(Color::White), 0.5),
+            Some(Color::Rgb {
+                r: 128,
+                g: 128,
+                b: 128
+            })
+        );
+        // Colors without an RGB value switch over half way
+        assert_eq!(lerp_color(black, Some(Color::Reset), 0.4), black);
+        assert_eq!(lerp_color(None, Some(Color::Red), 0.5), Some(Color::Red));
+    }
+}
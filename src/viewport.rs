@@ -0,0 +1,286 @@
+use bevy::prelude::*;
+
+use crate::camera::Camera;
+use crate::components::{DrawArea, Position, ScreenRect};
+use crate::layers::{Layers, RenderLayers};
+use crate::CrosstermWindow;
+
+/// A rectangle of the window which shows the world through its own camera, like one half of a
+/// split screen. It only shows entities on the layers in its mask.
+///
+/// Entities are clipped to the viewport, and screen space entities are drawn relative to its top left
+/// corner rather than the window's. Viewports shouldn't overlap, since nothing decides which one is
+/// drawn on top.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: u16,
+    pub height: u16,
+    pub camera: Camera,
+    pub layers: RenderLayers,
+}
+
+impl Viewport {
+    /// A viewport with a camera at the origin, showing every layer
+    pub fn new(x: i32, y: i32, width: u16, height: u16) -> Viewport {
+        Viewport {
+            x,
+            y,
+            width,
+            height,
+            camera: Camera::default(),
+            layers: RenderLayers::all(),
+        }
+    }
+
+    pub fn with_camera(mut self, camera: Camera) -> Viewport {
+        self.camera = camera;
+        self
+    }
+
+    pub fn with_layers(mut self, layers: RenderLayers) -> Viewport {
+        self.layers = layers;
+        self
+    }
+
+    pub(crate) fn rect(&self) -> ScreenRect {
+        ScreenRect {
+            x: self.x,
+            y: self.y,
+            width: self.width as i32,
+            height: self.height as i32,
+        }
+    }
+
+    /// Whether the viewport shows an entity on the given layers
+    pub(crate) fn shows(&self, layers: Option<&RenderLayers>) -> bool {
+        self.layers.intersects(&layers.copied().unwrap_or_default())
+    }
+
+    /// Where an entity with the given draw area is drawn on the screen in this viewport
+    pub(crate) fn draw_area(&self, area: Option<&DrawArea>) -> DrawArea {
+        let mut area = area
+            .copied()
+            .unwrap_or_default()
+            .with_camera(self.camera.offset());
+        area.offset_x += self.x;
+        area.offset_y += self.y;
+        area.clip = Some(match area.clip {
+            Some(clip) => ScreenRect {
+                x: clip.x + self.x,
+                y: clip.y + self.y,
+                ..clip
+            }
+            .intersect(&self.rect()),
+            None => self.rect(),
+        });
+        area
+    }
+}
+
+/// The viewports the window is split into. When there aren't any, which is the default, the whole
+/// window is one viewport which uses the Camera resource and shows every layer.
+///
+/// Changing the viewports redraws the whole screen.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Viewports(pub Vec<Viewport>);
+
+/// The viewports that are drawn this frame, including the one made for the whole window when there
+/// aren't any others. Hidden layers are taken out of their masks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ActiveViewports(pub Vec<Viewport>);
+
+impl ActiveViewports {
+    /// Where an entity is drawn, in each of the viewports that show it
+    pub fn draw_areas<'a>(
+        &'a self,
+        area: Option<&'a DrawArea>,
+        layers: Option<&'a RenderLayers>,
+    ) -> impl Iterator<Item = DrawArea> + 'a {
+        self.0
+            .iter()
+            .filter(move |viewport| viewport.shows(layers))
+            .map(move |viewport| viewport.draw_area(area))
+    }
+
+    /// The parts of the screen a rectangle of cells is drawn in, where the rectangle is relative to
+    /// an entity's position. There's one for each viewport that shows the entity and has some of the
+    /// rectangle on the screen.
+    pub fn screen_rects(
+        &self,
+        position: &Position,
+        cells: &ScreenRect,
+        area: Option<&DrawArea>,
+        layers: Option<&RenderLayers>,
+    ) -> Vec<ScreenRect> {
+        self.draw_areas(area, layers)
+            .map(|area| {
+                let rect = ScreenRect {
+                    x: position.x + area.offset_x + cells.x,
+                    y: position.y + area.offset_y + cells.y,
+                    ..*cells
+                };
+                match area.clip {
+                    Some(clip) => rect.intersect(&clip),
+                    None => rect,
+                }
+            })
+            .filter(|rect| rect.width > 0 && rect.height > 0)
+            .collect()
+    }
+
+    /// Finds the cell of an entity's sprite that's drawn at a cell of the screen, relative to the
+    /// sprite's top left corner
+    pub fn cell_at(
+        &self,
+        position: &Position,
+        (width, height): (usize, usize),
+        area: Option<&DrawArea>,
+        layers: Option<&RenderLayers>,
+        (column, row): (u16, u16),
+    ) -> Option<(usize, usize)> {
+        let (column, row) = (column as i32, row as i32);
+        self.draw_areas(area, layers)
+            .find(|area| {
+                area.sprite_rect(position, width, height)
+                    .contains(column, row)
+            })
+            .map(|area| {
+                (
+                    (column - position.x - area.offset_x) as usize,
+                    (row - position.y - area.offset_y) as usize,
+                )
+            })
+    }
+}
+
+pub(crate) fn update_active_viewports(
+    window: Res<CrosstermWindow>,
+    camera: Res<Camera>,
+    layers: Res<Layers>,
+    viewports: Res<Viewports>,
+    mut active: ResMut<ActiveViewports>,
+) {
+    let mut viewports = if viewports.0.is_empty() {
+        vec![Viewport::new(0, 0, window.width(), window.height()).with_camera(camera.clone())]
+    } else {
+        viewports.0.clone()
+    };
+    // Hidden layers aren't shown anywhere
+    for viewport in viewports.iter_mut() {
+        viewport.layers = RenderLayers(viewport.layers.0 & layers.visible.0);
+    }
+    if active.0 != viewports {
+        active.0 = viewports;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> ScreenRect {
+        ScreenRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // A split screen, where the right half's camera is moved 5 cells right and only shows the world
+    fn split() -> ActiveViewports {
+        ActiveViewports(vec![
+            Viewport::new(0, 0, 10, 5),
+            Viewport::new(10, 0, 10, 5)
+                .with_camera(Camera::new(5.0, 0.0))
+                .with_layers(RenderLayers::world()),
+        ])
+    }
+
+    #[test]
+    fn rects_are_drawn_in_every_viewport_that_shows_them() {
+        let viewports = split();
+        let position = Position::with_xy(6, 1);
+        let cells = rect(0, 0, 3, 2);
+        assert_eq!(
+            viewports.screen_rects(&position, &cells, None, None),
+            vec![rect(6, 1, 3, 2), rect(11, 1, 3, 2)]
+        );
+        assert_eq!(
+            viewports.screen_rects(&position, &cells, None, Some(&RenderLayers::ui())),
+            vec![rect(6, 1, 3, 2)]
+        );
+
+        let screen_space = DrawArea {
+            screen_space: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            viewports.screen_rects(&position, &cells, Some(&screen_space), None),
+            vec![rect(6, 1, 3, 2), rect(16, 1, 3, 2)]
+        );
+    }
+
+    #[test]
+    fn rects_are_clipped() {
+        let viewports = split();
+        let cells = rect(0, 0, 3, 2);
+        assert_eq!(
+            viewports.screen_rects(&Position::with_xy(8, 1), &cells, None, None),
+            vec![rect(8, 1, 2, 2), rect(13, 1, 3, 2)]
+        );
+        assert!(viewports
+            .screen_rects(&Position::with_xy(-10, 1), &cells, None, None)
+            .is_empty());
+
+        let clipped = DrawArea {
+            clip: Some(rect(0, 0, 7, 5)),
+            ..Default::default()
+        };
+        assert_eq!(
+            viewports.screen_rects(&Position::with_xy(6, 1), &cells, Some(&clipped), None),
+            vec![rect(6, 1, 1, 2), rect(11, 1, 1, 2)]
+        );
+    }
+
+    #[test]
+    fn screen_cells_map_to_sprite_cells() {
+        let viewports = split();
+        let position = Position::with_xy(6, 1);
+        assert_eq!(
+            viewports.cell_at(&position, (3, 2), None, None, (7, 2)),
+            Some((1, 1))
+        );
+        assert_eq!(
+            viewports.cell_at(&position, (3, 2), None, None, (12, 1)),
+            Some((1, 0))
+        );
+        assert_eq!(
+            viewports.cell_at(&position, (3, 2), None, None, (9, 1)),
+            None
+        );
+        assert_eq!(
+            viewports.cell_at(&position, (3, 2), None, None, (10, 1)),
+            None
+        );
+        assert_eq!(
+            viewports.cell_at(&position, (3, 2), None, Some(&RenderLayers::ui()), (12, 1)),
+            None
+        );
+
+        let clipped = DrawArea {
+            clip: Some(rect(0, 0, 7, 5)),
+            ..Default::default()
+        };
+        assert_eq!(
+            viewports.cell_at(&position, (3, 2), Some(&clipped), None, (6, 1)),
+            Some((0, 0))
+        );
+        assert_eq!(
+            viewports.cell_at(&position, (3, 2), Some(&clipped), None, (7, 1)),
+            None
+        );
+    }
+}
@@ -0,0 +1,620 @@
+use bevy::prelude::*;
+use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::components::{self, DrawArea, Position, Sprite, Style, StyleGrid, StyleMap};
+use crate::focus::{Focus, FocusedKeyEvent};
+use crate::layers::RenderLayers;
+use crate::viewport::ActiveViewports;
+
+// Partially filled cells, from one eighth up to seven eighths
+const HORIZONTAL_EIGHTHS: [char; 7] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+const VERTICAL_EIGHTHS: [char; 7] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇'];
+
+/// Finds the cell of an entity's sprite that's under a terminal cell, wherever it's drawn on the
+/// screen
+fn cell_under(
+    viewports: &ActiveViewports,
+    position: &Position,
+    area: Option<&DrawArea>,
+    layers: Option<&RenderLayers>,
+    sprite: Option<&Sprite>,
+    column: u16,
+    row: u16,
+) -> Option<(usize, usize)> {
+    let size = sprite.map(|sprite| (sprite.width(), sprite.height()))?;
+    viewports.cell_at(position, size, area, layers, (column, row))
+}
+
+fn is_activate_key(key: &KeyEvent) -> bool {
+    key.code == KeyCode::Enter || key.code == KeyCode::Char(' ')
+}
+
+/// A single line of text
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Label {
+    pub text: String,
+}
+
+impl Label {
+    pub fn new<T: std::string::ToString>(text: T) -> Label {
+        Label {
+            text: text.to_string(),
+        }
+    }
+}
+
+pub(crate) fn update_labels(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut labels: Query<(&Label, &mut Handle<Sprite>), Changed<Label>>,
+) {
+    for (label, mut sprite) in labels.iter_mut() {
+        components::set_sprite(&mut sprites, &mut sprite, &label.text);
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ButtonState {
+    Normal,
+    /// The mouse is over the button. Terminals only report where the mouse is while a button is
+    /// held, so this is where the mouse was last seen.
+    Hovered,
+    Pressed,
+    Disabled,
+}
+
+/// The style a button is drawn with in each of its states
+#[derive(Copy, Clone, PartialEq)]
+pub struct ButtonStyles {
+    pub normal: Style,
+    pub hovered: Style,
+    pub pressed: Style,
+    pub disabled: Style,
+}
+
+impl Default for ButtonStyles {
+    fn default() -> Self {
+        ButtonStyles {
+            normal: Style::default(),
+            hovered: Style::with_attrib(crossterm::style::Attribute::Bold),
+            pressed: Style::with_attrib(crossterm::style::Attribute::Reverse),
+            disabled: Style::with_attrib(crossterm::style::Attribute::Dim),
+        }
+    }
+}
+
+/// A button which sends a ButtonPressed event when it's clicked, or when enter or space is pressed
+/// while it's focused. The entity's sprite and stylemap are generated from the button.
+#[derive(Clone, PartialEq)]
+pub struct Button {
+    pub label: String,
+    pub styles: ButtonStyles,
+    pub disabled: bool,
+    state: ButtonState,
+}
+
+impl Button {
+    pub fn new<T: std::string::ToString>(label: T) -> Button {
+        Button {
+            label: label.to_string(),
+            styles: ButtonStyles::default(),
+            disabled: false,
+            state: ButtonState::Normal,
+        }
+    }
+
+    pub fn state(&self) -> ButtonState {
+        if self.disabled {
+            ButtonState::Disabled
+        } else {
+            self.state
+        }
+    }
+
+    fn style(&self) -> Style {
+        match self.state() {
+            ButtonState::Normal => self.styles.normal,
+            ButtonState::Hovered => self.styles.hovered,
+            ButtonState::Pressed => self.styles.pressed,
+            ButtonState::Disabled => self.styles.disabled,
+        }
+    }
+}
+
+/// Sent when a button is pressed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ButtonPressed {
+    pub entity: Entity,
+}
+
+pub(crate) fn button_input(
+    mut mouse_reader: Local<EventReader<MouseEvent>>,
+    mouse_events: Res<Events<MouseEvent>>,
+    mut key_reader: Local<EventReader<FocusedKeyEvent>>,
+    key_events: Res<Events<FocusedKeyEvent>>,
+    focus: Res<Focus>,
+    viewports: Res<ActiveViewports>,
+    sprites: Res<Assets<Sprite>>,
+    mut pressed_events: ResMut<Events<ButtonPressed>>,
+    mut buttons: Query<(
+        Entity,
+        &mut Button,
+        &Position,
+        &Handle<Sprite>,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
+) {
+    for event in mouse_reader.iter(&mouse_events) {
+        for (entity, mut button, position, sprite, area, layers) in buttons.iter_mut() {
+            if button.disabled || focus.is_blocked(entity) {
+                continue;
+            }
+            let sprite = sprites.get(sprite);
+            let under_mouse =
+                |column, row| cell_under(&viewports, position, area, layers, sprite, column, row);
+            let state = match *event {
+                MouseEvent::Down(MouseButton::Left, column, row, _) => {
+                    if under_mouse(column, row).is_some() {
+                        ButtonState::Pressed
+                    } else {
+                        ButtonState::Normal
+                    }
+                }
+                MouseEvent::Up(MouseButton::Left, column, row, _) => {
+                    if under_mouse(column, row).is_some() {
+                        if button.state == ButtonState::Pressed {
+                            pressed_events.send(ButtonPressed { entity });
+                        }
+                        ButtonState::Hovered
+                    } else {
+                        ButtonState::Normal
+                    }
+                }
+                MouseEvent::Down(_, column, row, _)
+                | MouseEvent::Up(_, column, row, _)
+                | MouseEvent::Drag(_, column, row, _)
+                | MouseEvent::ScrollDown(column, row, _)
+                | MouseEvent::ScrollUp(column, row, _) => {
+                    let over = under_mouse(column, row).is_some();
+                    match button.state {
+                        // A pressed button stays pressed until the mouse button is let go
+                        ButtonState::Pressed => ButtonState::Pressed,
+                        _ if over => ButtonState::Hovered,
+                        _ => ButtonState::Normal,
+                    }
+                }
+            };
+            if button.state != state {
+                button.state = state;
+            }
+        }
+    }
+
+    for event in key_reader.iter(&key_events) {
+        if !is_activate_key(&event.key) {
+            continue;
+        }
+        if let Ok((entity, button, ..)) = buttons.get_mut(event.entity) {
+            if !button.disabled {
+                pressed_events.send(ButtonPressed { entity });
+            }
+        }
+    }
+}
+
+pub(crate) fn update_buttons(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut buttons: Query<(&Button, &mut Handle<Sprite>, &mut Handle<StyleMap>), Changed<Button>>,
+) {
+    for (button, mut sprite, mut stylemap) in buttons.iter_mut() {
+        components::set_sprite(&mut sprites, &mut sprite, format!(" {} ", button.label));
+        components::set_stylemap(
+            &mut stylemaps,
+            &mut stylemap,
+            StyleMap {
+                style: button.style(),
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/// A box that can be checked and unchecked by clicking on it, or by pressing enter or space while
+/// it's focused
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Checkbox {
+    pub label: String,
+    pub checked: bool,
+    pub disabled: bool,
+}
+
+impl Checkbox {
+    pub fn new<T: std::string::ToString>(label: T, checked: bool) -> Checkbox {
+        Checkbox {
+            label: label.to_string(),
+            checked,
+            disabled: false,
+        }
+    }
+}
+
+/// Sent when a checkbox is checked or unchecked
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CheckboxToggled {
+    pub entity: Entity,
+    pub checked: bool,
+}
+
+pub(crate) fn checkbox_input(
+    mut mouse_reader: Local<EventReader<MouseEvent>>,
+    mouse_events: Res<Events<MouseEvent>>,
+    mut key_reader: Local<EventReader<FocusedKeyEvent>>,
+    key_events: Res<Events<FocusedKeyEvent>>,
+    focus: Res<Focus>,
+    viewports: Res<ActiveViewports>,
+    sprites: Res<Assets<Sprite>>,
+    mut toggled_events: ResMut<Events<CheckboxToggled>>,
+    mut checkboxes: Query<(
+        Entity,
+        &mut Checkbox,
+        &Position,
+        &Handle<Sprite>,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
+) {
+    let mut toggle = |entity, checkbox: &mut Mut<Checkbox>| {
+        checkbox.checked = !checkbox.checked;
+        toggled_events.send(CheckboxToggled {
+            entity,
+            checked: checkbox.checked,
+        });
+    };
+
+    for event in mouse_reader.iter(&mouse_events) {
+        if let MouseEvent::Down(MouseButton::Left, column, row, _) = *event {
+            for (entity, mut checkbox, position, sprite, area, layers) in checkboxes.iter_mut() {
+                let sprite = sprites.get(sprite);
+                let under_mouse = |column, row| {
+                    cell_under(&viewports, position, area, layers, sprite, column, row)
+                };
+                if !checkbox.disabled
+                    && !focus.is_blocked(entity)
+                    && under_mouse(column, row).is_some()
+                {
+                    toggle(entity, &mut checkbox);
+                }
+            }
+        }
+    }
+
+    for event in key_reader.iter(&key_events) {
+        if !is_activate_key(&event.key) {
+            continue;
+        }
+        if let Ok((entity, mut checkbox, ..)) = checkboxes.get_mut(event.entity) {
+            if !checkbox.disabled {
+                toggle(entity, &mut checkbox);
+            }
+        }
+    }
+}
+
+pub(crate) fn update_checkboxes(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut checkboxes: Query<(&Checkbox, &mut Handle<Sprite>), Changed<Checkbox>>,
+) {
+    for (checkbox, mut sprite) in checkboxes.iter_mut() {
+        let mark = if checkbox.checked { 'x' } else { ' ' };
+        components::set_sprite(
+            &mut sprites,
+            &mut sprite,
+            format!("[{}] {}", mark, checkbox.label),
+        );
+    }
+}
+
+/// A scrolling list of items, where one item can be selected. While it's focused, the arrow keys,
+/// page up and down, home and end move the selection, and enter activates the selected item.
+/// Clicking an item selects it, and clicking it again activates it.
+///
+/// Lists use the arrow keys, so they should be made focusable with `Focusable::with_arrow_keys`.
+#[derive(Clone, PartialEq)]
+pub struct List {
+    pub items: Vec<String>,
+    pub selected: Option<usize>,
+    pub width: usize,
+    /// How many items are shown at once
+    pub height: usize,
+    pub style: Style,
+    pub selected_style: Style,
+    scroll: usize,
+}
+
+impl List {
+    pub fn new(items: Vec<String>, width: usize, height: usize) -> List {
+        List {
+            selected: if items.is_empty() { None } else { Some(0) },
+            items,
+            width,
+            height,
+            style: Style::default(),
+            selected_style: Style::with_attrib(crossterm::style::Attribute::Reverse),
+            scroll: 0,
+        }
+    }
+
+    /// The index of the first item which is shown
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    fn max_scroll(&self) -> usize {
+        self.items.len().saturating_sub(self.height)
+    }
+
+    /// Scrolls as little as possible to bring the selected item into view
+    fn scroll_to_selected(&self) -> usize {
+        let scroll = self.scroll.min(self.max_scroll());
+        match self.selected {
+            Some(selected) if selected < scroll => selected,
+            Some(selected) if selected >= scroll + self.height => {
+                (selected + 1).saturating_sub(self.height)
+            }
+            _ => scroll,
+        }
+    }
+}
+
+/// Sent when the selected item of a list changes
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ListSelected {
+    pub entity: Entity,
+    pub index: usize,
+}
+
+/// Sent when the selected item of a list is activated
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ListActivated {
+    pub entity: Entity,
+    pub index: usize,
+}
+
+pub(crate) fn list_input(
+    mut mouse_reader: Local<EventReader<MouseEvent>>,
+    mouse_events: Res<Events<MouseEvent>>,
+    mut key_reader: Local<EventReader<FocusedKeyEvent>>,
+    key_events: Res<Events<FocusedKeyEvent>>,
+    focus: Res<Focus>,
+    viewports: Res<ActiveViewports>,
+    sprites: Res<Assets<Sprite>>,
+    mut selected_events: ResMut<Events<ListSelected>>,
+    mut activated_events: ResMut<Events<ListActivated>>,
+    mut lists: Query<(
+        Entity,
+        &mut List,
+        &Position,
+        &Handle<Sprite>,
+        Option<&DrawArea>,
+        Option<&RenderLayers>,
+    )>,
+) {
+    let mut select = |entity, list: &mut Mut<List>, index: usize| {
+        if list.items.is_empty() {
+            return;
+        }
+        let index = index.min(list.items.len() - 1);
+        if list.selected != Some(index) {
+            list.selected = Some(index);
+            let scroll = list.scroll_to_selected();
+            if list.scroll != scroll {
+                list.scroll = scroll;
+            }
+            selected_events.send(ListSelected { entity, index });
+        }
+    };
+
+    for event in mouse_reader.iter(&mouse_events) {
+        for (entity, mut list, position, sprite, area, layers) in lists.iter_mut() {
+            if focus.is_blocked(entity) {
+                continue;
+            }
+            let sprite = sprites.get(sprite);
+            let under_mouse =
+                |column, row| cell_under(&viewports, position, area, layers, sprite, column, row);
+            match *event {
+                MouseEvent::Down(MouseButton::Left, column, row, _) => {
+                    let clicked_row = match under_mouse(column, row) {
+                        Some((_, row)) => row,
+                        None => continue,
+                    };
+                    let index = list.scroll + clicked_row;
+                    if index >= list.items.len() {
+                        continue;
+                    }
+                    if list.selected == Some(index) {
+                        activated_events.send(ListActivated { entity, index });
+                    } else {
+                        select(entity, &mut list, index);
+                    }
+                }
+                MouseEvent::ScrollDown(column, row, _) | MouseEvent::ScrollUp(column, row, _) => {
+                    if under_mouse(column, row).is_none() {
+                        continue;
+                    }
+                    let scroll = match event {
+                        MouseEvent::ScrollDown(..) => (list.scroll + 1).min(list.max_scroll()),
+                        _ => list.scroll.saturating_sub(1),
+                    };
+                    if list.scroll != scroll {
+                        list.scroll = scroll;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for event in key_reader.iter(&key_events) {
+        let (entity, mut list, ..) = match lists.get_mut(event.entity) {
+            Ok(list) => list,
+            Err(_) => continue,
+        };
+        let selected = list.selected.unwrap_or(0);
+        let page = list.height.max(1);
+        match event.key.code {
+            KeyCode::Up => select(entity, &mut list, selected.saturating_sub(1)),
+            KeyCode::Down => select(entity, &mut list, selected + 1),
+            KeyCode::PageUp => select(entity, &mut list, selected.saturating_sub(page)),
+            KeyCode::PageDown => select(entity, &mut list, selected + page),
+            KeyCode::Home => select(entity, &mut list, 0),
+            KeyCode::End => select(entity, &mut list, usize::MAX),
+            KeyCode::Enter => {
+                if let Some(index) = list.selected {
+                    activated_events.send(ListActivated { entity, index });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn update_lists(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut lists: Query<(&mut List, &mut Handle<Sprite>, &mut Handle<StyleMap>), Changed<List>>,
+) {
+    for (mut list, mut sprite, mut stylemap) in lists.iter_mut() {
+        // The items or the selection may have been changed directly, so make sure the selected
+        // item is still in view
+        let scroll = list.scroll_to_selected();
+        if list.scroll != scroll {
+            list.scroll = scroll;
+        }
+
+        let rows: Vec<String> = (scroll..scroll + list.height)
+            .map(|index| {
+                let item = list.items.get(index).map_or("", String::as_str);
+                let mut row: String = item.graphemes(true).take(list.width).collect();
+                let len = row.graphemes(true).count();
+                row.push_str(&" ".repeat(list.width - len));
+                row
+            })
+            .collect();
+        components::set_sprite(&mut sprites, &mut sprite, rows.join("\n"));
+
+        let selected_row = list
+            .selected
+            .filter(|selected| *selected >= scroll)
+            .map(|selected| selected - scroll);
+        let styles = (0..list.height).map(|row| {
+            let style = if Some(row) == selected_row {
+                Some(list.selected_style)
+            } else {
+                None
+            };
+            vec![style; list.width]
+        });
+        components::set_stylemap(
+            &mut stylemaps,
+            &mut stylemap,
+            StyleMap::with_grid(
+                list.style,
+                StyleGrid::from_cells_or_full(styles, list.style),
+            ),
+        );
+    }
+}
+
+/// Splits a fraction of some cells into how many cells are full, and how many eighths of the next
+/// cell are filled
+fn eighths(fraction: f32, cells: usize) -> (usize, usize) {
+    let eighths = (fraction.clamp(0.0, 1.0) * cells as f32 * 8.0).round() as usize;
+    (eighths / 8, eighths % 8)
+}
+
+/// A horizontal bar which fills up from left to right, using partially filled cells to show
+/// progress more finely. The filled part is drawn in the foreground color of the entity's style.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ProgressBar {
+    /// How full the bar is, from 0.0 to 1.0
+    pub progress: f32,
+    pub width: usize,
+}
+
+impl ProgressBar {
+    pub fn new(progress: f32, width: usize) -> ProgressBar {
+        ProgressBar { progress, width }
+    }
+}
+
+pub(crate) fn update_progress_bars(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut bars: Query<(&ProgressBar, &mut Handle<Sprite>), Changed<ProgressBar>>,
+) {
+    for (bar, mut sprite) in bars.iter_mut() {
+        let (full, partial) = eighths(bar.progress, bar.width);
+        let mut cells = "█".repeat(full);
+        if partial > 0 {
+            cells.push(HORIZONTAL_EIGHTHS[partial - 1]);
+        }
+        let filled = full + (partial > 0) as usize;
+        cells.push_str(&" ".repeat(bar.width.saturating_sub(filled)));
+        components::set_sprite(&mut sprites, &mut sprite, cells);
+    }
+}
+
+/// A vertical bar which fills up from the bottom, using partially filled cells to show its value
+/// more finely. The filled part is drawn in the foreground color of the entity's style.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Gauge {
+    /// How full the gauge is, from 0.0 to 1.0
+    pub value: f32,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Default for Gauge {
+    fn default() -> Self {
+        Gauge {
+            value: 0.0,
+            width: 1,
+            height: 0,
+        }
+    }
+}
+
+impl Gauge {
+    pub fn new(value: f32, height: usize) -> Gauge {
+        Gauge {
+            value,
+            height,
+            ..Default::default()
+        }
+    }
+}
+
+pub(crate) fn update_gauges(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut gauges: Query<(&Gauge, &mut Handle<Sprite>), Changed<Gauge>>,
+) {
+    for (gauge, mut sprite) in gauges.iter_mut() {
+        let (full, partial) = eighths(gauge.value, gauge.height);
+        let rows: Vec<String> = (0..gauge.height)
+            .rev()
+            .map(|level| {
+                let cell = if level < full {
+                    '█'
+                } else if level == full && partial > 0 {
+                    VERTICAL_EIGHTHS[partial - 1]
+                } else {
+                    ' '
+                };
+                cell.to_string().repeat(gauge.width)
+            })
+            .collect();
+        components::set_sprite(&mut sprites, &mut sprite, rows.join("\n"));
+    }
+}